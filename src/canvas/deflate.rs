@@ -0,0 +1,409 @@
+// Just enough of zlib (RFC 1950) and deflate (RFC 1951) to write PNG files:
+// stored blocks, and LZ77 matches coded with the fixed Huffman tables.
+
+// Sizes of the match lengths and distances each code stands for, and how many
+// extra bits follow the code to pick one within its range
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How many earlier positions with the same three bytes are tried per match
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+const MAX_STORED_BLOCK: usize = 65535;
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    crc ^ 0xffff_ffff
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+// Compresses the data into a zlib stream, using whichever of stored and fixed
+// Huffman blocks comes out smaller
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let fixed = deflate_fixed(data);
+    let stored_size = data.len() + 5 * data.len().div_ceil(MAX_STORED_BLOCK).max(1);
+    if fixed.len() < stored_size {
+        zlib_wrap(data, &fixed)
+    } else {
+        zlib_wrap(data, &deflate_stored(data))
+    }
+}
+
+fn zlib_wrap(data: &[u8], deflated: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window and no preset dictionary; the check bits make
+    // the header a multiple of 31
+    let mut out = vec![0x78, 0x01];
+    out.extend_from_slice(deflated);
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// The data split into uncompressed blocks
+pub fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_STORED_BLOCK * 5 + 5);
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        return out;
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        out.push(last as u8);
+        let len = chunk.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(chunk);
+    }
+    out
+}
+
+// The data as a single block using the fixed Huffman codes
+pub fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::new();
+    // final block, fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &head, &prev);
+        if length >= MIN_MATCH {
+            write_match(&mut bits, length, distance);
+            for p in pos..pos + length {
+                insert(data, p, &mut head, &mut prev);
+            }
+            pos += length;
+        } else {
+            write_literal(&mut bits, data[pos] as usize);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    write_literal(&mut bits, 256);
+    bits.finish()
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH > data.len() {
+        return;
+    }
+    let h = hash(data, pos);
+    prev[pos % WINDOW_SIZE] = head[h];
+    head[h] = pos;
+}
+
+// The longest earlier copy of the bytes at pos within the window, as
+// (length, distance)
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+            break;
+        }
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[pos..pos + max_length])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+        let next = prev[candidate % WINDOW_SIZE];
+        // Entries are overwritten as the window slides, so a link pointing
+        // forwards belongs to a newer position and ends the chain
+        if next == usize::MAX || next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+    best
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: usize) {
+    let (code, length) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    bits.write_huffman(code as u32, length);
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE.iter().rposition(|base| *base <= length).unwrap();
+    write_literal(bits, 257 + code);
+    bits.write((length - LENGTH_BASE[code]) as u32, LENGTH_EXTRA[code]);
+
+    let code = DISTANCE_BASE.iter().rposition(|base| *base <= distance).unwrap();
+    bits.write_huffman(code as u32, 5);
+    bits.write((distance - DISTANCE_BASE[code]) as u32, DISTANCE_EXTRA[code]);
+}
+
+// Packs values into bytes starting from the least significant bit, as deflate
+// requires
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+    // Huffman codes are stored starting from their most significant bit
+    fn write_huffman(&mut self, code: u32, length: u32) {
+        self.write(code.reverse_bits() >> (32 - length), length);
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+// Decompresses a zlib stream made of stored and fixed Huffman blocks, checking
+// its header and checksum, so tests can read back what was written
+#[cfg(test)]
+pub fn zlib_decompress(data: &[u8]) -> Vec<u8> {
+    assert_eq!(data[0] & 0x0f, 8, "not deflate");
+    assert_eq!(((data[0] as u16) << 8 | data[1] as u16) % 31, 0, "bad header check");
+    let out = inflate(&data[2..data.len() - 4]);
+    let checksum = u32::from_be_bytes([
+        data[data.len() - 4],
+        data[data.len() - 3],
+        data[data.len() - 2],
+        data[data.len() - 1],
+    ]);
+    assert_eq!(adler32(&out), checksum, "bad checksum");
+    out
+}
+
+#[cfg(test)]
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let read = |pos: &mut usize, count: u32| -> usize {
+        let mut value = 0;
+        for i in 0..count {
+            let bit = (data[*pos / 8] >> (*pos % 8)) & 1;
+            value |= (bit as usize) << i;
+            *pos += 1;
+        }
+        value
+    };
+    // Reads a Huffman code of the given length, most significant bit first
+    let read_code = |pos: &mut usize, count: u32| -> usize {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | read(pos, 1);
+        }
+        value
+    };
+
+    let mut out: Vec<u8> = Vec::new();
+    loop {
+        let last = read(&mut pos, 1) == 1;
+        match read(&mut pos, 2) {
+            0 => {
+                pos = pos.div_ceil(8) * 8;
+                let start = pos / 8;
+                let len = data[start] as usize | (data[start + 1] as usize) << 8;
+                let nlen = data[start + 2] as usize | (data[start + 3] as usize) << 8;
+                assert_eq!(len, !nlen & 0xffff);
+                out.extend_from_slice(&data[start + 4..start + 4 + len]);
+                pos = (start + 4 + len) * 8;
+            }
+            1 => loop {
+                let mut symbol = read_code(&mut pos, 7);
+                if symbol <= 0x17 {
+                    symbol += 256;
+                } else {
+                    symbol = symbol << 1 | read(&mut pos, 1);
+                    if (0x30..=0xbf).contains(&symbol) {
+                        symbol -= 0x30;
+                    } else if (0xc0..=0xc7).contains(&symbol) {
+                        symbol = symbol - 0xc0 + 280;
+                    } else {
+                        symbol = (symbol << 1 | read(&mut pos, 1)) - 0x190 + 144;
+                    }
+                }
+                if symbol < 256 {
+                    out.push(symbol as u8);
+                } else if symbol == 256 {
+                    break;
+                } else {
+                    let code = symbol - 257;
+                    let length = LENGTH_BASE[code] + read(&mut pos, LENGTH_EXTRA[code]);
+                    let code = read_code(&mut pos, 5);
+                    let distance = DISTANCE_BASE[code] + read(&mut pos, DISTANCE_EXTRA[code]);
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            },
+            other => panic!("unsupported block type {}", other),
+        }
+        if last {
+            return out;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_data() -> Vec<u8> {
+        let mut data = b"the quick brown fox jumps over the lazy dog, ".repeat(40);
+        data.extend((0..3000u32).map(|i| (i * i % 251) as u8));
+        data.extend(vec![7; 1000]);
+        data
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // long enough for the sums to wrap
+        assert_eq!(adler32(&vec![0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn stored_blocks_round_trip() {
+        for size in [0, 10, MAX_STORED_BLOCK, MAX_STORED_BLOCK + 1, 200_000].iter() {
+            let data: Vec<u8> = (0..*size).map(|i| (i % 256) as u8).collect();
+            let stored = deflate_stored(&data);
+            assert_eq!(inflate(&stored), data);
+        }
+    }
+
+    #[test]
+    fn fixed_huffman_round_trips() {
+        let data = sample_data();
+        let fixed = deflate_fixed(&data);
+        assert!(fixed.len() < data.len() / 2);
+        assert_eq!(inflate(&fixed), data);
+        assert_eq!(inflate(&deflate_fixed(b"")), b"");
+        assert_eq!(inflate(&deflate_fixed(b"ab")), b"ab");
+    }
+
+    #[test]
+    fn long_repeats_use_the_whole_window() {
+        // a match of maximal length, and one that reaches back to the edge
+        // of the window
+        let mut data: Vec<u8> = (0..WINDOW_SIZE).map(|i| (i * 7 % 253) as u8).collect();
+        data.extend_from_within(0..1000);
+        data.extend(vec![1; 600]);
+        assert_eq!(inflate(&deflate_fixed(&data)), data);
+    }
+
+    #[test]
+    fn inflates_a_stream_from_another_encoder() {
+        // Python's zlib.compressobj(9, zlib.DEFLATED, 15, 9, zlib.Z_FIXED),
+        // a single fixed Huffman block with back references
+        let stream = [
+            0x78, 0x01, 0x4b, 0x54, 0x28, 0x4a, 0xac, 0x2c, 0x29, 0x4a, 0x4c, 0x4e, 0x2d, 0x52,
+            0x28, 0x2f, 0xca, 0x2c, 0x29, 0x49, 0xcd, 0x53, 0xc8, 0xcc, 0x53, 0x28, 0x2a, 0x2d,
+            0x2e, 0xd1, 0x51, 0x48, 0xc4, 0x2d, 0xc9, 0x05, 0x00, 0x6a, 0x7b, 0x15, 0x17,
+        ];
+        let data = b"a raytracer written in rust, a raytracer written in rust\n";
+        assert_eq!(zlib_decompress(&stream), data.to_vec());
+        // zlib.crc32 and zlib.adler32 of the same data
+        assert_eq!(crc32(data), 0x7bf7_969f);
+        assert_eq!(adler32(data), 0x6a7b_1517);
+    }
+
+    #[test]
+    fn zlib_stream_round_trips() {
+        let data = sample_data();
+        let compressed = zlib_compress(&data);
+        assert_eq!(compressed[..2], [0x78, 0x01]);
+        assert_eq!(zlib_decompress(&compressed), data);
+        // random bytes do not compress, so they are stored
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..5000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let compressed = zlib_compress(&noise);
+        assert!(compressed.len() <= noise.len() + 11);
+        assert_eq!(zlib_decompress(&compressed), noise);
+    }
+}
@@ -0,0 +1,386 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{image_size, parse_error, Canvas, ImageError};
+use crate::color::Color;
+
+// Scanlines of this many pixels can be run length encoded per channel
+const MIN_ENCODED_WIDTH: usize = 8;
+const MAX_ENCODED_WIDTH: usize = 0x7fff;
+// Runs shorter than this are cheaper to store as part of a literal
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+
+impl Canvas {
+    // Writes the canvas as a Radiance RGBE image, where every pixel shares one
+    // exponent between its channels, so colors far beyond 1.0 survive with
+    // about 1% precision. Negative channels are stored as 0.
+    pub fn write_hdr<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(
+            out,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        let mut pixels = Vec::with_capacity(self.width);
+        let mut encoded = Vec::new();
+        for y in 0..self.height {
+            pixels.clear();
+            pixels.extend((0..self.width).map(|x| to_rgbe(self.pixel_at(x, y))));
+            encoded.clear();
+            encode_scanline(&pixels, &mut encoded);
+            out.write_all(&encoded)?;
+        }
+        out.flush()
+    }
+    pub fn write_hdr_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_hdr(&mut out)
+    }
+
+    // Reads a Radiance RGBE image stored top to bottom and left to right, the
+    // orientation nearly every program writes, with flat or run length encoded
+    // scanlines
+    pub fn from_hdr<R: Read>(mut input: R) -> Result<Canvas, ImageError> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut reader = ByteReader { data: &data, pos: 0 };
+
+        if !reader.line()?.starts_with("#?") {
+            return Err(parse_error("missing #? signature".to_string()));
+        }
+        loop {
+            let line = reader.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format.trim() != "32-bit_rle_rgbe" {
+                    return Err(parse_error(format!("unsupported format {:?}", format)));
+                }
+            }
+        }
+        let resolution = reader.line()?;
+        let tokens: Vec<&str> = resolution.split_whitespace().collect();
+        let (width, height) = match tokens[..] {
+            ["-Y", height, "+X", width] => match (width.parse(), height.parse()) {
+                (Ok(width), Ok(height)) => (width, height),
+                _ => return Err(parse_error(format!("invalid size {:?}", resolution))),
+            },
+            _ => {
+                return Err(parse_error(format!(
+                    "unsupported orientation {:?}",
+                    resolution
+                )))
+            }
+        };
+
+        // Even fully run length encoded, every scanline takes some bytes, which
+        // bounds the size before anything is allocated
+        image_size(width, height, 4)?;
+        let least = height.saturating_mul(min_scanline_size(width));
+        if least > reader.data.len() - reader.pos {
+            return Err(parse_error(format!(
+                "expected at least {} bytes of pixel data, found {}",
+                least,
+                reader.data.len() - reader.pos
+            )));
+        }
+        let mut canvas = Canvas::new(width, height);
+        let mut pixels = vec![[0u8; 4]; width];
+        for y in 0..height {
+            decode_scanline(&mut reader, &mut pixels)?;
+            for (x, rgbe) in pixels.iter().enumerate() {
+                canvas.write_pixel(x, y, &from_rgbe(rgbe));
+            }
+        }
+        Ok(canvas)
+    }
+    pub fn read_hdr_file<P: AsRef<Path>>(path: P) -> Result<Canvas, ImageError> {
+        Canvas::from_hdr(File::open(path)?)
+    }
+}
+
+// The channels as mantissas of the exponent that fits the brightest of them
+fn to_rgbe(color: &Color) -> [u8; 4] {
+    let channel = |c: f64| if c > 0. { c.min(f64::MAX) } else { 0. };
+    let (r, g, b) = (channel(color.r()), channel(color.g()), channel(color.b()));
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // brightest = mantissa * 2^exponent with the mantissa in [0.5, 1)
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    let mantissa = brightest / 2f64.powi(exponent);
+    if mantissa >= 1. {
+        exponent += 1;
+    } else if mantissa < 0.5 {
+        exponent -= 1;
+    }
+    if exponent > 127 {
+        return [255, 255, 255, 255];
+    }
+    let scale = 256. / 2f64.powi(exponent);
+    let byte = |c: f64| (c * scale).min(255.) as u8;
+    [byte(r), byte(g), byte(b), (exponent + 128) as u8]
+}
+
+fn from_rgbe(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0., 0., 0.);
+    }
+    // Each mantissa stands for the middle of the range it was rounded from
+    let scale = 2f64.powi(rgbe[3] as i32 - 136);
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * scale,
+        (rgbe[1] as f64 + 0.5) * scale,
+        (rgbe[2] as f64 + 0.5) * scale,
+    )
+}
+
+// Scanlines of a suitable width are written as a marker followed by each
+// channel on its own, as a sequence of runs and literals. Others are written
+// as plain RGBE.
+fn encode_scanline(pixels: &[[u8; 4]], out: &mut Vec<u8>) {
+    let width = pixels.len();
+    if !(MIN_ENCODED_WIDTH..=MAX_ENCODED_WIDTH).contains(&width) {
+        for pixel in pixels {
+            out.extend_from_slice(pixel);
+        }
+        return;
+    }
+    out.extend_from_slice(&[2, 2, (width >> 8) as u8, width as u8]);
+    let mut channel = Vec::with_capacity(width);
+    for c in 0..4 {
+        channel.clear();
+        channel.extend(pixels.iter().map(|pixel| pixel[c]));
+        encode_channel(&channel, out);
+    }
+}
+
+fn run_length(data: &[u8], start: usize) -> usize {
+    data[start..]
+        .iter()
+        .take(MAX_RUN)
+        .take_while(|b| **b == data[start])
+        .count()
+}
+
+// A run is its length plus 128 followed by the repeated byte; a literal is its
+// length followed by its bytes
+fn encode_channel(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let run = run_length(data, i);
+        if run >= MIN_RUN {
+            out.push((128 + run) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < data.len() && i - start < MAX_LITERAL && run_length(data, i) < MIN_RUN {
+            i += 1;
+        }
+        out.push((i - start) as u8);
+        out.extend_from_slice(&data[start..i]);
+    }
+}
+
+// The fewest bytes a scanline of the given width can be stored in: a marker
+// and one two byte run per 127 bytes of each channel when it can be encoded,
+// four bytes per pixel otherwise
+fn min_scanline_size(width: usize) -> usize {
+    if (MIN_ENCODED_WIDTH..=MAX_ENCODED_WIDTH).contains(&width) {
+        4 + 4 * 2 * width.div_ceil(MAX_RUN)
+    } else {
+        width.saturating_mul(4)
+    }
+}
+
+fn decode_scanline(reader: &mut ByteReader, pixels: &mut [[u8; 4]]) -> Result<(), ImageError> {
+    let width = pixels.len();
+    let rest = &reader.data[reader.pos..];
+    let encoded = (MIN_ENCODED_WIDTH..=MAX_ENCODED_WIDTH).contains(&width)
+        && rest.len() >= 4
+        && rest[0] == 2
+        && rest[1] == 2
+        && rest[2] & 0x80 == 0;
+    if !encoded {
+        for pixel in pixels.iter_mut() {
+            for byte in pixel.iter_mut() {
+                *byte = reader.byte()?;
+            }
+        }
+        return Ok(());
+    }
+
+    reader.pos += 4;
+    let encoded_width = (rest[2] as usize) << 8 | rest[3] as usize;
+    if encoded_width != width {
+        return Err(parse_error(format!(
+            "scanline is {} pixels wide, expected {}",
+            encoded_width, width
+        )));
+    }
+    for c in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = reader.byte()? as usize;
+            let (length, run) = if count > 128 {
+                (count - 128, true)
+            } else {
+                (count, false)
+            };
+            if length == 0 || x + length > width {
+                return Err(parse_error("run overflows scanline".to_string()));
+            }
+            if run {
+                let value = reader.byte()?;
+                for pixel in pixels[x..x + length].iter_mut() {
+                    pixel[c] = value;
+                }
+            } else {
+                for pixel in pixels[x..x + length].iter_mut() {
+                    pixel[c] = reader.byte()?;
+                }
+            }
+            x += length;
+        }
+    }
+    Ok(())
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn byte(&mut self) -> Result<u8, ImageError> {
+        let byte = self.data.get(self.pos).copied();
+        self.pos += 1;
+        byte.ok_or_else(|| parse_error("unexpected end of data".to_string()))
+    }
+    // The next line of the text header, without its newline
+    fn line(&mut self) -> Result<&'a str, ImageError> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| parse_error("unexpected end of header".to_string()))?;
+        self.pos += end + 1;
+        std::str::from_utf8(&rest[..end]).map_err(|_| parse_error("header is not text".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Canvas, ImageError};
+    use super::{encode_channel, from_rgbe, to_rgbe};
+    use crate::color::Color;
+
+    fn close(a: f64, b: f64) -> bool {
+        // one part in 256 of the brightest channel, plus rounding
+        (a - b).abs() <= b.abs().max(1e-9) / 128.
+    }
+
+    #[test]
+    fn rgbe_shares_exponent_between_channels() {
+        assert_eq!(to_rgbe(&Color::new(0., 0., 0.)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(&Color::new(1., 0.5, 0.)), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(&Color::new(-3., 0.25, 0.)), [0, 128, 0, 127]);
+        let c = from_rgbe(&to_rgbe(&Color::new(1500., 3.2, 0.7)));
+        assert!(close(c.r(), 1500.));
+        // dim channels lose precision next to bright ones
+        assert!((c.g() - 3.2).abs() < 1500. / 256.);
+    }
+
+    #[test]
+    fn channels_are_run_length_encoded() {
+        let mut out = Vec::new();
+        encode_channel(&[5, 5, 5, 5, 1, 2, 3, 3, 9, 9, 9], &mut out);
+        assert_eq!(out, vec![132, 5, 4, 1, 2, 3, 3, 131, 9]);
+        let long = vec![7u8; 300];
+        out.clear();
+        encode_channel(&long, &mut out);
+        assert_eq!(out, vec![255, 7, 255, 7, 128 + 46, 7]);
+    }
+
+    #[test]
+    fn hdr_round_trips_bright_values() {
+        // wide enough to be run length encoded, with runs and literals
+        let mut c = Canvas::new(40, 3);
+        for y in 0..3 {
+            for x in 0..40 {
+                let v = if x < 20 { 50. } else { (x * y) as f64 * 0.37 };
+                c.write_pixel(x, y, &Color::new(v, v / 2., 0.01 * x as f64));
+            }
+        }
+        let mut out = Vec::new();
+        c.write_hdr(&mut out).unwrap();
+        assert!(out.starts_with(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 3 +X 40\n"));
+        assert!(out.len() < 40 + 40 * 3 * 4);
+
+        let read = Canvas::from_hdr(&out[..]).unwrap();
+        assert_eq!((read.width, read.height), (40, 3));
+        for y in 0..3 {
+            for x in 0..40 {
+                let (a, b) = (c.pixel_at(x, y), read.pixel_at(x, y));
+                let brightest = a.r().max(a.g()).max(a.b());
+                if brightest < 1e-32 {
+                    assert_eq!(b.r(), 0.);
+                    continue;
+                }
+                assert!((b.r() - a.r()).abs() < brightest / 128.);
+                assert!((b.g() - a.g()).abs() < brightest / 128.);
+                assert!((b.b() - a.b()).abs() < brightest / 128.);
+            }
+        }
+    }
+
+    #[test]
+    fn narrow_hdr_is_stored_flat() {
+        let c = Canvas::new_with_fill(3, 2, &Color::new(2., 1., 0.5));
+        let mut out = Vec::new();
+        c.write_hdr(&mut out).unwrap();
+        let header_length = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 3\n".len();
+        assert_eq!(out.len(), header_length + 3 * 2 * 4);
+        assert_eq!(out[header_length..header_length + 4], [128, 64, 32, 130]);
+        let read = Canvas::from_hdr(&out[..]).unwrap();
+        assert!(close(read.pixel_at(2, 1).r(), 2.));
+    }
+
+    #[test]
+    fn reading_invalid_hdr_reports_why() {
+        let cases: [(&[u8], &str); 7] = [
+            (b"P6\n", "missing #? signature"),
+            (b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n", "unsupported format \"32-bit_rle_xyze\""),
+            (b"#?RADIANCE\n\n+Y 1 +X 1\n", "unsupported orientation \"+Y 1 +X 1\""),
+            (
+                b"#?RADIANCE\n\n-Y 1 +X 2\n\x01\x02\x03\x04",
+                "expected at least 8 bytes of pixel data, found 4",
+            ),
+            (
+                b"#?RADIANCE\n\n-Y 1 +X 8\n\x02\x02\x00\x08\x89\x00\0\0\0\0\0\0",
+                "run overflows scanline",
+            ),
+            (
+                b"#?RADIANCE\n\n-Y 100000 +X 1000\n\x02\x02",
+                "expected at least 6800000 bytes of pixel data, found 2",
+            ),
+            (
+                b"#?RADIANCE\n\n-Y 4611686018427387904 +X 8\n",
+                "image size 8x4611686018427387904 is too large",
+            ),
+        ];
+        for (data, expected) in cases.iter() {
+            match Canvas::from_hdr(*data) {
+                Err(ImageError::Parse(message)) => assert_eq!(message, *expected),
+                _ => panic!("expected a parse error for {:?}", expected),
+            }
+        }
+    }
+}
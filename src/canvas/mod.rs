@@ -1,7 +1,124 @@
+use std::error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::Write;
 
 use crate::color;
+
+mod deflate;
+mod hdr;
+mod pfm;
+mod png;
+mod ppm;
+mod tonemap;
+
+pub use self::tonemap::{linear_to_srgb, srgb_to_linear, ExportSettings, ToneMap, Transfer};
+
+// Why an image file could not be read
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    // The data is not in the format it claims to be, or uses a feature the
+    // reader does not support
+    Parse(String),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Parse(message) => write!(f, "invalid image: {}", message),
+        }
+    }
+}
+impl error::Error for ImageError {}
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> ImageError {
+        ImageError::Io(e)
+    }
+}
+
+fn parse_error(message: String) -> ImageError {
+    ImageError::Parse(message)
+}
+
+// The number of bytes an image of the given size takes at the given number of
+// bytes per pixel, or an error if that does not fit in memory. Images without
+// pixels are rejected too, as nothing would bound how many empty rows are read.
+fn image_size(width: usize, height: usize, bytes_per_pixel: usize) -> Result<usize, ImageError> {
+    if width == 0 || height == 0 {
+        return Err(parse_error(format!("image size {}x{} has no pixels", width, height)));
+    }
+    width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(bytes_per_pixel))
+        .ok_or_else(|| parse_error(format!("image size {}x{} is too large", width, height)))
+}
+
+// Splits the text headers of the Netpbm family of formats into whitespace
+// separated tokens, skipping comments, which run from a '#' to the end of the
+// line
+struct HeaderParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderParser<'a> {
+    fn new(data: &'a [u8]) -> HeaderParser<'a> {
+        HeaderParser { data, pos: 0 }
+    }
+    fn token(&mut self) -> Result<&'a str, ImageError> {
+        while self.pos < self.data.len() {
+            match self.data[self.pos] {
+                b'#' => {
+                    while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                c if c.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+        let start = self.pos;
+        while self.pos < self.data.len()
+            && !self.data[self.pos].is_ascii_whitespace()
+            && self.data[self.pos] != b'#'
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(parse_error("unexpected end of data".to_string()));
+        }
+        std::str::from_utf8(&self.data[start..self.pos])
+            .map_err(|_| parse_error("header is not text".to_string()))
+    }
+    fn number(&mut self, what: &str) -> Result<usize, ImageError> {
+        let token = self.token()?;
+        token
+            .parse()
+            .map_err(|_| parse_error(format!("invalid {} {:?}", what, token)))
+    }
+    // The number of bytes after the current position
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+    // The given number of bytes following the single whitespace character
+    // that ends the header
+    fn binary_data(&mut self, expected: usize) -> Result<&'a [u8], ImageError> {
+        let start = self.pos + 1;
+        let available = self.data.len().saturating_sub(start);
+        if available < expected {
+            return Err(parse_error(format!(
+                "expected {} bytes of pixel data, found {}",
+                expected, available
+            )));
+        }
+        self.pos = start + expected;
+        Ok(&self.data[start..self.pos])
+    }
+}
+
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
@@ -39,17 +156,18 @@ impl Canvas {
     }
 
     pub fn to_ppm(&self) -> String {
+        self.to_ppm_with_settings(&ExportSettings::new())
+    }
+    pub fn to_ppm_with_settings(&self, settings: &ExportSettings) -> String {
         let header = format!("P3\n{} {}\n255\n", self.width, self.height);
-        let mut pixel_string = String::from(header);
+        let mut pixel_string = header;
         let mut characters_in_line = 0;
         for (i, color) in self.pixels.iter().enumerate() {
-            let r = &Canvas::scale_clamp_to_string(color.r());
-            Canvas::add_color_maybe_newline(r, &mut pixel_string, &mut characters_in_line);
-            let g = &Canvas::scale_clamp_to_string(color.g());
-            Canvas::add_color_maybe_newline(g, &mut pixel_string, &mut characters_in_line);
-            let b = &Canvas::scale_clamp_to_string(color.b());
-            Canvas::add_color_maybe_newline(b, &mut pixel_string, &mut characters_in_line);
-            if (i + 1) % self.width as usize == 0 {
+            for channel in settings.to_bytes(color).iter() {
+                let channel = &channel.to_string();
+                Canvas::add_color_maybe_newline(channel, &mut pixel_string, &mut characters_in_line);
+            }
+            if (i + 1) % self.width == 0 {
                 pixel_string.pop();
                 pixel_string.push('\n');
                 characters_in_line = 0;
@@ -69,7 +187,7 @@ impl Canvas {
         Canvas {
             width,
             height,
-            pixels: vec![color_clone; (height * width) as usize],
+            pixels: vec![color_clone; height * width],
         }
     }
 
@@ -79,14 +197,16 @@ impl Canvas {
             .write_all(ppm.as_bytes())
             .expect("Failed to write to file");
     }
-    fn scale_clamp_to_string(n: f64) -> String {
+    // The channel value as a byte, 0.0 and below giving 0 and 1.0 and above
+    // giving 255
+    fn scale_clamp(n: f64) -> u8 {
         let scaled_number = (n * 255.0).round() as i32;
         if scaled_number > 255 {
-            255.to_string()
+            255
         } else if scaled_number < 0 {
-            0.to_string()
+            0
         } else {
-            scaled_number.to_string()
+            scaled_number as u8
         }
     }
 }
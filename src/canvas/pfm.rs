@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{image_size, parse_error, Canvas, HeaderParser, ImageError};
+use crate::color::Color;
+
+impl Canvas {
+    // Writes the canvas as a color Portable Float Map: unclamped 32 bit floats
+    // in little endian order, with the bottom row first as the format requires
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        let mut row = Vec::with_capacity(self.width * 12);
+        for y in (0..self.height).rev() {
+            row.clear();
+            for x in 0..self.width {
+                let color = self.pixel_at(x, y);
+                for channel in [color.r(), color.g(), color.b()].iter() {
+                    row.extend_from_slice(&(*channel as f32).to_le_bytes());
+                }
+            }
+            out.write_all(&row)?;
+        }
+        out.flush()
+    }
+    pub fn write_pfm_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_pfm(&mut out)
+    }
+
+    // Reads a color (PF) or grayscale (Pf) Portable Float Map in either byte
+    // order. The magnitude of the scale factor is ignored.
+    pub fn from_pfm<R: Read>(mut input: R) -> Result<Canvas, ImageError> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut parser = HeaderParser::new(&data);
+
+        let channels = match parser.token()? {
+            "PF" => 3,
+            "Pf" => 1,
+            other => return Err(parse_error(format!("unknown magic number {:?}", other))),
+        };
+        let width = parser.number("width")?;
+        let height = parser.number("height")?;
+        let token = parser.token()?;
+        let scale: f64 = token
+            .parse()
+            .map_err(|_| parse_error(format!("invalid scale {:?}", token)))?;
+        if scale == 0. || !scale.is_finite() {
+            return Err(parse_error(format!("invalid scale {:?}", token)));
+        }
+        let little_endian = scale < 0.;
+
+        let samples = parser.binary_data(image_size(width, height, channels * 4)?)?;
+        let mut values = samples.chunks(4).map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if little_endian {
+                f32::from_le_bytes(bytes) as f64
+            } else {
+                f32::from_be_bytes(bytes) as f64
+            }
+        });
+        let mut canvas = Canvas::new(width, height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let color = if channels == 3 {
+                    let r = values.next().unwrap();
+                    let g = values.next().unwrap();
+                    let b = values.next().unwrap();
+                    Color::new(r, g, b)
+                } else {
+                    let v = values.next().unwrap();
+                    Color::new(v, v, v)
+                };
+                canvas.write_pixel(x, y, &color);
+            }
+        }
+        Ok(canvas)
+    }
+    pub fn read_pfm_file<P: AsRef<Path>>(path: P) -> Result<Canvas, ImageError> {
+        Canvas::from_pfm(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Canvas, ImageError};
+    use crate::color::Color;
+
+    #[test]
+    fn pfm_keeps_values_beyond_one() {
+        let mut c = Canvas::new(3, 2);
+        c.write_pixel(0, 0, &Color::new(12.5, -0.25, 0.0));
+        c.write_pixel(2, 1, &Color::new(0.1, 1000.0, 3.0));
+        let mut out = Vec::new();
+        c.write_pfm(&mut out).unwrap();
+        assert!(out.starts_with(b"PF\n3 2\n-1.0\n"));
+        assert_eq!(out.len(), 12 + 3 * 2 * 12);
+        // the bottom row comes first
+        assert_eq!(out[12 + 36..12 + 40], 12.5f32.to_le_bytes());
+
+        let read = Canvas::from_pfm(&out[..]).unwrap();
+        assert_eq!((read.width, read.height), (3, 2));
+        for y in 0..2 {
+            for x in 0..3 {
+                let (a, b) = (c.pixel_at(x, y), read.pixel_at(x, y));
+                assert_eq!(a.r() as f32, b.r() as f32);
+                assert_eq!(a.g() as f32, b.g() as f32);
+                assert_eq!(a.b() as f32, b.b() as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn read_big_endian_grayscale_pfm() {
+        let mut pfm = b"Pf\n2 1\n1.0\n".to_vec();
+        pfm.extend_from_slice(&0.5f32.to_be_bytes());
+        pfm.extend_from_slice(&4.0f32.to_be_bytes());
+        let c = Canvas::from_pfm(&pfm[..]).unwrap();
+        assert!(c.pixel_at(0, 0).is_equal(&Color::new(0.5, 0.5, 0.5)));
+        assert!(c.pixel_at(1, 0).is_equal(&Color::new(4.0, 4.0, 4.0)));
+    }
+
+    #[test]
+    fn reading_invalid_pfm_reports_why() {
+        let cases: [(&[u8], &str); 6] = [
+            (b"P6\n1 1\n-1.0\n", "unknown magic number \"P6\""),
+            (b"PF\n1 1\nbig\n", "invalid scale \"big\""),
+            (b"PF\n1 1\n-1.0\n\0\0\0\0", "expected 12 bytes of pixel data, found 4"),
+            (
+                b"PF\n100000 100000\n-1.0\n\0\0\0\0",
+                "expected 120000000000 bytes of pixel data, found 4",
+            ),
+            (
+                b"Pf\n4611686018427387904 4\n-1.0\n",
+                "image size 4611686018427387904x4 is too large",
+            ),
+            (
+                b"PF\n0 1000000000000\n-1.0\n",
+                "image size 0x1000000000000 has no pixels",
+            ),
+        ];
+        for (data, expected) in cases.iter() {
+            match Canvas::from_pfm(*data) {
+                Err(ImageError::Parse(message)) => assert_eq!(message, *expected),
+                _ => panic!("expected a parse error"),
+            }
+        }
+    }
+}
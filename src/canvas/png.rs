@@ -0,0 +1,277 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::deflate;
+use super::{Canvas, ExportSettings};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+impl Canvas {
+    // Writes the canvas as an 8 bit RGB PNG
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_png_with_settings(out, &ExportSettings::new())
+    }
+    pub fn write_png_with_settings<W: Write>(
+        &self,
+        out: &mut W,
+        settings: &ExportSettings,
+    ) -> io::Result<()> {
+        out.write_all(&SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // bit depth 8, truecolor, deflate, adaptive filtering, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        write_chunk(
+            out,
+            b"IDAT",
+            &deflate::zlib_compress(&self.filtered_scanlines(settings)),
+        )?;
+        write_chunk(out, b"IEND", &[])?;
+        out.flush()
+    }
+    pub fn write_png_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_png(&mut out)
+    }
+
+    // The rows of RGB bytes, each preceded by the filter that suits it best.
+    // As the PNG specification suggests, that is the filter whose output has
+    // the smallest sum when read as signed bytes.
+    fn filtered_scanlines(&self, settings: &ExportSettings) -> Vec<u8> {
+        let stride = self.width * 3;
+        let mut data = Vec::with_capacity((stride + 1) * self.height);
+        let mut previous = vec![0u8; stride];
+        let mut row = Vec::with_capacity(stride);
+        let mut candidate = vec![0u8; stride];
+        let mut best = vec![0u8; stride];
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                row.extend_from_slice(&settings.to_bytes(self.pixel_at(x, y)));
+            }
+            let mut best_filter = 0;
+            let mut best_cost = usize::MAX;
+            for filter in 0..5 {
+                apply_filter(filter, &row, &previous, &mut candidate);
+                let cost = candidate
+                    .iter()
+                    .map(|b| (*b as i8).unsigned_abs() as usize)
+                    .sum();
+                if cost < best_cost {
+                    best_cost = cost;
+                    best_filter = filter;
+                    best.copy_from_slice(&candidate);
+                }
+            }
+            data.push(best_filter);
+            data.extend_from_slice(&best);
+            previous.copy_from_slice(&row);
+        }
+        data
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    // The CRC covers the chunk type as well as its data
+    let mut body = Vec::with_capacity(data.len() + 4);
+    body.extend_from_slice(kind);
+    body.extend_from_slice(data);
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(&body)?;
+    out.write_all(&deflate::crc32(&body).to_be_bytes())
+}
+
+// Each byte is predicted from the byte to its left (a), above (b) and above
+// and to the left (c), one pixel of three bytes away
+fn apply_filter(filter: u8, row: &[u8], previous: &[u8], out: &mut [u8]) {
+    for i in 0..row.len() {
+        let a = if i >= 3 { row[i - 3] } else { 0 };
+        let b = previous[i];
+        let c = if i >= 3 { previous[i - 3] } else { 0 };
+        out[i] = row[i].wrapping_sub(predict(filter, a, b, c));
+    }
+}
+
+fn predict(filter: u8, a: u8, b: u8, c: u8) -> u8 {
+    match filter {
+        0 => 0,
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        _ => paeth(a, b, c),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::deflate;
+    use super::super::{Canvas, ExportSettings};
+    use super::{predict, SIGNATURE};
+    use crate::color::Color;
+
+    // Reads back a PNG written by write_png, checking every chunk's CRC, and
+    // returns its size and RGB bytes
+    fn decode(png: &[u8]) -> (usize, usize, Vec<u8>) {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut pos = 8;
+        let mut kinds = Vec::new();
+        let mut header = Vec::new();
+        let mut compressed = Vec::new();
+        while pos < png.len() {
+            let len = u32::from_be_bytes([png[pos], png[pos + 1], png[pos + 2], png[pos + 3]]);
+            let end = pos + 8 + len as usize;
+            let kind = &png[pos + 4..pos + 8];
+            let data = &png[pos + 8..end];
+            let crc = u32::from_be_bytes([png[end], png[end + 1], png[end + 2], png[end + 3]]);
+            assert_eq!(deflate::crc32(&png[pos + 4..end]), crc);
+            match kind {
+                b"IHDR" => header = data.to_vec(),
+                b"IDAT" => compressed.extend_from_slice(data),
+                _ => (),
+            }
+            kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+            pos = end + 4;
+        }
+        assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+        let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        assert_eq!(header[8..], [8, 2, 0, 0, 0]);
+
+        let filtered = deflate::zlib_decompress(&compressed);
+        let stride = width * 3;
+        assert_eq!(filtered.len(), (stride + 1) * height);
+        let mut pixels: Vec<u8> = Vec::with_capacity(stride * height);
+        for y in 0..height {
+            let filter = filtered[y * (stride + 1)];
+            assert!(filter < 5);
+            for i in 0..stride {
+                let a = if i >= 3 {
+                    pixels[y * stride + i - 3]
+                } else {
+                    0
+                };
+                let b = if y > 0 {
+                    pixels[(y - 1) * stride + i]
+                } else {
+                    0
+                };
+                let c = if i >= 3 && y > 0 {
+                    pixels[(y - 1) * stride + i - 3]
+                } else {
+                    0
+                };
+                let byte = filtered[y * (stride + 1) + 1 + i];
+                pixels.push(byte.wrapping_add(predict(filter, a, b, c)));
+            }
+        }
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn png_decodes_back_to_canvas() {
+        let mut c = Canvas::new(37, 23);
+        for y in 0..23 {
+            for x in 0..37 {
+                let color = Color::new(x as f64 / 36., y as f64 / 22., ((x * y) % 7) as f64 / 6.);
+                c.write_pixel(x, y, &color);
+            }
+        }
+        c.write_pixel(3, 4, &Color::new(1.5, -0.5, 0.5));
+        let mut png = Vec::new();
+        c.write_png(&mut png).unwrap();
+
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (37, 23));
+        for y in 0..23 {
+            for x in 0..37 {
+                let color = c.pixel_at(x, y);
+                let i = (y * 37 + x) * 3;
+                assert_eq!(pixels[i], Canvas::scale_clamp(color.r()));
+                assert_eq!(pixels[i + 1], Canvas::scale_clamp(color.g()));
+                assert_eq!(pixels[i + 2], Canvas::scale_clamp(color.b()));
+            }
+        }
+        assert_eq!(pixels[(4 * 37 + 3) * 3..(4 * 37 + 4) * 3], [255, 0, 128]);
+    }
+
+    #[test]
+    fn small_png_matches_reference_bytes() {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 0, &Color::new(1., 0., 0.));
+        c.write_pixel(1, 0, &Color::new(0., 1., 0.));
+        c.write_pixel(0, 1, &Color::new(0., 0., 1.));
+        c.write_pixel(1, 1, &Color::new(1., 1., 1.));
+        let mut png = Vec::new();
+        c.write_png(&mut png).unwrap();
+        // Checked outside this crate: every chunk CRC equals Python's
+        // zlib.crc32, and zlib.decompress turns the IDAT data into the rows
+        // 00 ff0000 00ff00 and 01 0000ff ffff00
+        let expected = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00,
+            0x00, 0xfd, 0xd4, 0x9a, 0x73, 0x00, 0x00, 0x00, 0x13, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0x01, 0x63, 0xf8, 0xcf, 0xc0, 0x00, 0xc4, 0x8c, 0x40, 0xe2, 0xff, 0x7f, 0x06, 0x00,
+            0x1e, 0xf6, 0x04, 0xfd, 0x3e, 0xad, 0x56, 0xde, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45,
+            0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ];
+        assert_eq!(png, expected.to_vec());
+    }
+
+    #[test]
+    fn flat_png_compresses_well() {
+        let c = Canvas::new_with_fill(200, 100, &Color::new(0.2, 0.4, 0.6));
+        let mut png = Vec::new();
+        c.write_png(&mut png).unwrap();
+        assert!(png.len() < 2000, "{} bytes", png.len());
+        let (_, _, pixels) = decode(&png);
+        assert!(pixels.chunks(3).all(|p| p == [51, 102, 153]));
+    }
+
+    #[test]
+    fn png_export_applies_settings() {
+        let mut c = Canvas::new(2, 1);
+        c.write_pixel(0, 0, &Color::new(0.18, 0.18, 0.18));
+        c.write_pixel(1, 0, &Color::new(4., 0.5, 0.));
+        let mut png = Vec::new();
+        c.write_png_with_settings(&mut png, &ExportSettings::display())
+            .unwrap();
+        let (_, _, pixels) = decode(&png);
+        let settings = ExportSettings::display();
+        assert_eq!(pixels[..3], settings.to_bytes(c.pixel_at(0, 0)));
+        assert_eq!(pixels[3..], settings.to_bytes(c.pixel_at(1, 0)));
+        // brighter than the linear 46, and the highlight is no longer clipped
+        assert!(pixels[0] > 100);
+        assert!(pixels[3] < 255);
+    }
+
+    #[test]
+    fn empty_canvas_makes_valid_png() {
+        let mut png = Vec::new();
+        Canvas::new(0, 0).write_png(&mut png).unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (0, 0));
+        assert!(pixels.is_empty());
+    }
+}
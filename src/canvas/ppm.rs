@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use super::{image_size, parse_error, Canvas, ExportSettings, HeaderParser, ImageError};
+use crate::color::Color;
+
+impl Canvas {
+    // Writes the canvas as a binary (P6) PPM, one row at a time, so that large
+    // renders never need the whole file in memory
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_ppm_with_settings(out, &ExportSettings::new())
+    }
+    pub fn write_ppm_with_settings<W: Write>(
+        &self,
+        out: &mut W,
+        settings: &ExportSettings,
+    ) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut row = Vec::with_capacity(self.width * 3);
+        for y in 0..self.height {
+            row.clear();
+            for x in 0..self.width {
+                row.extend_from_slice(&settings.to_bytes(self.pixel_at(x, y)));
+            }
+            out.write_all(&row)?;
+        }
+        out.flush()
+    }
+    pub fn write_ppm_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_ppm(&mut out)
+    }
+
+    // Reads an ASCII (P3) or binary (P6) PPM with any maxval. Channels are
+    // scaled so that maxval becomes 1.0.
+    pub fn from_ppm<R: Read>(mut input: R) -> Result<Canvas, ImageError> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        let mut parser = HeaderParser::new(&data);
+
+        let binary = match parser.token()? {
+            "P3" => false,
+            "P6" => true,
+            other => return Err(parse_error(format!("unknown magic number {:?}", other))),
+        };
+        let width = parser.number("width")?;
+        let height = parser.number("height")?;
+        let maxval = parser.number("maxval")?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(parse_error(format!(
+                "maxval must be between 1 and 65535, not {}",
+                maxval
+            )));
+        }
+
+        // The pixel data is checked against the size before the canvas is
+        // allocated, so a corrupt header cannot claim an enormous image
+        if binary {
+            // Samples take two bytes each when maxval needs them
+            let sample_size = if maxval < 256 { 1 } else { 2 };
+            let samples = parser.binary_data(image_size(width, height, 3 * sample_size)?)?;
+            let mut canvas = Canvas::new(width, height);
+            let mut values = samples.chunks(sample_size).map(|bytes| {
+                if sample_size == 1 {
+                    bytes[0] as usize
+                } else {
+                    (bytes[0] as usize) << 8 | bytes[1] as usize
+                }
+            });
+            for y in 0..height {
+                for x in 0..width {
+                    let mut channel = || -> Result<f64, ImageError> {
+                        let value = values.next().unwrap();
+                        check_sample(value, maxval)
+                    };
+                    let color = Color::new(channel()?, channel()?, channel()?);
+                    canvas.write_pixel(x, y, &color);
+                }
+            }
+            Ok(canvas)
+        } else {
+            // Every sample takes at least one digit
+            let samples = image_size(width, height, 3)?;
+            if samples > parser.remaining() {
+                return Err(parse_error(format!(
+                    "expected {} samples, found {} bytes",
+                    samples,
+                    parser.remaining()
+                )));
+            }
+            let mut canvas = Canvas::new(width, height);
+            for y in 0..height {
+                for x in 0..width {
+                    let mut channel = || -> Result<f64, ImageError> {
+                        let value = parser.number("sample")?;
+                        check_sample(value, maxval)
+                    };
+                    let color = Color::new(channel()?, channel()?, channel()?);
+                    canvas.write_pixel(x, y, &color);
+                }
+            }
+            Ok(canvas)
+        }
+    }
+    pub fn read_ppm_file<P: AsRef<Path>>(path: P) -> Result<Canvas, ImageError> {
+        Canvas::from_ppm(File::open(path)?)
+    }
+}
+
+fn check_sample(value: usize, maxval: usize) -> Result<f64, ImageError> {
+    if value > maxval {
+        return Err(parse_error(format!(
+            "sample value {} exceeds maxval {}",
+            value, maxval
+        )));
+    }
+    Ok(value as f64 / maxval as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Canvas, ImageError};
+    use crate::color::Color;
+
+    fn parse_message(data: &[u8]) -> String {
+        match Canvas::from_ppm(data) {
+            Err(ImageError::Parse(message)) => message,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("parsed invalid PPM"),
+        }
+    }
+
+    #[test]
+    fn write_binary_ppm() {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 0, &Color::new(1.5, 0.0, 0.0));
+        c.write_pixel(1, 0, &Color::new(0.0, 0.5, 0.0));
+        c.write_pixel(1, 1, &Color::new(-0.5, 0.0, 1.0));
+        let mut out = Vec::new();
+        c.write_ppm(&mut out).unwrap();
+        let mut expected = b"P6\n2 2\n255\n".to_vec();
+        expected.extend_from_slice(&[255, 0, 0, 0, 128, 0, 0, 0, 0, 0, 0, 255]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn binary_ppm_round_trips() {
+        let mut c = Canvas::new(4, 3);
+        for y in 0..3 {
+            for x in 0..4 {
+                let color = Color::new(x as f64 / 3., y as f64 / 2., 0.2);
+                c.write_pixel(x, y, &color);
+            }
+        }
+        let mut out = Vec::new();
+        c.write_ppm(&mut out).unwrap();
+        let read = Canvas::from_ppm(&out[..]).unwrap();
+        assert_eq!((read.width, read.height), (4, 3));
+        for y in 0..3 {
+            for x in 0..4 {
+                let (a, b) = (c.pixel_at(x, y), read.pixel_at(x, y));
+                assert_eq!(Canvas::scale_clamp(a.r()), Canvas::scale_clamp(b.r()));
+                assert_eq!(Canvas::scale_clamp(a.g()), Canvas::scale_clamp(b.g()));
+                assert_eq!(Canvas::scale_clamp(a.b()), Canvas::scale_clamp(b.b()));
+            }
+        }
+    }
+
+    #[test]
+    fn read_ascii_ppm_with_comments() {
+        let ppm = "P3\n# made by hand\n2 1 # width and height\n100\n100 0 50\n# second pixel\n0 25 100\n";
+        let c = Canvas::from_ppm(ppm.as_bytes()).unwrap();
+        assert_eq!((c.width, c.height), (2, 1));
+        assert!(c.pixel_at(0, 0).is_equal(&Color::new(1.0, 0.0, 0.5)));
+        assert!(c.pixel_at(1, 0).is_equal(&Color::new(0.0, 0.25, 1.0)));
+    }
+
+    #[test]
+    fn ascii_ppm_round_trips() {
+        let c = Canvas::new_with_fill(10, 2, &Color::new(1.0, 0.8, 0.6));
+        let read = Canvas::from_ppm(c.to_ppm().as_bytes()).unwrap();
+        assert!(read.pixel_at(9, 1).is_equal(&Color::new(1.0, 0.8, 0.6)));
+    }
+
+    #[test]
+    fn read_sixteen_bit_binary_ppm() {
+        let mut ppm = b"P6 1 1 65535\n".to_vec();
+        ppm.extend_from_slice(&[0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+        let c = Canvas::from_ppm(&ppm[..]).unwrap();
+        assert!(c.pixel_at(0, 0).is_equal(&Color::new(1.0, 0.50001, 0.0)));
+    }
+
+    #[test]
+    fn reading_invalid_ppm_reports_why() {
+        assert_eq!(parse_message(b"P5\n1 1\n255\n"), "unknown magic number \"P5\"");
+        assert_eq!(parse_message(b"P3\n1 x\n255\n"), "invalid height \"x\"");
+        assert_eq!(
+            parse_message(b"P3\n1 1\n0\n"),
+            "maxval must be between 1 and 65535, not 0"
+        );
+        assert_eq!(parse_message(b"P3\n1 1\n255\n1 2"), "unexpected end of data");
+        assert_eq!(
+            parse_message(b"P6\n100000 100000 255\n\0\0\0"),
+            "expected 30000000000 bytes of pixel data, found 3"
+        );
+        assert_eq!(
+            parse_message(b"P6\n18446744073709551615 2 255\n\0\0\0"),
+            "image size 18446744073709551615x2 is too large"
+        );
+        assert_eq!(
+            parse_message(b"P3\n100000 100000 255\n1 2 3\n"),
+            "expected 30000000000 samples, found 7 bytes"
+        );
+        assert_eq!(
+            parse_message(b"P3\n1 1\n255\n1 2 256"),
+            "sample value 256 exceeds maxval 255"
+        );
+        assert_eq!(
+            parse_message(b"P6\n2 1\n255\n\x01\x02\x03"),
+            "expected 6 bytes of pixel data, found 3"
+        );
+        assert_eq!(parse_message(b""), "unexpected end of data");
+    }
+}
@@ -0,0 +1,195 @@
+use super::Canvas;
+use crate::color::Color;
+
+// How colors beyond 1.0 are brought into range before being stored in 8 bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    // Anything beyond 1.0 is cut off
+    Clamp,
+    // Each channel c becomes c / (1 + c), compressing highlights smoothly
+    Reinhard,
+    // Krzysztof Narkowicz's fit of the ACES filmic curve, which adds contrast
+    // in the mid tones and rolls off highlights
+    Aces,
+}
+
+// How the tone mapped, linear values are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    // Stored as they are, which displays too dark
+    Linear,
+    // The piecewise sRGB curve image viewers expect
+    Srgb,
+    // A plain power curve, c^(1/gamma)
+    Gamma(f64),
+}
+
+// The steps that turn a rendered color into bytes for an 8 bit image: scale
+// by the exposure, tone map, then apply the transfer function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportSettings {
+    // In stops: each one doubles the brightness
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    pub transfer: Transfer,
+}
+
+impl ExportSettings {
+    // The plain scale and clamp 8 bit exports have always used
+    pub fn new() -> ExportSettings {
+        ExportSettings {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+            transfer: Transfer::Linear,
+        }
+    }
+    // Clamping with sRGB encoding, which is what the renderer writes unless
+    // told otherwise. Colors in range keep their brightness on screen, and
+    // anything brighter than 1.0 still clips.
+    pub fn srgb() -> ExportSettings {
+        ExportSettings {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+            transfer: Transfer::Srgb,
+        }
+    }
+    // Settings suited to viewing: filmic tone mapping and sRGB encoding. Unlike
+    // srgb() this rolls off highlights instead of clipping them, at the cost of
+    // darkening and adding contrast to colors that were already in range.
+    pub fn display() -> ExportSettings {
+        ExportSettings {
+            exposure: 0.,
+            tone_map: ToneMap::Aces,
+            transfer: Transfer::Srgb,
+        }
+    }
+
+    pub fn map_channel(&self, c: f64) -> f64 {
+        let exposed = c.max(0.) * 2f64.powf(self.exposure);
+        let mapped = match self.tone_map {
+            ToneMap::Clamp => exposed,
+            ToneMap::Reinhard => exposed / (1. + exposed),
+            ToneMap::Aces => {
+                (exposed * (2.51 * exposed + 0.03)) / (exposed * (2.43 * exposed + 0.59) + 0.14)
+            }
+        };
+        let mapped = mapped.min(1.);
+        match self.transfer {
+            Transfer::Linear => mapped,
+            Transfer::Srgb => linear_to_srgb(mapped),
+            Transfer::Gamma(gamma) => mapped.powf(1. / gamma),
+        }
+    }
+    pub fn map(&self, color: &Color) -> Color {
+        Color::new(
+            self.map_channel(color.r()),
+            self.map_channel(color.g()),
+            self.map_channel(color.b()),
+        )
+    }
+    pub fn to_bytes(&self, color: &Color) -> [u8; 3] {
+        let mapped = self.map(color);
+        [
+            Canvas::scale_clamp(mapped.r()),
+            Canvas::scale_clamp(mapped.g()),
+            Canvas::scale_clamp(mapped.b()),
+        ]
+    }
+}
+
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util;
+
+    #[test]
+    fn default_settings_scale_and_clamp() {
+        let settings = ExportSettings::new();
+        assert_eq!(
+            settings.to_bytes(&Color::new(1.5, 0.5, -0.5)),
+            [255, 128, 0]
+        );
+        assert!(util::equal(settings.map_channel(0.25), 0.25));
+    }
+
+    #[test]
+    fn srgb_settings_clamp_then_encode() {
+        let settings = ExportSettings::srgb();
+        assert_eq!(
+            settings.to_bytes(&Color::new(1.5, 0.5, -0.5)),
+            [255, 188, 0]
+        );
+        assert!(util::equal(settings.map_channel(0.2140), 0.5));
+    }
+
+    #[test]
+    fn exposure_is_measured_in_stops() {
+        let mut settings = ExportSettings::new();
+        settings.exposure = 1.;
+        assert!(util::equal(settings.map_channel(0.25), 0.5));
+        settings.exposure = -2.;
+        assert!(util::equal(settings.map_channel(0.8), 0.2));
+    }
+
+    #[test]
+    fn reinhard_compresses_highlights() {
+        let mut settings = ExportSettings::new();
+        settings.tone_map = ToneMap::Reinhard;
+        assert!(util::equal(settings.map_channel(1.), 0.5));
+        assert!(util::equal(settings.map_channel(3.), 0.75));
+        assert!(settings.map_channel(1000.) < 1.);
+        assert_eq!(settings.map_channel(-1.), 0.);
+    }
+
+    #[test]
+    fn aces_rolls_off_smoothly() {
+        let mut settings = ExportSettings::new();
+        settings.tone_map = ToneMap::Aces;
+        assert_eq!(settings.map_channel(0.), 0.);
+        assert!(util::equal(settings.map_channel(0.18), 0.2669));
+        assert_eq!(settings.map_channel(100.), 1.);
+        let mut previous = 0.;
+        for i in 1..100 {
+            let v = settings.map_channel(i as f64 * 0.1);
+            assert!(v >= previous);
+            previous = v;
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_matches_the_standard() {
+        assert_eq!(linear_to_srgb(0.), 0.);
+        assert!(util::equal(linear_to_srgb(1.), 1.));
+        assert!(util::equal(linear_to_srgb(0.002), 0.02584));
+        assert!(util::equal(linear_to_srgb(0.5), 0.73536));
+        for i in 0..=20 {
+            let c = i as f64 / 20.;
+            assert!(util::equal(srgb_to_linear(linear_to_srgb(c)), c));
+        }
+        // middle gray is stored as about 118 rather than 46
+        let mut settings = ExportSettings::new();
+        settings.transfer = Transfer::Srgb;
+        assert_eq!(
+            settings.to_bytes(&Color::new(0.18, 0.18, 0.18)),
+            [118, 118, 118]
+        );
+        settings.transfer = Transfer::Gamma(2.2);
+        assert!(util::equal(settings.map_channel(0.5), 0.72974));
+    }
+}
@@ -0,0 +1,486 @@
+// The command line interface of the renderer binary: argument parsing, scene
+// loading and writing the result
+use std::f64::consts;
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::canvas::{Canvas, ExportSettings, ToneMap, Transfer};
+use crate::color::Color;
+use crate::math::Tuple;
+use crate::raytracer::bounds::parent_space_bounds;
+use crate::raytracer::{Camera, ObjParser, PointLight, Scene, World};
+
+pub const USAGE: &str = "Usage: raytracer [OPTIONS] <SCENE>
+
+Renders SCENE, a YAML scene description (.yml or .yaml) or a Wavefront .obj
+model, to an image.
+
+Options:
+  -w, --width <PIXELS>    Width of the image [default: 640, or the scene's camera]
+  -H, --height <PIXELS>   Height of the image [default: 480, or the scene's camera]
+  -s, --samples <COUNT>   Rays traced per pixel [default: 1]
+  -d, --depth <COUNT>     Reflection and refraction bounces per ray [default: 4]
+  -t, --threads <COUNT>   Render threads [default: one per CPU]
+  -o, --output <FILE>     Where to write the image [default: render.<format>]
+  -f, --format <FORMAT>   ppm, png, pfm or hdr [default: from the output file's
+                          extension, otherwise png]
+      --tonemap <MODE>    How ppm and png images fit bright colors into range:
+                          clamp, reinhard or aces [default: clamp]. clamp
+                          keeps colors in range exact and clips the rest;
+                          aces rolls highlights off like a film camera
+      --exposure <STOPS>  Brighten (or darken, if negative) ppm and png images
+                          by this many stops [default: 0]
+      --linear            Store ppm and png images without the sRGB curve,
+                          which is applied by default
+  -h, --help              Print this help";
+
+const DEFAULT_WIDTH: usize = 640;
+const DEFAULT_HEIGHT: usize = 480;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+  Ppm,
+  Png,
+  Pfm,
+  Hdr,
+}
+
+impl Format {
+  pub fn from_name(name: &str) -> Option<Format> {
+    match name.to_ascii_lowercase().as_str() {
+      "ppm" => Some(Format::Ppm),
+      "png" => Some(Format::Png),
+      "pfm" => Some(Format::Pfm),
+      "hdr" => Some(Format::Hdr),
+      _ => None,
+    }
+  }
+  pub fn extension(&self) -> &'static str {
+    match self {
+      Format::Ppm => "ppm",
+      Format::Png => "png",
+      Format::Pfm => "pfm",
+      Format::Hdr => "hdr",
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+  pub scene: String,
+  // Settings left out keep what the scene or the camera says
+  pub width: Option<usize>,
+  pub height: Option<usize>,
+  pub samples: Option<usize>,
+  pub depth: Option<u8>,
+  pub threads: Option<usize>,
+  pub output: String,
+  pub format: Format,
+  // How 8 bit formats are encoded, ExportSettings::srgb() unless the flags
+  // change it; pfm and hdr keep the colors as rendered
+  pub export: ExportSettings,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+  Help,
+  Render(Options),
+}
+
+// Parses the arguments following the program name. Errors are messages meant
+// for the user.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+  let mut scene = None;
+  let mut width = None;
+  let mut height = None;
+  let mut samples = None;
+  let mut depth = None;
+  let mut threads = None;
+  let mut output = None;
+  let mut format = None;
+  let mut export = ExportSettings::srgb();
+
+  let mut args = args.into_iter();
+  while let Some(arg) = args.next() {
+    if !arg.starts_with('-') || arg == "-" {
+      if scene.is_some() {
+        return Err(format!("unexpected argument '{}'", arg));
+      }
+      scene = Some(arg);
+      continue;
+    }
+    // Values may follow the option or be attached with '='
+    let (name, attached) = match arg.find('=') {
+      Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+      _ => (arg.clone(), None),
+    };
+    if name == "-h" || name == "--help" {
+      return Ok(Command::Help);
+    }
+    let mut value = || {
+      attached
+        .clone()
+        .or_else(|| args.next())
+        .ok_or_else(|| format!("{} needs a value", name))
+    };
+    match name.as_str() {
+      "-w" | "--width" => width = Some(positive(&name, &value()?)?),
+      "-H" | "--height" => height = Some(positive(&name, &value()?)?),
+      "-s" | "--samples" => samples = Some(positive(&name, &value()?)?),
+      "-d" | "--depth" => {
+        let text = value()?;
+        let parsed = text
+          .parse()
+          .map_err(|_| format!("{} must be a number from 0 to 255, not '{}'", name, text))?;
+        depth = Some(parsed);
+      }
+      "-t" | "--threads" => threads = Some(positive(&name, &value()?)?),
+      "-o" | "--output" => output = Some(value()?),
+      "-f" | "--format" => {
+        let text = value()?;
+        let parsed = Format::from_name(&text)
+          .ok_or_else(|| format!("unknown format '{}', expected ppm, png, pfm or hdr", text))?;
+        format = Some(parsed);
+      }
+      "--tonemap" => {
+        let text = value()?;
+        export.tone_map = match text.to_ascii_lowercase().as_str() {
+          "clamp" => ToneMap::Clamp,
+          "reinhard" => ToneMap::Reinhard,
+          "aces" => ToneMap::Aces,
+          _ => {
+            return Err(format!(
+              "unknown tone map '{}', expected clamp, reinhard or aces",
+              text
+            ))
+          }
+        };
+      }
+      "--exposure" => {
+        let text = value()?;
+        export.exposure = match text.parse::<f64>() {
+          Ok(stops) if stops.is_finite() => stops,
+          _ => return Err(format!("{} must be a number, not '{}'", name, text)),
+        };
+      }
+      "--linear" => {
+        if attached.is_some() {
+          return Err(format!("{} does not take a value", name));
+        }
+        export.transfer = Transfer::Linear;
+      }
+      _ => return Err(format!("unknown option '{}'", name)),
+    }
+  }
+
+  let scene = scene.ok_or_else(|| "no scene file given".to_string())?;
+  let format = match (format, &output) {
+    (Some(format), _) => format,
+    (None, Some(output)) => Path::new(output)
+      .extension()
+      .and_then(|e| e.to_str())
+      .and_then(Format::from_name)
+      .unwrap_or(Format::Png),
+    (None, None) => Format::Png,
+  };
+  let output = output.unwrap_or_else(|| format!("render.{}", format.extension()));
+  Ok(Command::Render(Options {
+    scene,
+    width,
+    height,
+    samples,
+    depth,
+    threads,
+    output,
+    format,
+    export,
+  }))
+}
+
+fn positive(name: &str, text: &str) -> Result<usize, String> {
+  match text.parse() {
+    Ok(n) if n > 0 => Ok(n),
+    _ => Err(format!(
+      "{} must be a whole number above 0, not '{}'",
+      name, text
+    )),
+  }
+}
+
+pub fn load_scene(path: &str) -> Result<Scene, String> {
+  let extension = Path::new(path)
+    .extension()
+    .and_then(|e| e.to_str())
+    .map(|e| e.to_ascii_lowercase());
+  match extension.as_deref() {
+    Some("obj") => {
+      let parser =
+        ObjParser::parse_file(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+      scene_around_model(&parser).map_err(|message| format!("{}: {}", path, message))
+    }
+    Some("yml") | Some("yaml") => {
+      let contents =
+        fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+      Scene::from_yaml(&contents).map_err(|e| format!("{}:{}: {}", path, e.line, e.message))
+    }
+    _ => Err(format!(
+      "{}: unsupported scene file, expected a .obj or .yml file",
+      path
+    )),
+  }
+}
+
+// Places a model in an otherwise empty world, with a light above and to the
+// left of the camera, and a camera that takes in the whole model
+fn scene_around_model(parser: &ObjParser) -> Result<Scene, String> {
+  let group = parser.to_group();
+  let bounds = parent_space_bounds(group.as_ref());
+  if bounds.is_empty() {
+    return Err("the model has no faces".to_string());
+  }
+  let center = bounds.centroid();
+  let radius = (&bounds.max - &bounds.min).magnitude().max(1e-6) / 2.;
+
+  let mut world = World::new();
+  world.shapes.push(group);
+  world.lights.push(Arc::new(PointLight::new(
+    &(&center + &Tuple::vector(-4. * radius, 6. * radius, -6. * radius)),
+    &Color::new(1., 1., 1.),
+  )));
+
+  let mut camera = Camera::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, consts::FRAC_PI_3);
+  camera.set_transform(Camera::view_transform(
+    &(&center + &Tuple::vector(0., 0.6 * radius, -2.4 * radius)),
+    &center,
+    &Tuple::vector(0., 1., 0.),
+  ));
+  Ok(Scene {
+    world,
+    camera: Some(camera),
+  })
+}
+
+// The scene's camera with the command line settings applied
+pub fn configure_camera(camera: Option<Camera>, options: &Options) -> Camera {
+  let mut camera =
+    camera.unwrap_or_else(|| Camera::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, consts::FRAC_PI_3));
+  if options.width.is_some() || options.height.is_some() {
+    let width = options.width.unwrap_or(camera.hsize);
+    let height = options.height.unwrap_or(camera.vsize);
+    camera.set_size(width, height);
+  }
+  if let Some(samples) = options.samples {
+    camera.samples = samples;
+  }
+  if let Some(depth) = options.depth {
+    camera.depth = depth;
+  }
+  if let Some(threads) = options.threads {
+    camera.threads = threads;
+  }
+  camera
+}
+
+pub fn write_image(
+  canvas: &Canvas,
+  path: &str,
+  format: Format,
+  settings: &ExportSettings,
+) -> Result<(), String> {
+  let error = |e: std::io::Error| format!("could not write {}: {}", path, e);
+  let mut out = BufWriter::new(File::create(path).map_err(error)?);
+  match format {
+    Format::Ppm => canvas.write_ppm_with_settings(&mut out, settings),
+    Format::Png => canvas.write_png_with_settings(&mut out, settings),
+    Format::Pfm => canvas.write_pfm(&mut out),
+    Format::Hdr => canvas.write_hdr(&mut out),
+  }
+  .map_err(error)
+}
+
+// Loads, renders and saves, returning a summary of what was done
+pub fn run(options: &Options) -> Result<String, String> {
+  let scene = load_scene(&options.scene)?;
+  let camera = configure_camera(scene.camera, options);
+  let start = Instant::now();
+  let canvas = camera.render(&scene.world);
+  let elapsed = start.elapsed();
+  write_image(&canvas, &options.output, options.format, &options.export)?;
+  Ok(format!(
+    "Rendered {}x{} in {:.2}s to {}",
+    canvas.width,
+    canvas.height,
+    elapsed.as_secs_f64(),
+    options.output
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+
+  fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(|s| s.to_string()).collect()
+  }
+
+  fn options(line: &str) -> Options {
+    match parse_args(args(line)) {
+      Ok(Command::Render(options)) => options,
+      other => panic!("expected options, got {:?}", other),
+    }
+  }
+
+  fn error(line: &str) -> String {
+    parse_args(args(line)).unwrap_err()
+  }
+
+  #[test]
+  fn defaults_come_from_the_scene() {
+    let o = options("teapot.obj");
+    assert_eq!(o.scene, "teapot.obj");
+    assert_eq!(
+      (o.width, o.height, o.samples, o.depth, o.threads),
+      (None, None, None, None, None)
+    );
+    assert_eq!(o.output, "render.png");
+    assert_eq!(o.format, Format::Png);
+    // 8 bit images are sRGB encoded so they display at the right brightness
+    assert_eq!(o.export.transfer, Transfer::Srgb);
+    assert_eq!(o.export.tone_map, ToneMap::Clamp);
+    assert_eq!(o.export.exposure, 0.);
+  }
+
+  #[test]
+  fn parse_every_option() {
+    let o = options("-w 100 --height=50 --samples 4 -d 2 --threads 3 -o out.ppm scene.obj");
+    assert_eq!(o.width, Some(100));
+    assert_eq!(o.height, Some(50));
+    assert_eq!(o.samples, Some(4));
+    assert_eq!(o.depth, Some(2));
+    assert_eq!(o.threads, Some(3));
+    assert_eq!(o.output, "out.ppm");
+    // the format follows the output file's extension
+    assert_eq!(o.format, Format::Ppm);
+
+    let o = options("scene.obj --format HDR");
+    assert_eq!(o.format, Format::Hdr);
+    assert_eq!(o.output, "render.hdr");
+    let o = options("scene.obj -f pfm -o image.png");
+    assert_eq!(o.format, Format::Pfm);
+    assert_eq!(o.output, "image.png");
+
+    let o = options("scene.obj --tonemap ACES --exposure=-1.5 --linear");
+    assert_eq!(o.export.tone_map, ToneMap::Aces);
+    assert_eq!(o.export.exposure, -1.5);
+    assert_eq!(o.export.transfer, Transfer::Linear);
+  }
+
+  #[test]
+  fn help_stops_parsing() {
+    assert_eq!(parse_args(args("--help")), Ok(Command::Help));
+    assert_eq!(parse_args(args("scene.obj -h -w 0")), Ok(Command::Help));
+  }
+
+  #[test]
+  fn bad_arguments_are_explained() {
+    assert_eq!(error(""), "no scene file given");
+    assert_eq!(error("a.obj b.obj"), "unexpected argument 'b.obj'");
+    assert_eq!(error("a.obj --size 3"), "unknown option '--size'");
+    assert_eq!(error("a.obj --width"), "--width needs a value");
+    assert_eq!(
+      error("a.obj --width wide"),
+      "--width must be a whole number above 0, not 'wide'"
+    );
+    assert_eq!(
+      error("a.obj -d 300"),
+      "-d must be a number from 0 to 255, not '300'"
+    );
+    assert_eq!(
+      error("a.obj -f gif"),
+      "unknown format 'gif', expected ppm, png, pfm or hdr"
+    );
+    assert_eq!(
+      error("a.obj --tonemap filmic"),
+      "unknown tone map 'filmic', expected clamp, reinhard or aces"
+    );
+    assert_eq!(
+      error("a.obj --exposure bright"),
+      "--exposure must be a number, not 'bright'"
+    );
+    assert_eq!(
+      error("a.obj --linear=yes"),
+      "--linear does not take a value"
+    );
+  }
+
+  #[test]
+  fn loading_scenes_reports_problems() {
+    assert_eq!(
+      load_scene("scene.txt").err().unwrap(),
+      "scene.txt: unsupported scene file, expected a .obj or .yml file"
+    );
+    let message = load_scene("/no/such/dir/scene.obj").err().unwrap();
+    assert!(message.starts_with("could not read /no/such/dir/scene.obj: "));
+  }
+
+  #[test]
+  fn load_yaml_scene() {
+    let dir = env::temp_dir().join(format!("raytracer-yaml-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let scene = dir.join("scene.yml");
+    fs::write(
+      &scene,
+      "- add: camera\n  width: 32\n  height: 16\n  field-of-view: 1\n  from: [ 0, 0, -5 ]\n  to: [ 0, 0, 0 ]\n  up: [ 0, 1, 0 ]\n- add: sphere\n",
+    )
+    .unwrap();
+    let loaded = load_scene(scene.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.camera.unwrap().hsize, 32);
+    assert_eq!(loaded.world.shapes.len(), 1);
+
+    let broken = dir.join("broken.yaml");
+    fs::write(&broken, "- add: sphere\n  material:\n    glow: 1\n").unwrap();
+    let path = broken.to_str().unwrap();
+    assert_eq!(
+      load_scene(path).err().unwrap(),
+      format!("{}:3: unknown material property 'glow'", path)
+    );
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn render_obj_scene_to_file() {
+    let dir = env::temp_dir().join(format!("raytracer-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let scene = dir.join("triangle.obj");
+    fs::write(&scene, "v -1 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+    let output = dir.join("out.ppm");
+    let line = format!(
+      "{} -w 16 -H 8 -t 2 -o {}",
+      scene.display(),
+      output.display()
+    );
+
+    let summary = run(&options(&line)).unwrap();
+    assert!(summary.starts_with("Rendered 16x8 in "));
+    let canvas = Canvas::read_ppm_file(&output).unwrap();
+    assert_eq!((canvas.width, canvas.height), (16, 8));
+    // the camera is aimed at the model, so the middle of the image shows it
+    assert!(canvas.pixel_at(8, 4).r() > 0.);
+
+    // no bounces at all still shows the surfaces themselves
+    let line = format!(
+      "{} -w 16 -H 8 -d 0 -o {}",
+      scene.display(),
+      output.display()
+    );
+    run(&options(&line)).unwrap();
+    let canvas = Canvas::read_ppm_file(&output).unwrap();
+    assert!(canvas.pixel_at(8, 4).r() > 0.);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
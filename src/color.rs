@@ -61,7 +61,7 @@ impl<'a> ops::Mul for &'a Color {
     )
   }
 }
-impl<'a> ops::Mul<&Tuple> for &'a Color {
+impl ops::Mul<&Tuple> for &Color {
   type Output = Color;
   fn mul(self, other: &Tuple) -> Color {
     Color::new(self.r() * other.x, self.g() * other.y, self.b() * other.z)
@@ -0,0 +1,13 @@
+#![allow(
+  clippy::needless_return,
+  clippy::new_without_default,
+  clippy::should_implement_trait,
+  clippy::unnecessary_cast
+)]
+#![cfg_attr(test, allow(clippy::approx_constant))]
+pub mod canvas;
+pub mod cli;
+pub mod color;
+pub mod math;
+pub mod raytracer;
+pub mod util;
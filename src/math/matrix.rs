@@ -49,9 +49,9 @@ impl<'a> ops::Mul for &'a Matrix {
             data: vec![0.; self.rows * other.cols],
         };
         for row in 0..self.rows {
-            for column in 0..self.cols {
+            for column in 0..other.cols {
                 let mut dot = 0.0;
-                for i in 0..self.rows {
+                for i in 0..self.cols {
                     dot += self[row][i] * other[i][column];
                 }
                 res[row][column] = dot;
@@ -70,9 +70,9 @@ impl ops::Mul for Matrix {
             data: vec![0.; self.rows * other.cols],
         };
         for row in 0..self.rows {
-            for column in 0..self.cols {
+            for column in 0..other.cols {
                 let mut dot = 0.0;
-                for i in 0..self.rows {
+                for i in 0..self.cols {
                     dot += self[row][i] * other[i][column];
                 }
                 res[row][column] = dot;
@@ -196,8 +196,8 @@ impl Matrix {
             data.extend(transposed_row);
         }
         Matrix {
-            rows: self.rows,
-            cols: self.cols,
+            rows: self.cols,
+            cols: self.rows,
             data,
         }
     }
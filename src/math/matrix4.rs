@@ -0,0 +1,236 @@
+use std::cmp;
+use std::ops;
+
+use crate::math;
+use crate::util;
+
+// A 4x4 matrix kept on the stack. This is what every transformation in the
+// ray tracer uses; the generic `Matrix` remains for other sizes.
+#[derive(Debug, Clone, Copy)]
+pub struct Matrix4 {
+    data: [[f64; 4]; 4],
+}
+
+impl ops::Index<usize> for Matrix4 {
+    type Output = [f64; 4];
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.data[index]
+    }
+}
+impl ops::IndexMut<usize> for Matrix4 {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index]
+    }
+}
+impl cmp::PartialEq for Matrix4 {
+    fn eq(&self, other: &Self) -> bool {
+        for row in 0..4 {
+            for col in 0..4 {
+                if !util::equal(self[row][col], other[row][col]) {
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+}
+
+impl ops::Mul for Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut res = Matrix4::new([[0.; 4]; 4]);
+        for row in 0..4 {
+            for col in 0..4 {
+                res[row][col] = self[row][0] * other[0][col]
+                    + self[row][1] * other[1][col]
+                    + self[row][2] * other[2][col]
+                    + self[row][3] * other[3][col];
+            }
+        }
+        res
+    }
+}
+impl<'a> ops::Mul for &'a Matrix4 {
+    type Output = Matrix4;
+    fn mul(self, other: &'a Matrix4) -> Matrix4 {
+        *self * *other
+    }
+}
+impl<'a> ops::Mul<&'a math::Tuple> for &'a Matrix4 {
+    type Output = math::Tuple;
+    fn mul(self, tuple: &'a math::Tuple) -> math::Tuple {
+        let mut res = [0.; 4];
+        for (row, value) in res.iter_mut().enumerate() {
+            *value = self[row][0] * tuple.x
+                + self[row][1] * tuple.y
+                + self[row][2] * tuple.z
+                + self[row][3] * tuple.w
+        }
+        return math::Tuple::new(res[0], res[1], res[2], res[3]);
+    }
+}
+
+impl From<&math::Matrix> for Matrix4 {
+    fn from(matrix: &math::Matrix) -> Matrix4 {
+        assert!(matrix.rows == 4 && matrix.cols == 4, "Matrix4 needs a 4x4 matrix");
+        let mut res = Matrix4::new([[0.; 4]; 4]);
+        for row in 0..4 {
+            res[row].copy_from_slice(&matrix[row]);
+        }
+        res
+    }
+}
+impl From<math::Matrix> for Matrix4 {
+    fn from(matrix: math::Matrix) -> Matrix4 {
+        Matrix4::from(&matrix)
+    }
+}
+impl From<Matrix4> for math::Matrix {
+    fn from(matrix: Matrix4) -> math::Matrix {
+        let rows: Vec<&[f64]> = matrix.data.iter().map(|row| &row[..]).collect();
+        math::Matrix::new(&rows)
+    }
+}
+
+impl Matrix4 {
+    pub fn new(data: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { data }
+    }
+    pub fn identity() -> Matrix4 {
+        Matrix4::new([
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn translation(x: f64, y: f64, z: f64) -> Matrix4 {
+        Matrix4::new([
+            [1., 0., 0., x],
+            [0., 1., 0., y],
+            [0., 0., 1., z],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn scale(x: f64, y: f64, z: f64) -> Matrix4 {
+        Matrix4::new([
+            [x, 0., 0., 0.],
+            [0., y, 0., 0.],
+            [0., 0., z, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn rotation_x(angle: f64) -> Matrix4 {
+        Matrix4::new([
+            [1., 0., 0., 0.],
+            [0., angle.cos(), -angle.sin(), 0.],
+            [0., angle.sin(), angle.cos(), 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn rotation_y(angle: f64) -> Matrix4 {
+        Matrix4::new([
+            [angle.cos(), 0., angle.sin(), 0.],
+            [0., 1., 0., 0.],
+            [-angle.sin(), 0., angle.cos(), 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn rotation_z(angle: f64) -> Matrix4 {
+        Matrix4::new([
+            [angle.cos(), -angle.sin(), 0., 0.],
+            [angle.sin(), angle.cos(), 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+    pub fn shear(x_y: f64, x_z: f64, y_x: f64, y_z: f64, z_x: f64, z_y: f64) -> Matrix4 {
+        Matrix4::new([
+            [1., x_y, x_z, 0.],
+            [y_x, 1., y_z, 0.],
+            [z_x, z_y, 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut res = Matrix4::new([[0.; 4]; 4]);
+        for row in 0..4 {
+            for col in 0..4 {
+                res[col][row] = self[row][col];
+            }
+        }
+        res
+    }
+
+    // The 2x2 determinants of the top two and bottom two rows, from which both
+    // the determinant and the inverse are built (Laplace expansion by
+    // complementary minors)
+    fn sub_determinants(&self) -> ([f64; 6], [f64; 6]) {
+        let m = &self.data;
+        let s = [
+            m[0][0] * m[1][1] - m[1][0] * m[0][1],
+            m[0][0] * m[1][2] - m[1][0] * m[0][2],
+            m[0][0] * m[1][3] - m[1][0] * m[0][3],
+            m[0][1] * m[1][2] - m[1][1] * m[0][2],
+            m[0][1] * m[1][3] - m[1][1] * m[0][3],
+            m[0][2] * m[1][3] - m[1][2] * m[0][3],
+        ];
+        let c = [
+            m[2][0] * m[3][1] - m[3][0] * m[2][1],
+            m[2][0] * m[3][2] - m[3][0] * m[2][2],
+            m[2][0] * m[3][3] - m[3][0] * m[2][3],
+            m[2][1] * m[3][2] - m[3][1] * m[2][2],
+            m[2][1] * m[3][3] - m[3][1] * m[2][3],
+            m[2][2] * m[3][3] - m[3][2] * m[2][3],
+        ];
+        (s, c)
+    }
+    pub fn determinant(&self) -> f64 {
+        let (s, c) = self.sub_determinants();
+        s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0]
+    }
+    // Unlike the generic matrix this only rejects an exactly zero
+    // determinant, so that small but valid scalings can still be inverted
+    pub fn is_invertible(&self) -> bool {
+        let determinant = self.determinant();
+        determinant != 0. && determinant.is_finite()
+    }
+    pub fn invert(&self) -> Option<Matrix4> {
+        let (s, c) = self.sub_determinants();
+        let determinant =
+            s[0] * c[5] - s[1] * c[4] + s[2] * c[3] + s[3] * c[2] - s[4] * c[1] + s[5] * c[0];
+        if determinant == 0. || !determinant.is_finite() {
+            return None;
+        }
+        let inv = 1. / determinant;
+        let m = &self.data;
+        Some(Matrix4::new([
+            [
+                (m[1][1] * c[5] - m[1][2] * c[4] + m[1][3] * c[3]) * inv,
+                (-m[0][1] * c[5] + m[0][2] * c[4] - m[0][3] * c[3]) * inv,
+                (m[3][1] * s[5] - m[3][2] * s[4] + m[3][3] * s[3]) * inv,
+                (-m[2][1] * s[5] + m[2][2] * s[4] - m[2][3] * s[3]) * inv,
+            ],
+            [
+                (-m[1][0] * c[5] + m[1][2] * c[2] - m[1][3] * c[1]) * inv,
+                (m[0][0] * c[5] - m[0][2] * c[2] + m[0][3] * c[1]) * inv,
+                (-m[3][0] * s[5] + m[3][2] * s[2] - m[3][3] * s[1]) * inv,
+                (m[2][0] * s[5] - m[2][2] * s[2] + m[2][3] * s[1]) * inv,
+            ],
+            [
+                (m[1][0] * c[4] - m[1][1] * c[2] + m[1][3] * c[0]) * inv,
+                (-m[0][0] * c[4] + m[0][1] * c[2] - m[0][3] * c[0]) * inv,
+                (m[3][0] * s[4] - m[3][1] * s[2] + m[3][3] * s[0]) * inv,
+                (-m[2][0] * s[4] + m[2][1] * s[2] - m[2][3] * s[0]) * inv,
+            ],
+            [
+                (-m[1][0] * c[3] + m[1][1] * c[1] - m[1][2] * c[0]) * inv,
+                (m[0][0] * c[3] - m[0][1] * c[1] + m[0][2] * c[0]) * inv,
+                (-m[3][0] * s[3] + m[3][1] * s[1] - m[3][2] * s[0]) * inv,
+                (m[2][0] * s[3] - m[2][1] * s[1] + m[2][2] * s[0]) * inv,
+            ],
+        ]))
+    }
+}
@@ -1,8 +1,12 @@
 pub mod tuple;
 pub mod matrix;
+pub mod matrix4;
+pub mod transform;
 
 pub use self::tuple::Tuple;
 pub use self::matrix::Matrix;
+pub use self::matrix4::Matrix4;
+pub use self::transform::Transform;
 
 
 #[cfg(test)]
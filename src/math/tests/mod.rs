@@ -2,6 +2,7 @@ use std::f64::consts;
 
 use super::Tuple;
 use super::Matrix;
+use super::Matrix4;
 
 use crate::util;
   #[test]
@@ -748,4 +749,108 @@ pub fn chained_transforms_must_be_applied_reverse() {
   let c= Matrix::translation(10., 5., 7.);
   let t = c * b * a;
   assert_eq!(&t * &p, Tuple::point(15., 0., 7.));
-}
\ No newline at end of file
+}
+#[test]
+pub fn multiply_non_square_matrices() {
+  let a = Matrix::new(&[&[1., 2., 3.], &[4., 5., 6.]]);
+  let b = Matrix::new(&[&[7., 8.], &[9., 10.], &[11., 12.]]);
+  assert_eq!(&a * &b, Matrix::new(&[&[58., 64.], &[139., 154.]]));
+  assert_eq!(b * a, Matrix::new(&[&[39., 54., 69.], &[49., 68., 87.], &[59., 82., 105.]]));
+}
+
+#[test]
+pub fn transpose_non_square_matrix() {
+  let a = Matrix::new(&[&[1., 2., 3.], &[4., 5., 6.]]);
+  let t = a.transpose();
+  assert_eq!((t.rows, t.cols), (3, 2));
+  assert_eq!(t, Matrix::new(&[&[1., 4.], &[2., 5.], &[3., 6.]]));
+}
+
+#[test]
+pub fn matrix4_converts_to_and_from_generic_matrix() {
+  let a = Matrix::new(&[
+    &[-5., 2., 6., -8.],
+    &[1., -5., 1., 8.],
+    &[7., 7., -6., -7.],
+    &[1., -3., 7., 4.],
+  ]);
+  let m = Matrix4::from(&a);
+  assert_eq!(m[2][1], 7.);
+  assert_eq!(m[3][2], 7.);
+  assert_eq!(Matrix::from(m), a);
+}
+
+#[test]
+#[should_panic]
+pub fn matrix4_rejects_other_sizes() {
+  let _ = Matrix4::from(Matrix::new_identity_matrix(3));
+}
+
+#[test]
+pub fn matrix4_matches_generic_matrix() {
+  let matrices = [
+    Matrix::new(&[
+      &[-5., 2., 6., -8.],
+      &[1., -5., 1., 8.],
+      &[7., 7., -6., -7.],
+      &[1., -3., 7., 4.],
+    ]),
+    Matrix::new(&[
+      &[8., -5., 9., 2.],
+      &[7., 5., 6., 1.],
+      &[-6., 0., 9., 6.],
+      &[-3., 0., -9., -4.],
+    ]),
+    Matrix::new(&[
+      &[9., 3., 0., 9.],
+      &[-5., -2., -6., -3.],
+      &[-4., 9., 6., 4.],
+      &[-7., 6., 6., 2.],
+    ]),
+  ];
+  for a in matrices.iter() {
+    let m = Matrix4::from(a);
+    assert!(util::equal(m.determinant(), a.determinant()));
+    assert_eq!(Matrix::from(m.invert().unwrap()), a.invert().unwrap());
+    assert_eq!(Matrix::from(m.transpose()), a.transpose());
+    assert_eq!(Matrix::from(m * m), a * a);
+  }
+}
+
+#[test]
+pub fn matrix4_multiplied_by_inverse_is_identity() {
+  let a = Matrix4::new([
+    [3., -9., 7., 3.],
+    [3., -8., 2., -9.],
+    [-4., 4., 4., 1.],
+    [-6., 5., -1., 1.],
+  ]);
+  let b = Matrix4::new([
+    [8., 2., 2., 2.],
+    [3., -1., 7., 0.],
+    [7., 0., 5., 4.],
+    [6., -2., 0., 5.],
+  ]);
+  let c = a * b;
+  assert_eq!(c * b.invert().unwrap(), a);
+  assert_eq!(a * a.invert().unwrap(), Matrix4::identity());
+}
+
+#[test]
+pub fn matrix4_inverts_small_scalings() {
+  // the generic matrix treats a determinant this small as zero
+  let m = Matrix4::scale(0.01, 0.01, 0.01);
+  assert!(m.is_invertible());
+  assert_eq!(m.invert().unwrap(), Matrix4::scale(100., 100., 100.));
+  assert!(!Matrix4::scale(0., 1., 1.).is_invertible());
+  assert!(Matrix4::scale(0., 1., 1.).invert().is_none());
+}
+
+#[test]
+pub fn matrix4_transforms_points() {
+  let p = Tuple::point(1., 0., 1.);
+  let t = Matrix4::translation(10., 5., 7.) * Matrix4::scale(5., 5., 5.) * Matrix4::rotation_x(consts::PI / 2.);
+  assert_eq!(&t * &p, Tuple::point(15., 0., 7.));
+  let s = Matrix4::shear(1., 0., 0., 0., 0., 0.);
+  assert_eq!(&s * &Tuple::point(2., 3., 4.), Tuple::point(5., 3., 4.));
+}
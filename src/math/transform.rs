@@ -0,0 +1,42 @@
+use crate::math::Matrix4;
+
+// A transformation matrix stored with its inverse and the transpose of its
+// inverse, since those are what points, rays and normals get multiplied by.
+// Both are computed once, when the transformation is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+  matrix: Matrix4,
+  inverse: Matrix4,
+  inverse_transpose: Matrix4,
+}
+
+impl Transform {
+  pub fn new(matrix: Matrix4) -> Transform {
+    let inverse = match matrix.invert() {
+      Some(i) => i,
+      None => panic!("transformation matrix is not invertible"),
+    };
+    let inverse_transpose = inverse.transpose();
+    Transform {
+      matrix,
+      inverse,
+      inverse_transpose,
+    }
+  }
+  pub fn identity() -> Transform {
+    Transform {
+      matrix: Matrix4::identity(),
+      inverse: Matrix4::identity(),
+      inverse_transpose: Matrix4::identity(),
+    }
+  }
+  pub fn matrix(&self) -> &Matrix4 {
+    &self.matrix
+  }
+  pub fn inverse(&self) -> &Matrix4 {
+    &self.inverse
+  }
+  pub fn inverse_transpose(&self) -> &Matrix4 {
+    &self.inverse_transpose
+  }
+}
@@ -49,7 +49,7 @@ impl ops::Sub<&Tuple> for Tuple {
     )
   }
 }
-impl<'a> ops::Sub<&Tuple> for &'a Tuple {
+impl ops::Sub<&Tuple> for &Tuple {
   type Output = Tuple;
   fn sub(self, other: &Tuple) -> Tuple {
     Tuple::new(
@@ -0,0 +1,155 @@
+use super::geometry::Shape;
+use super::rays::Ray;
+use crate::math::{Matrix4, Tuple};
+
+// An axis-aligned bounding box. Shapes that extend forever along an axis, like
+// planes, use infinite bounds on that axis.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundingBox {
+  pub min: Tuple,
+  pub max: Tuple,
+}
+
+impl BoundingBox {
+  // A box containing nothing, which grows as points and boxes are added
+  pub fn empty() -> BoundingBox {
+    BoundingBox {
+      min: Tuple::point(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+      max: Tuple::point(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    }
+  }
+  pub fn new(min: &Tuple, max: &Tuple) -> BoundingBox {
+    BoundingBox {
+      min: min.clone(),
+      max: max.clone(),
+    }
+  }
+  pub fn is_empty(&self) -> bool {
+    self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+  }
+  // Whether the box extends forever along any axis
+  pub fn is_infinite(&self) -> bool {
+    [self.min.x, self.min.y, self.min.z, self.max.x, self.max.y, self.max.z]
+      .iter()
+      .any(|v| v.is_infinite())
+  }
+
+  pub fn add_point(&mut self, p: &Tuple) {
+    self.min.x = self.min.x.min(p.x);
+    self.min.y = self.min.y.min(p.y);
+    self.min.z = self.min.z.min(p.z);
+    self.max.x = self.max.x.max(p.x);
+    self.max.y = self.max.y.max(p.y);
+    self.max.z = self.max.z.max(p.z);
+  }
+  pub fn merge(&mut self, other: &BoundingBox) {
+    if other.is_empty() {
+      return;
+    }
+    self.add_point(&other.min);
+    self.add_point(&other.max);
+  }
+
+  pub fn contains_point(&self, p: &Tuple) -> bool {
+    self.min.x <= p.x
+      && p.x <= self.max.x
+      && self.min.y <= p.y
+      && p.y <= self.max.y
+      && self.min.z <= p.z
+      && p.z <= self.max.z
+  }
+  pub fn contains_box(&self, other: &BoundingBox) -> bool {
+    self.contains_point(&other.min) && self.contains_point(&other.max)
+  }
+
+  // The box around all eight corners of this box after the transformation.
+  // An infinite extent multiplied by a zero matrix entry contributes nothing,
+  // and an axis where infinities cancel out is unbounded.
+  pub fn transform(&self, m: &Matrix4) -> BoundingBox {
+    let mut result = BoundingBox::empty();
+    if self.is_empty() {
+      return result;
+    }
+    for &x in [self.min.x, self.max.x].iter() {
+      for &y in [self.min.y, self.max.y].iter() {
+        for &z in [self.min.z, self.max.z].iter() {
+          let corner = [x, y, z, 1.];
+          let mut low = [0.; 3];
+          let mut high = [0.; 3];
+          for row in 0..3 {
+            let v = (0..4)
+              .filter(|&col| m[row][col] != 0.)
+              .map(|col| m[row][col] * corner[col])
+              .sum::<f64>();
+            if v.is_nan() {
+              low[row] = f64::NEG_INFINITY;
+              high[row] = f64::INFINITY;
+            } else {
+              low[row] = v;
+              high[row] = v;
+            }
+          }
+          result.add_point(&Tuple::point(low[0], low[1], low[2]));
+          result.add_point(&Tuple::point(high[0], high[1], high[2]));
+        }
+      }
+    }
+    result
+  }
+
+  // Whether the line the ray lies on passes through the box. Hits behind the
+  // origin count, since callers rely on seeing every intersection along the
+  // line (refraction, for one, looks at the ones behind the hit).
+  pub fn intersects(&self, ray: &Ray) -> bool {
+    if self.is_empty() {
+      return false;
+    }
+    let axes = [
+      (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+      (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+      (ray.origin.z, ray.direction.z, self.min.z, self.max.z),
+    ];
+    let mut tmin = f64::NEG_INFINITY;
+    let mut tmax = f64::INFINITY;
+    for &(origin, direction, min, max) in axes.iter() {
+      if direction == 0. {
+        // parallel to the slab, so the origin must already lie between its sides
+        if origin < min || origin > max {
+          return false;
+        }
+        continue;
+      }
+      let t0 = (min - origin) / direction;
+      let t1 = (max - origin) / direction;
+      tmin = tmin.max(t0.min(t1));
+      tmax = tmax.min(t0.max(t1));
+    }
+    tmin <= tmax
+  }
+
+  pub fn surface_area(&self) -> f64 {
+    if self.is_empty() {
+      return 0.;
+    }
+    if self.is_infinite() {
+      return f64::INFINITY;
+    }
+    let dx = self.max.x - self.min.x;
+    let dy = self.max.y - self.min.y;
+    let dz = self.max.z - self.min.z;
+    2. * (dx * dy + dy * dz + dz * dx)
+  }
+  pub fn centroid(&self) -> Tuple {
+    Tuple::point(
+      (self.min.x + self.max.x) / 2.,
+      (self.min.y + self.max.y) / 2.,
+      (self.min.z + self.max.z) / 2.,
+    )
+  }
+}
+
+// The bounds of a shape in the space of its parent, or world space for shapes
+// that are not part of a group
+pub fn parent_space_bounds(shape: &dyn Shape) -> BoundingBox {
+  shape.bounds().transform(shape.get_transform())
+}
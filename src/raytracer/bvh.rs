@@ -0,0 +1,240 @@
+use super::bounds::{parent_space_bounds, BoundingBox};
+use super::geometry::Shape;
+use super::rays::{Intersection, Ray};
+use crate::math::Tuple;
+use crate::util;
+use std::cmp;
+use std::sync::Arc;
+
+// Nodes with this many shapes or fewer become leaves when splitting them does
+// not pay off according to the surface area heuristic
+const MAX_LEAF_SIZE: usize = 4;
+// The cost of testing a ray against a node's box, relative to intersecting a
+// shape
+const TRAVERSAL_COST: f64 = 0.125;
+
+// A shape together with its position in the list the hierarchy was built from,
+// so intersections can be reported in the same order as a brute force search
+#[derive(Debug, Clone)]
+struct Entry {
+  index: usize,
+  shape: Arc<dyn Shape>,
+  bounds: BoundingBox,
+  centroid: Tuple,
+}
+
+#[derive(Debug)]
+enum Node {
+  Leaf {
+    bounds: BoundingBox,
+    entries: Vec<Entry>,
+  },
+  Split {
+    bounds: BoundingBox,
+    left: Box<Node>,
+    right: Box<Node>,
+  },
+}
+
+// A bounding volume hierarchy over a list of shapes, all in the same space.
+// Shapes without finite bounds (planes, open cylinders, ...) cannot be placed
+// in the tree and are tested against every ray.
+#[derive(Debug)]
+pub struct Bvh {
+  ids: Vec<usize>,
+  // The sum of the shapes' generations when the bounds were measured
+  generation: usize,
+  bounds: BoundingBox,
+  root: Option<Node>,
+  unbounded: Vec<Entry>,
+}
+
+impl Bvh {
+  pub fn new(shapes: &[Arc<dyn Shape>]) -> Bvh {
+    let generation = generation_of(shapes);
+    let mut bounded: Vec<Entry> = Vec::new();
+    let mut unbounded: Vec<Entry> = Vec::new();
+    let mut all_bounds = BoundingBox::empty();
+    for (index, shape) in shapes.iter().enumerate() {
+      let bounds = parent_space_bounds(shape.as_ref());
+      all_bounds.merge(&bounds);
+      let entry = Entry {
+        index,
+        shape: shape.clone(),
+        centroid: bounds.centroid(),
+        bounds,
+      };
+      if entry.bounds.is_infinite() {
+        unbounded.push(entry);
+      } else if !entry.bounds.is_empty() {
+        bounded.push(entry);
+      }
+    }
+    let root = if bounded.is_empty() {
+      None
+    } else {
+      Some(build(bounded))
+    };
+    Bvh {
+      ids: shapes.iter().map(|s| s.get_id()).collect(),
+      generation,
+      bounds: all_bounds,
+      root,
+      unbounded,
+    }
+  }
+
+  // Whether the hierarchy was built from exactly these shapes, and none of
+  // their bounds have changed since. Generations only grow, so any change
+  // shows up in their sum.
+  pub fn is_built_from(&self, shapes: &[Arc<dyn Shape>]) -> bool {
+    self.generation == generation_of(shapes)
+      && self.ids.len() == shapes.len()
+      && self.ids.iter().zip(shapes).all(|(id, s)| *id == s.get_id())
+  }
+
+  // The box around every shape in the hierarchy
+  pub fn bounds(&self) -> BoundingBox {
+    self.bounds.clone()
+  }
+
+  // All intersections of the ray with the shapes, sorted on t. Shapes are
+  // visited in their original order so that the result is exactly what testing
+  // every shape would give.
+  pub fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
+    let mut candidates: Vec<&Entry> = self.unbounded.iter().collect();
+    if let Some(root) = &self.root {
+      root.collect_candidates(ray, &mut candidates);
+    }
+    candidates.sort_by_key(|entry| entry.index);
+
+    let mut xs: Vec<Intersection> = Vec::new();
+    for entry in candidates {
+      xs.append(&mut entry.shape.clone().intersect(ray));
+    }
+    xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+    xs
+  }
+
+  // Number of leaves and depth of the tree, useful for checking how well it
+  // was balanced
+  pub fn leaf_count(&self) -> usize {
+    self.root.as_ref().map_or(0, |root| root.leaf_count())
+  }
+  pub fn depth(&self) -> usize {
+    self.root.as_ref().map_or(0, |root| root.depth())
+  }
+}
+
+impl Node {
+  fn bounds(&self) -> &BoundingBox {
+    match self {
+      Node::Leaf { bounds, .. } => bounds,
+      Node::Split { bounds, .. } => bounds,
+    }
+  }
+  fn collect_candidates<'a>(&'a self, ray: &Ray, candidates: &mut Vec<&'a Entry>) {
+    if !self.bounds().intersects(ray) {
+      return;
+    }
+    match self {
+      Node::Leaf { entries, .. } => candidates.extend(entries.iter()),
+      Node::Split { left, right, .. } => {
+        left.collect_candidates(ray, candidates);
+        right.collect_candidates(ray, candidates);
+      }
+    }
+  }
+  fn leaf_count(&self) -> usize {
+    match self {
+      Node::Leaf { .. } => 1,
+      Node::Split { left, right, .. } => left.leaf_count() + right.leaf_count(),
+    }
+  }
+  fn depth(&self) -> usize {
+    match self {
+      Node::Leaf { .. } => 1,
+      Node::Split { left, right, .. } => 1 + cmp::max(left.depth(), right.depth()),
+    }
+  }
+}
+
+fn generation_of(shapes: &[Arc<dyn Shape>]) -> usize {
+  shapes.iter().map(|shape| shape.generation()).sum()
+}
+
+fn centroid_axis(centroid: &Tuple, axis: usize) -> f64 {
+  match axis {
+    0 => centroid.x,
+    1 => centroid.y,
+    _ => centroid.z,
+  }
+}
+
+// The node's box is padded slightly so that rounding in the box test never
+// rejects a ray the shapes themselves would report a hit for
+fn padded_bounds(entries: &[Entry]) -> BoundingBox {
+  let mut bounds = BoundingBox::empty();
+  for entry in entries {
+    bounds.merge(&entry.bounds);
+  }
+  let pad = Tuple::vector(util::EPSILON, util::EPSILON, util::EPSILON);
+  BoundingBox::new(&(&bounds.min - &pad), &(&bounds.max + &pad))
+}
+
+// Splits the entries where the surface area heuristic says it is cheapest,
+// trying every position along each axis after sorting on the centroids
+fn build(mut entries: Vec<Entry>) -> Node {
+  let bounds = padded_bounds(&entries);
+  let count = entries.len();
+  if count == 1 {
+    return Node::Leaf { bounds, entries };
+  }
+
+  let mut best: Option<(f64, usize, usize)> = None;
+  for axis in 0..3 {
+    entries.sort_by(|a, b| {
+      centroid_axis(&a.centroid, axis)
+        .partial_cmp(&centroid_axis(&b.centroid, axis))
+        .unwrap()
+    });
+    // right_areas[i] is the area of the box around entries[i..]
+    let mut right_areas = vec![0.; count];
+    let mut right = BoundingBox::empty();
+    for i in (1..count).rev() {
+      right.merge(&entries[i].bounds);
+      right_areas[i] = right.surface_area();
+    }
+    let mut left = BoundingBox::empty();
+    for i in 1..count {
+      left.merge(&entries[i - 1].bounds);
+      let cost = left.surface_area() * i as f64 + right_areas[i] * (count - i) as f64;
+      let better = match best {
+        Some((best_cost, _, _)) => cost < best_cost,
+        None => true,
+      };
+      if better {
+        best = Some((cost, axis, i));
+      }
+    }
+  }
+
+  let (split_cost, axis, split_at) = best.unwrap();
+  let area = bounds.surface_area();
+  let leaf_cost = area * count as f64;
+  if count <= MAX_LEAF_SIZE && TRAVERSAL_COST * area + split_cost >= leaf_cost {
+    return Node::Leaf { bounds, entries };
+  }
+
+  entries.sort_by(|a, b| {
+    centroid_axis(&a.centroid, axis)
+      .partial_cmp(&centroid_axis(&b.centroid, axis))
+      .unwrap()
+  });
+  let right_entries = entries.split_off(split_at);
+  Node::Split {
+    bounds,
+    left: Box::new(build(entries)),
+    right: Box::new(build(right_entries)),
+  }
+}
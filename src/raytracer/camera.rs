@@ -1,16 +1,101 @@
-use crate::{canvas::Canvas, Ray};
-use std::cmp::Ordering;
+use crate::canvas::Canvas;
+use crate::color::Color;
 
-use crate::math::{Matrix, Tuple};
+use crate::math::{Matrix4, Transform, Tuple};
+use crate::util;
+use crate::util::Rng;
+use std::f64::consts;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-use super::World;
+use super::{Ray, World};
+
+// The canvas is split into square tiles of this many pixels a side, which the
+// render threads take turns claiming
+const TILE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+  // How far apart (in any color channel) samples may be before more are taken
+  pub threshold: f64,
+  // How many times a pixel may be split into quarters
+  pub max_depth: usize,
+}
+
+impl AdaptiveSampling {
+  pub fn new(threshold: f64, max_depth: usize) -> AdaptiveSampling {
+    AdaptiveSampling {
+      threshold,
+      max_depth,
+    }
+  }
+}
+
+// The result of an adaptive render: the image along with how many rays were
+// traced for each pixel
+pub struct AdaptiveRender {
+  pub canvas: Canvas,
+  pub sample_counts: Vec<usize>,
+}
+
+impl AdaptiveRender {
+  pub fn samples_at(&self, x: usize, y: usize) -> usize {
+    self.sample_counts[y * self.canvas.width + x]
+  }
+  // A grayscale image of the sample counts, white where the most rays were
+  // traced and black where none were
+  pub fn debug_canvas(&self) -> Canvas {
+    let max = self.sample_counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let mut canvas = Canvas::new(self.canvas.width, self.canvas.height);
+    for (i, count) in self.sample_counts.iter().enumerate() {
+      let v = *count as f64 / max;
+      canvas.write_pixel(i % canvas.width, i / canvas.width, &Color::new(v, v, v));
+    }
+    canvas
+  }
+}
+
+// How rays leave the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+  // Rays fan out from a point, covering the field of view
+  Perspective,
+  // Parallel rays from a rectangle this wide (along the longer side)
+  Orthographic { view_width: f64 },
+  // A 360 degree panorama: x maps to longitude and y to latitude, covering
+  // every direction whatever the field of view
+  Equirectangular,
+  // The angle from the view direction grows evenly with the distance from the
+  // center, with the field of view spanning the longer side
+  Fisheye,
+}
 
 pub struct Camera {
   pub hsize: usize,
   pub vsize: usize,
   pub field_of_view: f64,
-  pub transform: Matrix,
+  transform: Transform,
+  projection: Projection,
   pub pixel_size: f64,
+  // Number of threads render() uses; 1 renders on the calling thread
+  pub threads: usize,
+  // Rays per pixel. With more than one, the pixel is divided into a grid of
+  // equally sized cells with one ray through each, and their colors averaged
+  pub samples: usize,
+  // Whether each ray goes through a random point in its cell rather than the
+  // cell's center. The randomness is seeded per pixel, so renders repeat.
+  pub jitter: bool,
+  pub seed: u64,
+  // How many reflection and refraction bounces are followed per ray
+  pub depth: u8,
+  // When set, render() samples adaptively instead of using `samples`
+  pub adaptive: Option<AdaptiveSampling>,
+  // Radius of the lens. Zero makes a pinhole camera with everything in focus;
+  // otherwise each ray starts from a point on the lens and only things at
+  // `focal_distance` from the camera are sharp. Only the perspective
+  // projection has a lens.
+  pub aperture: f64,
+  pub focal_distance: f64,
   half_width: f64,
   half_height: f64,
 }
@@ -18,66 +103,391 @@ pub struct Camera {
 impl Camera {
   pub fn new(hsize: usize, vsize: usize, field_of_view: f64) -> Camera {
     let (half_width, half_height, pixel_size) =
-      Camera::compute_pixel_size(hsize, vsize, field_of_view);
+      Camera::compute_pixel_size(hsize, vsize, (field_of_view / 2.0).tan());
     Camera {
       hsize,
       vsize,
       field_of_view,
-      transform: Matrix::new_identity_matrix(4),
+      transform: Transform::identity(),
+      projection: Projection::Perspective,
+      threads: thread::available_parallelism().map_or(1, |n| n.get()),
+      samples: 1,
+      jitter: true,
+      seed: 0,
+      depth: 4,
+      adaptive: None,
+      aperture: 0.,
+      focal_distance: 1.,
       half_width,
       half_height,
       pixel_size,
     }
   }
-  pub fn view_transform(from: &Tuple, to: &Tuple, up: &Tuple) -> Matrix {
+  pub fn get_transform(&self) -> &Matrix4 {
+    self.transform.matrix()
+  }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
+  // Changes the size of the canvas, keeping the field of view and projection
+  pub fn set_size(&mut self, hsize: usize, vsize: usize) {
+    self.hsize = hsize;
+    self.vsize = vsize;
+    self.set_projection(self.projection);
+  }
+  pub fn get_projection(&self) -> Projection {
+    self.projection
+  }
+  pub fn set_projection(&mut self, projection: Projection) {
+    let half_view = match projection {
+      Projection::Perspective => (self.field_of_view / 2.0).tan(),
+      Projection::Orthographic { view_width } => view_width / 2.0,
+      // These measure the canvas in angles from the view direction
+      Projection::Fisheye => self.field_of_view / 2.0,
+      Projection::Equirectangular => consts::PI,
+    };
+    let (half_width, half_height, pixel_size) =
+      Camera::compute_pixel_size(self.hsize, self.vsize, half_view);
+    self.projection = projection;
+    self.half_width = half_width;
+    self.half_height = half_height;
+    self.pixel_size = pixel_size;
+  }
+  pub fn view_transform(from: &Tuple, to: &Tuple, up: &Tuple) -> Matrix4 {
     let forward = (to - from).normalize();
     let normalized_up = up.normalize();
     let left = Tuple::cross(&forward, &normalized_up);
     let true_up = Tuple::cross(&left, &forward);
 
-    let orientation = Matrix::new(&[
-      &[left.x, left.y, left.z, 0.],
-      &[true_up.x, true_up.y, true_up.z, 0.],
-      &[-forward.x, -forward.y, -forward.z, 0.],
-      &[0.00000, 0.00000, 0.00000, 1.00000],
+    let orientation = Matrix4::new([
+      [left.x, left.y, left.z, 0.],
+      [true_up.x, true_up.y, true_up.z, 0.],
+      [-forward.x, -forward.y, -forward.z, 0.],
+      [0.00000, 0.00000, 0.00000, 1.00000],
     ]);
 
-    orientation * Matrix::translation(-from.x, -from.y, -from.z)
+    orientation * Matrix4::translation(-from.x, -from.y, -from.z)
   }
   pub fn ray_for_pixel(&self, px: usize, py: usize) -> Ray {
-    // Calculate offset from edge of canvas to world pixel center
-    let x_offset = (px as f64 + 0.5) * self.pixel_size;
-    let y_offset = (py as f64 + 0.5) * self.pixel_size;
+    self.ray_for_pixel_offset(px, py, 0.5, 0.5)
+  }
+  // The ray through the point (dx, dy) of the pixel, where both offsets are
+  // fractions of the pixel size from its top left corner. With an aperture,
+  // the point on the lens is picked pseudo-randomly from the seed, the pixel
+  // and the offsets, so every sample in a pixel uses a different one.
+  pub fn ray_for_pixel_offset(&self, px: usize, py: usize, dx: f64, dy: f64) -> Ray {
+    if self.aperture <= 0. || self.projection != Projection::Perspective {
+      return self.ray_through_lens(px, py, dx, dy, 0.5, 0.5);
+    }
+    let (seed, x, y) = (self.seed as f64, px as f64, py as f64);
+    let u = util::hash_to_unit(&[seed, x, y, dx, dy, 0.]);
+    let v = util::hash_to_unit(&[seed, x, y, dx, dy, 1.]);
+    self.ray_through_lens(px, py, dx, dy, u, v)
+  }
+  // The ray through the point (dx, dy) of the pixel that starts from the
+  // point (u, v) of the lens, where u and v in [0, 1) cover the whole lens and
+  // (0.5, 0.5) is its center
+  pub fn ray_through_lens(&self, px: usize, py: usize, dx: f64, dy: f64, u: f64, v: f64) -> Ray {
+    // Calculate offset from edge of canvas to the point in the world pixel
+    let x_offset = (px as f64 + dx) * self.pixel_size;
+    let y_offset = (py as f64 + dy) * self.pixel_size;
 
     // this flips the coordinates around the center of the canvas
     let world_x = self.half_width - x_offset;
     let world_y = self.half_height - y_offset;
 
+    let inverse = self.transform.inverse();
+    match self.projection {
+      Projection::Perspective => (),
+      Projection::Orthographic { .. } => {
+        let origin = inverse * &Tuple::point(world_x, world_y, 0.);
+        let direction = (inverse * &Tuple::vector(0., 0., -1.)).normalize();
+        return Ray::new(&origin, &direction);
+      }
+      Projection::Equirectangular => {
+        // The full width spans every longitude and the full height the poles,
+        // whatever the canvas proportions
+        let longitude = (0.5 - (px as f64 + dx) / self.hsize as f64) * 2. * consts::PI;
+        let latitude = (0.5 - (py as f64 + dy) / self.vsize as f64) * consts::PI;
+        let direction = Tuple::vector(
+          longitude.sin() * latitude.cos(),
+          latitude.sin(),
+          -longitude.cos() * latitude.cos(),
+        );
+        return self.ray_from_camera(&direction);
+      }
+      Projection::Fisheye => {
+        let angle = (world_x * world_x + world_y * world_y).sqrt();
+        if angle == 0. {
+          return self.ray_from_camera(&Tuple::vector(0., 0., -1.));
+        }
+        let scale = angle.sin() / angle;
+        let direction = Tuple::vector(world_x * scale, world_y * scale, -angle.cos());
+        return self.ray_from_camera(&direction);
+      }
+    }
+
     // The screen is defined to be at z = -1 relative to camera
     // Camera is at (0,0,0)
     // This transforms the canvas pixel and origin according to
     // camera position and view vector
-    let pixel = &self.transform.invert().expect("help") * &Tuple::point(world_x, world_y, -1.);
-    let origin = &self.transform.invert().expect("help") * &Tuple::point(0., 0., 0.);
-    let direction = (pixel - &origin).normalize();
+    if self.aperture <= 0. {
+      let pixel = inverse * &Tuple::point(world_x, world_y, -1.);
+      let origin = inverse * &Tuple::point(0., 0., 0.);
+      let direction = (pixel - &origin).normalize();
+      return Ray::new(&origin, &direction);
+    }
 
+    // Rays from every point on the lens meet again on the focal plane, where
+    // the pinhole ray through the pixel would cross it
+    let (lens_x, lens_y) = sample_disk(u, v);
+    let focus = inverse
+      * &Tuple::point(
+        world_x * self.focal_distance,
+        world_y * self.focal_distance,
+        -self.focal_distance,
+      );
+    let origin = inverse * &Tuple::point(lens_x * self.aperture, lens_y * self.aperture, 0.);
+    let direction = (focus - &origin).normalize();
+    Ray::new(&origin, &direction)
+  }
+  // The ray from the camera's position in the given camera space direction
+  fn ray_from_camera(&self, direction: &Tuple) -> Ray {
+    let inverse = self.transform.inverse();
+    let origin = inverse * &Tuple::point(0., 0., 0.);
+    let direction = (inverse * direction).normalize();
     Ray::new(&origin, &direction)
   }
 
   pub fn render(&self, world: &World) -> Canvas {
+    if let Some(settings) = &self.adaptive {
+      return self.render_adaptive(world, settings).canvas;
+    }
+    if self.threads <= 1 {
+      return self.render_serial(world);
+    }
+    self.render_tiles(world, self.threads)
+  }
+  pub fn render_serial(&self, world: &World) -> Canvas {
     let mut canvas = Canvas::new(self.hsize, self.vsize);
     for x in 0..self.hsize {
       for y in 0..self.vsize {
-        let ray = self.ray_for_pixel(x, y);
-        let color = world.color_at(&ray, 4);
-        canvas.write_pixel(x, y, &color);
+        canvas.write_pixel(x, y, &self.color_for_pixel(world, x, y));
       }
     }
     canvas
   }
+  // Renders with the given number of worker threads. Every pixel is computed
+  // exactly as in the serial render, so the two produce identical canvases.
+  pub fn render_tiles(&self, world: &World, workers: usize) -> Canvas {
+    let colors = self.map_pixels(world, workers, |x, y| self.color_for_pixel(world, x, y));
+    let mut canvas = Canvas::new(self.hsize, self.vsize);
+    for (i, color) in colors.iter().enumerate() {
+      canvas.write_pixel(i % self.hsize, i / self.hsize, color);
+    }
+    canvas
+  }
+
+  // Renders one ray through the center of every pixel, then traces more rays
+  // only for pixels that stand out from their neighbours. Those are split into
+  // quarters, and quarters whose colors still differ are split again, up to
+  // the maximum depth.
+  pub fn render_adaptive(&self, world: &World, settings: &AdaptiveSampling) -> AdaptiveRender {
+    let workers = self.threads;
+    let first_pass = self.map_pixels(world, workers, |x, y| {
+      world.color_at(&self.ray_for_pixel(x, y), self.depth)
+    });
+    let refined = self.map_pixels(world, workers, |x, y| {
+      let color = &first_pass[y * self.hsize + x];
+      if !self.differs_from_neighbours(&first_pass, x, y, settings.threshold) {
+        return (color.clone(), 1);
+      }
+      let (color, samples) = self.refine(world, settings, x, y, (0., 0.), 1., color, 1);
+      (color, samples + 1)
+    });
+
+    let mut canvas = Canvas::new(self.hsize, self.vsize);
+    let mut sample_counts = Vec::with_capacity(refined.len());
+    for (i, (color, samples)) in refined.into_iter().enumerate() {
+      canvas.write_pixel(i % self.hsize, i / self.hsize, &color);
+      sample_counts.push(samples);
+    }
+    AdaptiveRender {
+      canvas,
+      sample_counts,
+    }
+  }
+  fn differs_from_neighbours(&self, colors: &[Color], x: usize, y: usize, threshold: f64) -> bool {
+    let color = &colors[y * self.hsize + x];
+    for ny in y.saturating_sub(1)..(y + 2).min(self.vsize) {
+      for nx in x.saturating_sub(1)..(x + 2).min(self.hsize) {
+        if color_difference(color, &colors[ny * self.hsize + nx]) > threshold {
+          return true;
+        }
+      }
+    }
+    false
+  }
+  // Samples the centers of the four quarters of the square at `corner` (in
+  // fractions of the pixel) and recurses into them while they disagree, handing
+  // each quarter the color already traced at its center. The square's color
+  // averages its center with its four quarters. Returns that color and the
+  // number of rays traced, not counting the center.
+  #[allow(clippy::too_many_arguments)]
+  fn refine(
+    &self,
+    world: &World,
+    settings: &AdaptiveSampling,
+    px: usize,
+    py: usize,
+    corner: (f64, f64),
+    size: f64,
+    center: &Color,
+    depth: usize,
+  ) -> (Color, usize) {
+    let half = size / 2.;
+    let quarters = [
+      (corner.0, corner.1),
+      (corner.0 + half, corner.1),
+      (corner.0, corner.1 + half),
+      (corner.0 + half, corner.1 + half),
+    ];
+    let colors: Vec<Color> = quarters
+      .iter()
+      .map(|(qx, qy)| {
+        let ray = self.ray_for_pixel_offset(px, py, qx + half / 2., qy + half / 2.);
+        world.color_at(&ray, self.depth)
+      })
+      .collect();
+    let disagree = colors
+      .iter()
+      .any(|a| colors.iter().any(|b| color_difference(a, b) > settings.threshold));
+
+    let mut total = center.clone();
+    let mut samples = 4;
+    for (quarter, color) in quarters.iter().zip(colors.iter()) {
+      if disagree && depth < settings.max_depth {
+        let (color, count) = self.refine(world, settings, px, py, *quarter, half, color, depth + 1);
+        total = total + &color;
+        samples += count;
+      } else {
+        total = total + color;
+      }
+    }
+    (total * 0.2, samples)
+  }
+
+  // Computes f for every pixel, on the given number of threads, returning the
+  // results in row-major order. Threads take turns claiming tiles of the
+  // canvas, so slow regions are shared out.
+  fn map_pixels<T, F>(&self, world: &World, workers: usize, f: F) -> Vec<T>
+  where
+    T: Send,
+    F: Fn(usize, usize) -> T + Sync,
+  {
+    let tiles = self.tiles();
+    let next_tile = AtomicUsize::new(0);
+    world.build_bvh();
+
+    let rendered: Vec<Vec<(usize, T)>> = thread::scope(|scope| {
+      let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+          scope.spawn(|| {
+            let mut pixels = Vec::new();
+            loop {
+              let index = next_tile.fetch_add(1, Ordering::Relaxed);
+              let (x0, y0, x1, y1) = match tiles.get(index) {
+                Some(&tile) => tile,
+                None => break,
+              };
+              for y in y0..y1 {
+                for x in x0..x1 {
+                  pixels.push((y * self.hsize + x, f(x, y)));
+                }
+              }
+            }
+            pixels
+          })
+        })
+        .collect();
+      handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut pixels: Vec<(usize, T)> = rendered.into_iter().flatten().collect();
+    pixels.sort_by_key(|(index, _)| *index);
+    pixels.into_iter().map(|(_, value)| value).collect()
+  }
+  fn color_for_pixel(&self, world: &World, x: usize, y: usize) -> Color {
+    if self.samples <= 1 {
+      let ray = self.ray_for_pixel(x, y);
+      return world.color_at(&ray, self.depth);
+    }
+    let offsets = self.pixel_samples(x, y);
+    let mut color = Color::new(0., 0., 0.);
+    for (dx, dy) in offsets.iter() {
+      let ray = self.ray_for_pixel_offset(x, y, *dx, *dy);
+      color = color + &world.color_at(&ray, self.depth);
+    }
+    color * (1. / offsets.len() as f64)
+  }
+  // The cells a pixel is split into, one per sample, as (x0, y0, x1, y1)
+  // offsets from its top left corner. Rows hold as close to the same number
+  // of cells as the count allows, and a row with more cells is taller, so
+  // every cell covers the same share of the pixel.
+  pub fn pixel_cells(&self) -> Vec<(f64, f64, f64, f64)> {
+    let count = self.samples.max(1);
+    let rows = (count as f64).sqrt().floor() as usize;
+    let mut cells = Vec::with_capacity(count);
+    let mut above = 0;
+    for row in 0..rows {
+      // the last count % rows rows take one cell more
+      let columns = count / rows + usize::from(row >= rows - count % rows);
+      let y0 = above as f64 / count as f64;
+      let y1 = (above + columns) as f64 / count as f64;
+      for column in 0..columns {
+        let x0 = column as f64 / columns as f64;
+        let x1 = (column + 1) as f64 / columns as f64;
+        cells.push((x0, y0, x1, y1));
+      }
+      above += columns;
+    }
+    cells
+  }
+  // Where in the pixel each of its samples goes, as offsets from the top left
+  // corner: one sample in each of the pixel's cells, at a random point of the
+  // cell when jittering and at its center otherwise. A single sample always
+  // goes through the center.
+  pub fn pixel_samples(&self, px: usize, py: usize) -> Vec<(f64, f64)> {
+    let cells = self.pixel_cells();
+    let jitter = self.jitter && cells.len() > 1;
+    let mut rng = Rng::new(self.seed ^ ((py as u64) << 32 | px as u64));
+    cells
+      .iter()
+      .map(|(x0, y0, x1, y1)| {
+        let (jx, jy) = if jitter {
+          (rng.next_f64(), rng.next_f64())
+        } else {
+          (0.5, 0.5)
+        };
+        (x0 + (x1 - x0) * jx, y0 + (y1 - y0) * jy)
+      })
+      .collect()
+  }
+  // The tiles covering the canvas as (x0, y0, x1, y1), end exclusive
+  fn tiles(&self) -> Vec<(usize, usize, usize, usize)> {
+    let mut tiles = Vec::new();
+    for y0 in (0..self.vsize).step_by(TILE_SIZE) {
+      for x0 in (0..self.hsize).step_by(TILE_SIZE) {
+        let x1 = (x0 + TILE_SIZE).min(self.hsize);
+        let y1 = (y0 + TILE_SIZE).min(self.vsize);
+        tiles.push((x0, y0, x1, y1));
+      }
+    }
+    tiles
+  }
   // computes the size one pixel represents in the world:
-  fn compute_pixel_size(hsize: usize, vsize: usize, field_of_view: f64) -> (f64, f64, f64) {
-    let half_view = (field_of_view / 2.0).tan();
+  // Half the extent of the canvas along its longer side is half_view
+  fn compute_pixel_size(hsize: usize, vsize: usize, half_view: f64) -> (f64, f64, f64) {
     let aspect = hsize as f64 / vsize as f64;
     let half_width = if aspect >= 1.0 {
       half_view
@@ -92,3 +502,23 @@ impl Camera {
     (half_width, half_height, (half_width * 2.0) / hsize as f64)
   }
 }
+
+// The largest difference between the two colors in any channel
+fn color_difference(a: &Color, b: &Color) -> f64 {
+  (a.r() - b.r()).abs().max((a.g() - b.g()).abs()).max((a.b() - b.b()).abs())
+}
+
+// Maps a point in the unit square onto the unit disk, keeping evenly spread
+// points evenly spread (Shirley and Chiu's concentric mapping)
+fn sample_disk(u: f64, v: f64) -> (f64, f64) {
+  let (a, b) = (2. * u - 1., 2. * v - 1.);
+  if a == 0. && b == 0. {
+    return (0., 0.);
+  }
+  let (r, theta) = if a.abs() > b.abs() {
+    (a, consts::FRAC_PI_4 * (b / a))
+  } else {
+    (b, consts::FRAC_PI_2 - consts::FRAC_PI_4 * (a / b))
+  };
+  (r * theta.cos(), r * theta.sin())
+}
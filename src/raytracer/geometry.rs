@@ -1,11 +1,13 @@
+use super::bounds::{parent_space_bounds, BoundingBox};
+use super::bvh::Bvh;
 use super::materials::Material;
 use super::rays::Intersection;
 use super::rays::Ray;
 use crate::math;
 use crate::math::Tuple;
 use crate::util;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::util::LockCell;
+use std::sync::{Arc, Weak};
 use std::{
   cmp, fmt,
   sync::atomic::{AtomicUsize, Ordering},
@@ -13,67 +15,159 @@ use std::{
 
 static GLOBAL_GEOMETRY_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
 
-pub fn normal_at(shape: Rc<dyn Shape>, point: &math::Tuple) -> math::Tuple {
-  let transform = shape.get_transform();
-  let inverted_transform = match transform.invert() {
-    Some(i) => i,
-    None => panic!(),
-  };
-  let point_in_object_space = &inverted_transform * point;
+pub fn normal_at(shape: Arc<dyn Shape>, point: &math::Tuple) -> math::Tuple {
+  let point_in_object_space = world_to_object(shape.as_ref(), point);
   let local_normal = shape.local_normal_at(&point_in_object_space);
 
   // convert the normal back to world coordinates
-  let mut world_normal = &inverted_transform.transpose() * &local_normal;
-  world_normal.w = 0.;
+  normal_to_world(shape.as_ref(), &local_normal)
+}
+
+// The normal at a point of an intersection, which also takes into account where
+// on the surface the hit was for shapes that interpolate their normals.
+pub fn normal_at_hit(hit: &Intersection, point: &math::Tuple) -> math::Tuple {
+  let shape = hit.shape.as_ref();
+  let point_in_object_space = world_to_object(shape, point);
+  let local_normal = match hit.uv {
+    Some((u, v)) => shape.local_normal_at_uv(&point_in_object_space, u, v),
+    None => shape.local_normal_at(&point_in_object_space),
+  };
+  normal_to_world(shape, &local_normal)
+}
+
+// Convert a point from world space to the object space of the shape, by first
+// converting it to the space of every group the shape is nested in.
+pub fn world_to_object(shape: &dyn Shape, point: &math::Tuple) -> math::Tuple {
+  let point = match shape.get_parent() {
+    Some(parent) => world_to_object(parent.as_ref(), point),
+    None => point.clone(),
+  };
+  shape.get_inverse() * &point
+}
 
-  world_normal.normalize()
+// Convert a normal from the object space of the shape to world space, passing
+// it up through every group the shape is nested in.
+pub fn normal_to_world(shape: &dyn Shape, normal: &math::Tuple) -> math::Tuple {
+  let mut normal = shape.get_inverse_transpose() * normal;
+  normal.w = 0.;
+  let normal = normal.normalize();
+
+  match shape.get_parent() {
+    Some(parent) => normal_to_world(parent.as_ref(), &normal),
+    None => normal,
+  }
+}
+
+// The state every shape has, whatever its geometry. Shapes embed one and the
+// accessors on Shape read and write it.
+#[derive(Debug, Clone)]
+pub struct ShapeCommon {
+  id: usize,
+  transform: math::Transform,
+  material: Arc<Material>,
+  parent: LockCell<Option<Weak<dyn Shape>>>,
+  // Bumped whenever the bounds of the shape, or of anything it is built from,
+  // may have changed
+  generation: LockCell<usize>,
+}
+impl ShapeCommon {
+  pub fn new() -> ShapeCommon {
+    ShapeCommon {
+      id: GLOBAL_GEOMETRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+      transform: math::Transform::identity(),
+      material: Arc::new(Material::new()),
+      parent: LockCell::new(None),
+      generation: LockCell::new(0),
+    }
+  }
+  // Record a change to the shape's bounds. Groups and CSGs the shape is part
+  // of change with it, so the change is passed up to them as well.
+  fn geometry_changed(&self) {
+    *self.generation.write() += 1;
+    let parent = self.parent.read().clone();
+    if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+      parent.common().geometry_changed();
+    }
+  }
 }
 
-pub trait Shape: fmt::Debug {
-  fn get_id(&self) -> usize;
-  fn get_transform(&self) -> Rc<math::Matrix>;
-  fn set_transform(&mut self, transform: math::Matrix);
-  fn get_material(&self) -> Rc<Material>;
-  fn set_material(&mut self, material: Material);
+pub trait Shape: fmt::Debug + Send + Sync {
+  fn common(&self) -> &ShapeCommon;
+  fn common_mut(&mut self) -> &mut ShapeCommon;
+  fn get_id(&self) -> usize {
+    self.common().id
+  }
+  // The group this shape is a child of, if any
+  fn get_parent(&self) -> Option<Arc<dyn Shape>> {
+    let parent = self.common().parent.read();
+    parent.as_ref().and_then(|parent| parent.upgrade())
+  }
+  fn set_parent(&self, parent: Weak<dyn Shape>) {
+    self.common().parent.replace(Some(parent));
+  }
+  fn get_transform(&self) -> &math::Matrix4 {
+    self.common().transform.matrix()
+  }
+  // The inverse of the transform and its transpose, cached so they are not
+  // recomputed for every ray and normal
+  fn get_inverse(&self) -> &math::Matrix4 {
+    self.common().transform.inverse()
+  }
+  fn get_inverse_transpose(&self) -> &math::Matrix4 {
+    self.common().transform.inverse_transpose()
+  }
+  fn set_transform(&mut self, transform: math::Matrix4) {
+    self.common_mut().transform = math::Transform::new(transform);
+    self.common().geometry_changed();
+  }
+  fn get_material(&self) -> Arc<Material> {
+    self.common().material.clone()
+  }
+  fn set_material(&mut self, material: Material) {
+    self.common_mut().material = Arc::new(material);
+  }
+  // Changes whenever the shape's bounds may have changed, so that hierarchies
+  // built from them can tell they are out of date
+  fn generation(&self) -> usize {
+    *self.common().generation.read()
+  }
   fn local_normal_at(&self, p: &math::Tuple) -> math::Tuple;
-  fn intersect(&self, ray: &Ray) -> Vec<Intersection>;
-  fn get_saved_ray(&self) -> Ray;
+  // The normal at a point hit at (u, v) on the surface. Only shapes that
+  // interpolate their normals need to override this.
+  fn local_normal_at_uv(&self, p: &math::Tuple, _u: f64, _v: f64) -> math::Tuple {
+    self.local_normal_at(p)
+  }
+  // Takes the shared shape rather than a reference, so the intersections can
+  // point back at it without copying the shape
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection>;
+  // An axis-aligned box around the shape in its own object space
+  fn bounds(&self) -> BoundingBox;
+  // Whether the shape is, or is built from, the other shape. Used by CSG to
+  // tell which operand an intersection belongs to.
+  fn includes(&self, other: &dyn Shape) -> bool {
+    self.get_id() == other.get_id()
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct Sphere {
-  pub id: usize,
-  pub transform: Rc<math::Matrix>,
-  pub material: Rc<Material>,
-  saved_ray: Option<Ray>,
+  common: ShapeCommon,
 }
 
 impl Sphere {
   pub fn new() -> Sphere {
     Sphere {
-      id: GLOBAL_GEOMETRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
-      transform: Rc::new(math::Matrix::new_identity_matrix(4)),
-      material: Rc::new(Material::new()),
-      saved_ray: None,
+      common: ShapeCommon::new(),
     }
   }
 }
 
 impl Shape for Sphere {
-  fn get_id(&self) -> usize {
-    self.id
-  }
-  fn get_transform(&self) -> Rc<math::Matrix> {
-    self.transform.clone()
+  fn common(&self) -> &ShapeCommon {
+    &self.common
   }
-  fn set_transform(&mut self, transform: math::Matrix) {
-    self.transform = Rc::new(transform)
-  }
-  fn get_material(&self) -> Rc<Material> {
-    self.material.clone()
-  }
-  fn set_material(&mut self, material: Material) {
-    self.material = Rc::new(material);
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
   }
   fn local_normal_at(&self, point_in_object_space: &math::Tuple) -> math::Tuple {
     // The normal at a point on the unit-sphere is the vector from (0,0,0) to the point in
@@ -81,13 +175,8 @@ impl Shape for Sphere {
     point_in_object_space - &math::Tuple::point(0., 0., 0.)
   }
 
-  fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
-    let inverse_sphere_transform = match self.get_transform().invert() {
-      Some(i) => i,
-      None => panic!(),
-    };
-
-    let ray = ray.transform(&inverse_sphere_transform);
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
     let sphere_to_ray = &ray.origin - &Tuple::point(0., 0., 0.);
 
     // Solve the quadratic equation resulting from:
@@ -106,61 +195,56 @@ impl Shape for Sphere {
     let t2 = (-b + discriminant.sqrt()) / (2. * a);
     vec.push(Intersection {
       t: t1,
-      shape: Rc::new(self.clone()),
+      shape: self.clone(),
+      uv: None,
     });
     vec.push(Intersection {
       t: t2,
-      shape: Rc::new(self.clone()),
+      shape: self.clone(),
+      uv: None,
     });
 
     vec
   }
-  fn get_saved_ray(&self) -> Ray {
-    self.saved_ray.clone().unwrap()
+  fn bounds(&self) -> BoundingBox {
+    BoundingBox::new(&Tuple::point(-1., -1., -1.), &Tuple::point(1., 1., 1.))
   }
 }
 
 impl cmp::PartialEq for Sphere {
   fn eq(&self, other: &Self) -> bool {
-    self.transform == other.transform && self.material == other.material
+    self.common.transform == other.common.transform && self.common.material == other.common.material
   }
 }
 
 #[derive(Debug, Clone)]
 pub struct TestShape {
-  pub id: usize,
-  pub transform: Rc<math::Matrix>,
-  pub material: Rc<Material>,
-  // We need interior mutability for saved_ray because intersect is used in an RC<dyn Shape> vector
+  common: ShapeCommon,
+  // We need interior mutability for saved_ray because intersect is used in an Arc<dyn Shape> vector
   // Therefore intersect must mutate saved_ray without taking a &mut self
-  saved_ray: RefCell<Option<Ray>>,
+  saved_ray: LockCell<Option<Ray>>,
 }
 impl TestShape {
   pub fn new() -> TestShape {
     TestShape {
-      id: GLOBAL_GEOMETRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
-      transform: Rc::new(math::Matrix::new_identity_matrix(4)),
-      material: Rc::new(Material::new()),
-      saved_ray: RefCell::new(None),
+      common: ShapeCommon::new(),
+      saved_ray: LockCell::new(None),
     }
   }
-}
-impl Shape for TestShape {
-  fn get_id(&self) -> usize {
-    self.id
-  }
-
-  fn get_transform(&self) -> Rc<math::Matrix> {
-    self.transform.clone()
+  // The ray, in object space, that the shape was last intersected with
+  pub fn get_saved_ray(&self) -> Ray {
+    self.saved_ray.read().clone().unwrap()
   }
-  fn set_transform(&mut self, transform: math::Matrix) {
-    self.transform = Rc::new(transform)
+  pub fn was_intersected(&self) -> bool {
+    self.saved_ray.read().is_some()
   }
-  fn set_material(&mut self, material: Material) {
-    self.material = Rc::new(material);
+}
+impl Shape for TestShape {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
   }
-  fn get_material(&self) -> Rc<Material> {
-    self.material.clone()
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
   }
 
   fn local_normal_at(&self, point_in_object_space: &math::Tuple) -> math::Tuple {
@@ -169,80 +253,715 @@ impl Shape for TestShape {
     point_in_object_space - &math::Tuple::point(0., 0., 0.)
   }
 
-  fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
-    let inverse_sphere_transform = match self.get_transform().invert() {
-      Some(i) => i,
-      None => panic!(),
-    };
-
-    let ray = ray.transform(&inverse_sphere_transform);
-    self.saved_ray.replace(Some(ray.clone()));
-    let vec = Vec::with_capacity(2);
-    vec
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+    self.saved_ray.replace(Some(ray));
+    Vec::with_capacity(2)
   }
-  fn get_saved_ray(&self) -> Ray {
-    self.saved_ray.clone().into_inner().unwrap()
+  fn bounds(&self) -> BoundingBox {
+    BoundingBox::new(&Tuple::point(-1., -1., -1.), &Tuple::point(1., 1., 1.))
   }
 }
 
 #[derive(Debug, Clone)]
 pub struct Plane {
-  pub id: usize,
-  pub transform: Rc<math::Matrix>,
-  pub material: Rc<Material>,
-  saved_ray: RefCell<Option<Ray>>,
+  common: ShapeCommon,
 }
 impl Plane {
   pub fn new() -> Plane {
     Plane {
-      id: GLOBAL_GEOMETRY_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
-      transform: Rc::new(math::Matrix::new_identity_matrix(4)),
-      material: Rc::new(Material::new()),
-      saved_ray: RefCell::new(None),
+      common: ShapeCommon::new(),
     }
   }
 }
 impl Shape for Plane {
-  fn get_id(&self) -> usize {
-    self.id
-  }
-
-  fn get_transform(&self) -> Rc<math::Matrix> {
-    self.transform.clone()
-  }
-  fn set_transform(&mut self, transform: math::Matrix) {
-    self.transform = Rc::new(transform)
+  fn common(&self) -> &ShapeCommon {
+    &self.common
   }
-  fn set_material(&mut self, material: Material) {
-    self.material = Rc::new(material);
-  }
-  fn get_material(&self) -> Rc<Material> {
-    self.material.clone()
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
   }
 
   fn local_normal_at(&self, _: &math::Tuple) -> math::Tuple {
     Tuple::vector(0.0, 1.0, 0.0)
   }
 
-  fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
-    let inverse_transform = match self.get_transform().invert() {
-      Some(i) => i,
-      None => panic!(),
-    };
-    let ray = ray.transform(&inverse_transform);
-    self.saved_ray.replace(Some(ray.clone()));
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
     let mut vec = Vec::with_capacity(1);
     if f64::abs(ray.direction.y) < util::EPSILON {
       return vec;
     } else {
       vec.push(Intersection {
         t: -ray.origin.y / ray.direction.y,
-        shape: Rc::new(self.clone()),
+        shape: self.clone(),
+        uv: None,
       });
       return vec;
     }
   }
-  fn get_saved_ray(&self) -> Ray {
-    self.saved_ray.clone().into_inner().unwrap()
+  fn bounds(&self) -> BoundingBox {
+    BoundingBox::new(
+      &Tuple::point(f64::NEG_INFINITY, 0., f64::NEG_INFINITY),
+      &Tuple::point(f64::INFINITY, 0., f64::INFINITY),
+    )
+  }
+}
+
+// An axis-aligned cube spanning -1..1 along every axis in object space
+#[derive(Debug, Clone)]
+pub struct Cube {
+  common: ShapeCommon,
+}
+impl Cube {
+  pub fn new() -> Cube {
+    Cube {
+      common: ShapeCommon::new(),
+    }
+  }
+  // Find where the ray enters and leaves the slab between the two planes at -1 and 1
+  // perpendicular to one axis:
+  fn check_axis(origin: f64, direction: f64) -> (f64, f64) {
+    let tmin_numerator = -1. - origin;
+    let tmax_numerator = 1. - origin;
+    // Dividing by a zero direction gives infinities with the correct sign,
+    // which is what we want for rays parallel to the slab
+    let tmin = tmin_numerator / direction;
+    let tmax = tmax_numerator / direction;
+    if tmin > tmax {
+      (tmax, tmin)
+    } else {
+      (tmin, tmax)
+    }
+  }
+}
+impl Shape for Cube {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, point_in_object_space: &math::Tuple) -> math::Tuple {
+    // The normal points along the axis where the point is furthest from the center
+    let p = point_in_object_space;
+    let maxc = f64::max(f64::max(p.x.abs(), p.y.abs()), p.z.abs());
+    if maxc == p.x.abs() {
+      Tuple::vector(p.x, 0., 0.)
+    } else if maxc == p.y.abs() {
+      Tuple::vector(0., p.y, 0.)
+    } else {
+      Tuple::vector(0., 0., p.z)
+    }
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    // The cube is the intersection of three slabs, the ray is inside the cube
+    // between the largest entry and the smallest exit of the slabs
+    let (xtmin, xtmax) = Cube::check_axis(ray.origin.x, ray.direction.x);
+    let (ytmin, ytmax) = Cube::check_axis(ray.origin.y, ray.direction.y);
+    let (ztmin, ztmax) = Cube::check_axis(ray.origin.z, ray.direction.z);
+    let tmin = f64::max(f64::max(xtmin, ytmin), ztmin);
+    let tmax = f64::min(f64::min(xtmax, ytmax), ztmax);
+
+    let mut vec = Vec::with_capacity(2);
+    if tmin > tmax {
+      return vec;
+    }
+    vec.push(Intersection {
+      t: tmin,
+      shape: self.clone(),
+      uv: None,
+    });
+    vec.push(Intersection {
+      t: tmax,
+      shape: self.clone(),
+      uv: None,
+    });
+    vec
+  }
+  fn bounds(&self) -> BoundingBox {
+    BoundingBox::new(&Tuple::point(-1., -1., -1.), &Tuple::point(1., 1., 1.))
+  }
+}
+
+// Check if the intersection at t lies within the given radius from the y axis,
+// used for the end caps of cylinders and cones
+fn check_cap(ray: &Ray, t: f64, radius: f64) -> bool {
+  let x = ray.origin.x + t * ray.direction.x;
+  let z = ray.origin.z + t * ray.direction.z;
+  (x * x + z * z) <= radius * radius + util::EPSILON
+}
+
+// A cylinder with radius 1 around the y axis. It is infinitely long unless
+// truncated by minimum and maximum (exclusive), and hollow unless closed.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+  common: ShapeCommon,
+  minimum: f64,
+  maximum: f64,
+  pub closed: bool,
+}
+impl Cylinder {
+  pub fn new() -> Cylinder {
+    Cylinder {
+      common: ShapeCommon::new(),
+      minimum: f64::NEG_INFINITY,
+      maximum: f64::INFINITY,
+      closed: false,
+    }
+  }
+  pub fn minimum(&self) -> f64 {
+    self.minimum
+  }
+  pub fn maximum(&self) -> f64 {
+    self.maximum
+  }
+  // The limits change the shape's bounds, so they can only be set through these
+  pub fn set_minimum(&mut self, minimum: f64) {
+    self.minimum = minimum;
+    self.common.geometry_changed();
+  }
+  pub fn set_maximum(&mut self, maximum: f64) {
+    self.maximum = maximum;
+    self.common.geometry_changed();
+  }
+  fn intersect_caps(self: &Arc<Self>, ray: &Ray, vec: &mut Vec<Intersection>) {
+    // Caps only matter if the cylinder is closed and the ray could hit them
+    if !self.closed || f64::abs(ray.direction.y) < util::EPSILON {
+      return;
+    }
+    let t = (self.minimum - ray.origin.y) / ray.direction.y;
+    if check_cap(ray, t, 1.) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: None,
+      });
+    }
+    let t = (self.maximum - ray.origin.y) / ray.direction.y;
+    if check_cap(ray, t, 1.) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: None,
+      });
+    }
+  }
+}
+impl Shape for Cylinder {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, point_in_object_space: &math::Tuple) -> math::Tuple {
+    let p = point_in_object_space;
+    let dist = p.x * p.x + p.z * p.z;
+    if dist < 1. && p.y >= self.maximum - util::EPSILON {
+      Tuple::vector(0., 1., 0.)
+    } else if dist < 1. && p.y <= self.minimum + util::EPSILON {
+      Tuple::vector(0., -1., 0.)
+    } else {
+      Tuple::vector(p.x, 0., p.z)
+    }
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    let mut vec = Vec::with_capacity(2);
+    // Solve x^2 + z^2 = 1 for points on the ray. If a is zero the ray is
+    // parallel to the y axis and can only hit the caps
+    let a = ray.direction.x * ray.direction.x + ray.direction.z * ray.direction.z;
+    if a.abs() >= util::EPSILON {
+      let b = 2. * ray.origin.x * ray.direction.x + 2. * ray.origin.z * ray.direction.z;
+      let c = ray.origin.x * ray.origin.x + ray.origin.z * ray.origin.z - 1.;
+      let discriminant = b * b - 4. * a * c;
+      if discriminant < 0. {
+        return vec;
+      }
+      let mut t0 = (-b - discriminant.sqrt()) / (2. * a);
+      let mut t1 = (-b + discriminant.sqrt()) / (2. * a);
+      if t0 > t1 {
+        std::mem::swap(&mut t0, &mut t1);
+      }
+      for t in [t0, t1].iter() {
+        let y = ray.origin.y + t * ray.direction.y;
+        if self.minimum < y && y < self.maximum {
+          vec.push(Intersection {
+            t: *t,
+            shape: self.clone(),
+            uv: None,
+          });
+        }
+      }
+    }
+    self.intersect_caps(&ray, &mut vec);
+    vec
+  }
+  fn bounds(&self) -> BoundingBox {
+    BoundingBox::new(
+      &Tuple::point(-1., self.minimum, -1.),
+      &Tuple::point(1., self.maximum, 1.),
+    )
+  }
+}
+
+// A double-napped cone around the y axis with its tip at the origin, where the
+// radius at any y equals |y|. Truncation and caps work as for the cylinder.
+#[derive(Debug, Clone)]
+pub struct Cone {
+  common: ShapeCommon,
+  minimum: f64,
+  maximum: f64,
+  pub closed: bool,
+}
+impl Cone {
+  pub fn new() -> Cone {
+    Cone {
+      common: ShapeCommon::new(),
+      minimum: f64::NEG_INFINITY,
+      maximum: f64::INFINITY,
+      closed: false,
+    }
+  }
+  pub fn minimum(&self) -> f64 {
+    self.minimum
+  }
+  pub fn maximum(&self) -> f64 {
+    self.maximum
+  }
+  // The limits change the shape's bounds, so they can only be set through these
+  pub fn set_minimum(&mut self, minimum: f64) {
+    self.minimum = minimum;
+    self.common.geometry_changed();
+  }
+  pub fn set_maximum(&mut self, maximum: f64) {
+    self.maximum = maximum;
+    self.common.geometry_changed();
+  }
+  fn intersect_caps(self: &Arc<Self>, ray: &Ray, vec: &mut Vec<Intersection>) {
+    if !self.closed || f64::abs(ray.direction.y) < util::EPSILON {
+      return;
+    }
+    // The radius of each cap is the distance from the tip along y
+    let t = (self.minimum - ray.origin.y) / ray.direction.y;
+    if check_cap(ray, t, self.minimum.abs()) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: None,
+      });
+    }
+    let t = (self.maximum - ray.origin.y) / ray.direction.y;
+    if check_cap(ray, t, self.maximum.abs()) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: None,
+      });
+    }
+  }
+}
+impl Shape for Cone {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, point_in_object_space: &math::Tuple) -> math::Tuple {
+    let p = point_in_object_space;
+    let dist = p.x * p.x + p.z * p.z;
+    if dist < self.maximum * self.maximum && p.y >= self.maximum - util::EPSILON {
+      return Tuple::vector(0., 1., 0.);
+    } else if dist < self.minimum * self.minimum && p.y <= self.minimum + util::EPSILON {
+      return Tuple::vector(0., -1., 0.);
+    }
+    let mut y = dist.sqrt();
+    if p.y > 0. {
+      y = -y;
+    }
+    Tuple::vector(p.x, y, p.z)
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    let mut vec = Vec::with_capacity(2);
+    // Solve x^2 - y^2 + z^2 = 0 for points on the ray
+    let (o, d) = (&ray.origin, &ray.direction);
+    let a = d.x * d.x - d.y * d.y + d.z * d.z;
+    let b = 2. * o.x * d.x - 2. * o.y * d.y + 2. * o.z * d.z;
+    let c = o.x * o.x - o.y * o.y + o.z * o.z;
+    let mut ts = Vec::with_capacity(2);
+    if a.abs() < util::EPSILON {
+      // The ray is parallel to one of the cone halves, so it hits the other one once
+      if b.abs() >= util::EPSILON {
+        ts.push(-c / (2. * b));
+      }
+    } else {
+      let discriminant = b * b - 4. * a * c;
+      if discriminant >= 0. {
+        let t0 = (-b - discriminant.sqrt()) / (2. * a);
+        let t1 = (-b + discriminant.sqrt()) / (2. * a);
+        ts.push(f64::min(t0, t1));
+        ts.push(f64::max(t0, t1));
+      }
+    }
+    for t in ts {
+      let y = o.y + t * d.y;
+      if self.minimum < y && y < self.maximum {
+        vec.push(Intersection {
+          t,
+          shape: self.clone(),
+          uv: None,
+        });
+      }
+    }
+    self.intersect_caps(&ray, &mut vec);
+    vec
+  }
+  fn bounds(&self) -> BoundingBox {
+    let limit = self.minimum.abs().max(self.maximum.abs());
+    BoundingBox::new(
+      &Tuple::point(-limit, self.minimum, -limit),
+      &Tuple::point(limit, self.maximum, limit),
+    )
+  }
+}
+
+// A collection of shapes that are transformed together. Children are intersected
+// in the group's object space, and keep a link to the group so that points and
+// normals can be converted through the whole hierarchy.
+#[derive(Debug, Clone)]
+pub struct Group {
+  common: ShapeCommon,
+  children: LockCell<Vec<Arc<dyn Shape>>>,
+  // Built on first use, together with the generation of the group it was built
+  // at, and rebuilt once anything in the group has changed since
+  bvh: LockCell<Option<(usize, Arc<Bvh>)>>,
+}
+impl Group {
+  pub fn new() -> Group {
+    Group {
+      common: ShapeCommon::new(),
+      children: LockCell::new(Vec::new()),
+      bvh: LockCell::new(None),
+    }
+  }
+  // The group must already be shared so the child can refer back to it
+  pub fn add_child(group: &Arc<Group>, child: Arc<dyn Shape>) {
+    let parent: Arc<dyn Shape> = group.clone();
+    child.set_parent(Arc::downgrade(&parent));
+    group.children.write().push(child);
+    group.common.geometry_changed();
+  }
+  pub fn children(&self) -> Vec<Arc<dyn Shape>> {
+    self.children.read().clone()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.children.read().is_empty()
+  }
+  // The hierarchy over the children, in the group's object space
+  pub fn bvh(&self) -> Arc<Bvh> {
+    let generation = self.generation();
+    if let Some((built_at, bvh)) = self.bvh.read().as_ref() {
+      if *built_at == generation {
+        return bvh.clone();
+      }
+    }
+    let bvh = Arc::new(Bvh::new(&self.children.read()));
+    self.bvh.replace(Some((generation, bvh.clone())));
+    bvh
+  }
+}
+impl Shape for Group {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, _: &math::Tuple) -> math::Tuple {
+    // Intersections always refer to the child that was hit, never the group
+    panic!("local_normal_at called on a group");
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    self.bvh().intersect(&ray)
+  }
+  fn bounds(&self) -> BoundingBox {
+    self.bvh().bounds()
+  }
+  fn includes(&self, other: &dyn Shape) -> bool {
+    self.children.read().iter().any(|child| child.includes(other))
+  }
+}
+
+// Intersect a ray with the triangle at p1 spanned by the edges e1 and e2 using the
+// Möller–Trumbore algorithm. Returns t and the barycentric coordinates (u, v) of the hit.
+fn intersect_triangle(ray: &Ray, p1: &Tuple, e1: &Tuple, e2: &Tuple) -> Option<(f64, f64, f64)> {
+  let dir_cross_e2 = Tuple::cross(&ray.direction, e2);
+  let det = Tuple::dot(e1, &dir_cross_e2);
+  if det.abs() < util::EPSILON {
+    // the ray is parallel to the triangle
+    return None;
+  }
+  let f = 1.0 / det;
+  let p1_to_origin = &ray.origin - p1;
+  let u = f * Tuple::dot(&p1_to_origin, &dir_cross_e2);
+  if !(0.0..=1.0).contains(&u) {
+    return None;
+  }
+  let origin_cross_e1 = Tuple::cross(&p1_to_origin, e1);
+  let v = f * Tuple::dot(&ray.direction, &origin_cross_e1);
+  if v < 0.0 || u + v > 1.0 {
+    return None;
+  }
+  let t = f * Tuple::dot(e2, &origin_cross_e1);
+  Some((t, u, v))
+}
+
+#[derive(Debug, Clone)]
+pub struct Triangle {
+  common: ShapeCommon,
+  pub p1: Tuple,
+  pub p2: Tuple,
+  pub p3: Tuple,
+  pub e1: Tuple,
+  pub e2: Tuple,
+  pub normal: Tuple,
+}
+impl Triangle {
+  pub fn new(p1: &Tuple, p2: &Tuple, p3: &Tuple) -> Triangle {
+    let e1 = p2 - p1;
+    let e2 = p3 - p1;
+    let normal = Tuple::cross(&e2, &e1).normalize();
+    Triangle {
+      common: ShapeCommon::new(),
+      p1: p1.clone(),
+      p2: p2.clone(),
+      p3: p3.clone(),
+      e1,
+      e2,
+      normal,
+    }
+  }
+}
+impl Shape for Triangle {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, _: &math::Tuple) -> math::Tuple {
+    self.normal.clone()
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    let mut vec = Vec::with_capacity(1);
+    if let Some((t, u, v)) = intersect_triangle(&ray, &self.p1, &self.e1, &self.e2) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: Some((u, v)),
+      });
+    }
+    vec
+  }
+  fn bounds(&self) -> BoundingBox {
+    let mut bounds = BoundingBox::empty();
+    bounds.add_point(&self.p1);
+    bounds.add_point(&self.p2);
+    bounds.add_point(&self.p3);
+    bounds
+  }
+}
+
+// A triangle with a normal at each vertex. The normal at a hit is interpolated
+// from the vertex normals, which makes meshes look smooth.
+#[derive(Debug, Clone)]
+pub struct SmoothTriangle {
+  common: ShapeCommon,
+  pub p1: Tuple,
+  pub p2: Tuple,
+  pub p3: Tuple,
+  pub n1: Tuple,
+  pub n2: Tuple,
+  pub n3: Tuple,
+  pub e1: Tuple,
+  pub e2: Tuple,
+}
+impl SmoothTriangle {
+  pub fn new(
+    p1: &Tuple,
+    p2: &Tuple,
+    p3: &Tuple,
+    n1: &Tuple,
+    n2: &Tuple,
+    n3: &Tuple,
+  ) -> SmoothTriangle {
+    SmoothTriangle {
+      common: ShapeCommon::new(),
+      p1: p1.clone(),
+      p2: p2.clone(),
+      p3: p3.clone(),
+      n1: n1.clone(),
+      n2: n2.clone(),
+      n3: n3.clone(),
+      e1: p2 - p1,
+      e2: p3 - p1,
+    }
+  }
+}
+impl Shape for SmoothTriangle {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, _: &math::Tuple) -> math::Tuple {
+    // Without knowing where the hit is, fall back to the face normal
+    Tuple::cross(&self.e2, &self.e1).normalize()
+  }
+  fn local_normal_at_uv(&self, _: &math::Tuple, u: f64, v: f64) -> math::Tuple {
+    &self.n2 * u + &(&self.n3 * v) + &(&self.n1 * (1. - u - v))
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    let mut vec = Vec::with_capacity(1);
+    if let Some((t, u, v)) = intersect_triangle(&ray, &self.p1, &self.e1, &self.e2) {
+      vec.push(Intersection {
+        t,
+        shape: self.clone(),
+        uv: Some((u, v)),
+      });
+    }
+    vec
+  }
+  fn bounds(&self) -> BoundingBox {
+    let mut bounds = BoundingBox::empty();
+    bounds.add_point(&self.p1);
+    bounds.add_point(&self.p2);
+    bounds.add_point(&self.p3);
+    bounds
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOperation {
+  Union,
+  Intersection,
+  Difference,
+}
+impl CsgOperation {
+  // Whether an intersection survives the operation. `left_hit` tells which
+  // operand was hit, and `in_left`/`in_right` whether the hit lies inside the
+  // left and right operands respectively.
+  pub fn intersection_allowed(&self, left_hit: bool, in_left: bool, in_right: bool) -> bool {
+    match self {
+      CsgOperation::Union => (left_hit && !in_right) || (!left_hit && !in_left),
+      CsgOperation::Intersection => (left_hit && in_right) || (!left_hit && in_left),
+      CsgOperation::Difference => (left_hit && !in_right) || (!left_hit && in_left),
+    }
+  }
+}
+
+// Constructive solid geometry: two shapes combined by a set operation. Like a
+// group, the operands are intersected in the CSG's object space and keep a link
+// back to it.
+#[derive(Debug, Clone)]
+pub struct Csg {
+  common: ShapeCommon,
+  pub operation: CsgOperation,
+  pub left: Arc<dyn Shape>,
+  pub right: Arc<dyn Shape>,
+}
+impl Csg {
+  pub fn new(operation: CsgOperation, left: Arc<dyn Shape>, right: Arc<dyn Shape>) -> Csg {
+    Csg {
+      common: ShapeCommon::new(),
+      operation,
+      left,
+      right,
+    }
+  }
+  // The CSG must be shared before the operands can refer back to it, so this
+  // is done once it is fully set up
+  pub fn into_shape(self) -> Arc<Csg> {
+    let csg = Arc::new(self);
+    let parent: Arc<dyn Shape> = csg.clone();
+    csg.left.set_parent(Arc::downgrade(&parent));
+    csg.right.set_parent(Arc::downgrade(&parent));
+    csg
+  }
+
+  // Walks the sorted intersections, tracking whether each point is inside the
+  // left and right operands, and keeps those the operation allows
+  pub fn filter_intersections(&self, xs: Vec<Intersection>) -> Vec<Intersection> {
+    let mut in_left = false;
+    let mut in_right = false;
+    let mut result: Vec<Intersection> = Vec::new();
+    for i in xs {
+      let left_hit = self.left.includes(i.shape.as_ref());
+      if self.operation.intersection_allowed(left_hit, in_left, in_right) {
+        result.push(i);
+      }
+      if left_hit {
+        in_left = !in_left;
+      } else {
+        in_right = !in_right;
+      }
+    }
+    result
+  }
+}
+impl Shape for Csg {
+  fn common(&self) -> &ShapeCommon {
+    &self.common
+  }
+  fn common_mut(&mut self) -> &mut ShapeCommon {
+    &mut self.common
+  }
+
+  fn local_normal_at(&self, _: &math::Tuple) -> math::Tuple {
+    // Intersections always refer to the operand that was hit, never the CSG
+    panic!("local_normal_at called on a CSG");
+  }
+
+  fn intersect(self: Arc<Self>, ray: &Ray) -> Vec<Intersection> {
+    let ray = ray.transform(self.get_inverse());
+
+    let mut xs = self.left.clone().intersect(&ray);
+    xs.append(&mut self.right.clone().intersect(&ray));
+    xs.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+    self.filter_intersections(xs)
+  }
+  fn bounds(&self) -> BoundingBox {
+    let mut bounds = parent_space_bounds(self.left.as_ref());
+    bounds.merge(&parent_space_bounds(self.right.as_ref()));
+    bounds
+  }
+  fn includes(&self, other: &dyn Shape) -> bool {
+    self.left.includes(other) || self.right.includes(other)
   }
 }
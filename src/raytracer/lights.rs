@@ -1,7 +1,48 @@
 use std::cmp;
+use std::fmt;
 
 use crate::math::Tuple;
 use crate::color::Color;
+use crate::util;
+
+// A single sample of a light as seen from a point: the direction from the point
+// towards the light and how far away the light is in that direction.
+#[derive(Debug, Clone)]
+pub struct LightSample {
+  pub direction: Tuple,
+  pub distance: f64,
+}
+
+impl LightSample {
+  // The sample of a light positioned at `position` as seen from `point`
+  pub fn towards(point: &Tuple, position: &Tuple) -> LightSample {
+    let point_to_light = position - point;
+    LightSample {
+      direction: point_to_light.normalize(),
+      distance: point_to_light.magnitude(),
+    }
+  }
+}
+
+pub trait Light: fmt::Debug + Send + Sync {
+  // Normalized vector from the point towards the light
+  fn direction_to_light(&self, point: &Tuple) -> Tuple;
+  // Distance from the point to the light, infinite for lights without a position
+  fn distance(&self, point: &Tuple) -> f64;
+  // The color and brightness of the light
+  fn intensity(&self) -> Color;
+  // The light arriving at the point, which may be less than the full intensity
+  fn intensity_at(&self, _point: &Tuple) -> Color {
+    self.intensity()
+  }
+  // The samples used for shading and shadow tests at the point
+  fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+    vec![LightSample {
+      direction: self.direction_to_light(point),
+      distance: self.distance(point),
+    }]
+  }
+}
 
 #[derive(Debug)]
 pub struct PointLight {
@@ -18,9 +59,186 @@ impl PointLight {
   } 
 }
 
+impl Light for PointLight {
+  fn direction_to_light(&self, point: &Tuple) -> Tuple {
+    (&self.position - point).normalize()
+  }
+  fn distance(&self, point: &Tuple) -> f64 {
+    (&self.position - point).magnitude()
+  }
+  fn intensity(&self) -> Color {
+    self.intensity.clone()
+  }
+}
+
 impl cmp::PartialEq for PointLight {
   fn eq(&self, other: &Self) -> bool {
     self.position == other.position &&
     self.intensity == other.intensity
   }
-}
\ No newline at end of file
+}
+
+// A rectangular light spanned by two edge vectors from a corner. The rectangle is
+// divided into usteps x vsteps cells, and one point in each cell is sampled, which
+// gives soft shadows with penumbras.
+#[derive(Debug)]
+pub struct AreaLight {
+  pub corner: Tuple,
+  pub uvec: Tuple,
+  pub usteps: usize,
+  pub vvec: Tuple,
+  pub vsteps: usize,
+  pub intensity: Color,
+  // Sample a random point within each cell instead of its center
+  pub jitter: bool,
+}
+
+impl AreaLight {
+  // A step count of 0 is taken as 1, so every light has at least one sample
+  pub fn new(
+    corner: &Tuple,
+    full_uvec: &Tuple,
+    usteps: usize,
+    full_vvec: &Tuple,
+    vsteps: usize,
+    intensity: &Color,
+  ) -> AreaLight {
+    let usteps = usteps.max(1);
+    let vsteps = vsteps.max(1);
+    AreaLight {
+      corner: corner.clone(),
+      uvec: full_uvec * (1.0 / usteps as f64),
+      usteps,
+      vvec: full_vvec * (1.0 / vsteps as f64),
+      vsteps,
+      intensity: intensity.clone(),
+      jitter: false,
+    }
+  }
+  pub fn sample_count(&self) -> usize {
+    self.usteps * self.vsteps
+  }
+  // The point in cell (u, v), offset into the cell by (u_offset, v_offset) in [0, 1)
+  pub fn point_on_light(&self, u: usize, v: usize, u_offset: f64, v_offset: f64) -> Tuple {
+    &self.corner
+      + &(&self.uvec * (u as f64 + u_offset))
+      + &(&self.vvec * (v as f64 + v_offset))
+  }
+  // The center of the rectangle
+  pub fn position(&self) -> Tuple {
+    &self.corner
+      + &(&self.uvec * (self.usteps as f64 / 2.0))
+      + &(&self.vvec * (self.vsteps as f64 / 2.0))
+  }
+}
+
+impl Light for AreaLight {
+  fn direction_to_light(&self, point: &Tuple) -> Tuple {
+    (&self.position() - point).normalize()
+  }
+  fn distance(&self, point: &Tuple) -> f64 {
+    (&self.position() - point).magnitude()
+  }
+  fn intensity(&self) -> Color {
+    self.intensity.clone()
+  }
+  fn samples(&self, point: &Tuple) -> Vec<LightSample> {
+    let mut samples = Vec::with_capacity(self.sample_count());
+    for v in 0..self.vsteps {
+      for u in 0..self.usteps {
+        let (u_offset, v_offset) = if self.jitter {
+          // Jitter is derived from the shaded point, so neighbouring points get
+          // different samples while rendering stays deterministic.
+          (
+            util::hash_to_unit(&[point.x, point.y, point.z, u as f64, v as f64, 0.]),
+            util::hash_to_unit(&[point.x, point.y, point.z, u as f64, v as f64, 1.]),
+          )
+        } else {
+          (0.5, 0.5)
+        };
+        let position = self.point_on_light(u, v, u_offset, v_offset);
+        samples.push(LightSample::towards(point, &position));
+      }
+    }
+    samples
+  }
+}
+
+// A light that shines from a position in a cone around a direction. Inside the
+// cone, `falloff` is the angle (in radians) at the edge over which the light
+// fades smoothly to nothing.
+#[derive(Debug)]
+pub struct SpotLight {
+  pub position: Tuple,
+  pub direction: Tuple,
+  pub angle: f64,
+  pub falloff: f64,
+  pub intensity: Color,
+}
+
+impl SpotLight {
+  pub fn new(position: &Tuple, direction: &Tuple, angle: f64, intensity: &Color) -> SpotLight {
+    SpotLight {
+      position: position.clone(),
+      direction: direction.normalize(),
+      angle,
+      falloff: 0.0,
+      intensity: intensity.clone(),
+    }
+  }
+}
+
+impl Light for SpotLight {
+  fn direction_to_light(&self, point: &Tuple) -> Tuple {
+    (&self.position - point).normalize()
+  }
+  fn distance(&self, point: &Tuple) -> f64 {
+    (&self.position - point).magnitude()
+  }
+  fn intensity(&self) -> Color {
+    self.intensity.clone()
+  }
+  fn intensity_at(&self, point: &Tuple) -> Color {
+    let light_to_point = (point - &self.position).normalize();
+    let cos_angle = Tuple::dot(&light_to_point, &self.direction).clamp(-1.0, 1.0);
+    let angle = cos_angle.acos();
+    if angle > self.angle {
+      return Color::new(0., 0., 0.);
+    }
+    let inner = self.angle - self.falloff;
+    if angle <= inner {
+      return self.intensity.clone();
+    }
+    // smoothstep from the edge of the cone towards the inner cone
+    let x = (self.angle - angle) / self.falloff;
+    &self.intensity * (x * x * (3.0 - 2.0 * x))
+  }
+}
+
+// A light infinitely far away shining in one direction, like the sun
+#[derive(Debug)]
+pub struct DirectionalLight {
+  pub direction: Tuple,
+  pub intensity: Color,
+}
+
+impl DirectionalLight {
+  pub fn new(direction: &Tuple, intensity: &Color) -> DirectionalLight {
+    DirectionalLight {
+      direction: direction.normalize(),
+      intensity: intensity.clone(),
+    }
+  }
+}
+
+impl Light for DirectionalLight {
+  fn direction_to_light(&self, _: &Tuple) -> Tuple {
+    -self.direction.clone()
+  }
+  fn distance(&self, _: &Tuple) -> f64 {
+    f64::INFINITY
+  }
+  fn intensity(&self) -> Color {
+    self.intensity.clone()
+  }
+}
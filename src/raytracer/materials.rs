@@ -1,12 +1,16 @@
 use super::geometry::Shape;
+use super::lights::Light;
+#[cfg(test)]
 use super::PointLight;
 use crate::color::Color;
 use crate::raytracer::patterns::Pattern;
+#[cfg(test)]
 use crate::raytracer::patterns::StripePattern;
+#[cfg(test)]
 use crate::raytracer::Sphere;
 use crate::{math::Tuple, util::equal};
 use std::cmp;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct Material {
@@ -15,8 +19,10 @@ pub struct Material {
   pub diffuse: f64,
   pub specular: f64,
   pub shininess: f64,
-  pub pattern: Option<Rc<dyn Pattern>>,
+  pub pattern: Option<Arc<dyn Pattern>>,
   pub reflective: f64,
+  pub transparency: f64,
+  pub refractive_index: f64,
 }
 
 impl Material {
@@ -29,52 +35,68 @@ impl Material {
       shininess: 200.0,
       pattern: None,
       reflective: 0.0,
+      transparency: 0.0,
+      refractive_index: 1.0,
     }
   }
-  // Calculate the color of a point in 3D space using the Phong shading model
+  // Calculate the color of a point in 3D space using the Phong shading model.
+  // The light intensity is the fraction of the light that is visible from the point,
+  // 0.0 when fully in shadow and 1.0 when fully lit.
   pub fn lighting(
     material: &Material,
-    object: Rc<dyn Shape>,
-    light: &PointLight,
+    object: Arc<dyn Shape>,
+    light: &dyn Light,
     point: &Tuple,
     eye_v: &Tuple,
     normal_v: &Tuple,
-    in_shadow: bool,
+    light_intensity: f64,
   ) -> Color {
-    let effective_color;
-    if let Some(pattern) = &material.pattern {
-      effective_color = &pattern.pattern_at_shape(object.clone(), point) * &light.intensity;
-    } else {
-      effective_color = &material.color * &light.intensity;
-    }
-
-    let light_vector = (&(light.position) - &point).normalize();
+    let surface_color = Material::color_at(material, object, point);
     // The ambient component is constant in the Phong model
-    let ambient = &effective_color * material.ambient;
+    let ambient = &(&surface_color * &light.intensity()) * material.ambient;
 
-    if in_shadow {
+    if light_intensity <= 0. {
       return ambient;
     }
-    let light_dot_normal = Tuple::dot(&light_vector, &normal_v);
-    let mut diffuse = Color::new(0., 0., 0.);
-    let mut specular = Color::new(0., 0., 0.);
+    // Spot lights only light up part of the scene
+    let intensity = light.intensity_at(point);
+    let effective_color = &surface_color * &intensity;
+    let mut sum = Color::new(0., 0., 0.);
+    let samples = light.samples(point);
+    for sample in &samples {
+      let light_vector = &sample.direction;
+      let light_dot_normal = Tuple::dot(light_vector, normal_v);
 
-    // The diffuse and specular components should only contribute if the angle
-    // between the light and the normal vector at that point is less than pi/2
-    if light_dot_normal > 0. {
-      // The diffuse component relies only on the angle between the light source
-      // and the surface normal:
-      diffuse = effective_color * material.diffuse * light_dot_normal;
-      // The specular component relies only on the angle between the
-      // light reflection vector and the eye vector
-      let reflect_vector = Tuple::reflect(&-light_vector, normal_v);
-      let reflect_dot_eye = Tuple::dot(&reflect_vector, &eye_v);
-      if reflect_dot_eye > 0. {
-        let factor = reflect_dot_eye.powf(material.shininess);
-        specular = &(light.intensity) * material.specular * factor;
+      // The diffuse and specular components should only contribute if the angle
+      // between the light and the normal vector at that point is less than pi/2
+      if light_dot_normal > 0. {
+        // The diffuse component relies only on the angle between the light source
+        // and the surface normal:
+        sum = sum + &(&effective_color * (material.diffuse * light_dot_normal));
+        // The specular component relies only on the angle between the
+        // light reflection vector and the eye vector
+        let reflect_vector = Tuple::reflect(&-light_vector.clone(), normal_v);
+        let reflect_dot_eye = Tuple::dot(&reflect_vector, eye_v);
+        if reflect_dot_eye > 0. {
+          let factor = reflect_dot_eye.powf(material.shininess);
+          sum = sum + &(&intensity * (material.specular * factor));
+        }
       }
     }
-    return ambient + &diffuse + &specular;
+    // Average the samples and attenuate by how much of the light is visible
+    return ambient + &(sum * (light_intensity / samples.len() as f64));
+  }
+  // The ambient contribution alone, used when there is no light source to shade with
+  pub fn ambient_lighting(material: &Material, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+    Material::color_at(material, object, point) * material.ambient
+  }
+  // The surface color at a point, taken from the pattern if the material has one
+  fn color_at(material: &Material, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+    if let Some(pattern) = &material.pattern {
+      pattern.pattern_at_shape(object, point)
+    } else {
+      material.color.clone()
+    }
   }
 }
 impl cmp::PartialEq for Material {
@@ -84,13 +106,16 @@ impl cmp::PartialEq for Material {
       && equal(self.diffuse, other.diffuse)
       && equal(self.specular, other.specular)
       && equal(self.shininess, other.shininess)
+      && equal(self.reflective, other.reflective)
+      && equal(self.transparency, other.transparency)
+      && equal(self.refractive_index, other.refractive_index)
   }
 }
 
 #[test]
 fn light_with_pattern_applied() {
   let mut m = Material::new();
-  m.pattern = Some(Rc::new(StripePattern::new(
+  m.pattern = Some(Arc::new(StripePattern::new(
     Color::new(1.0, 1.0, 1.0),
     Color::new(0.0, 0.0, 0.0),
   )));
@@ -102,21 +127,21 @@ fn light_with_pattern_applied() {
   let light = PointLight::new(&Tuple::point(0.0, 0.0, -10.0), &Color::new(1.0, 1.0, 1.0));
   let c1 = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &Tuple::point(0.9, 0.0, 0.0),
     &eye_vec,
     &normal_vec,
-    false,
+    1.0,
   );
   let c2 = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &Tuple::point(1.1, 0.0, 0.0),
     &eye_vec,
     &normal_vec,
-    false,
+    1.0,
   );
 
   assert_eq!(c1, Color::new(1.0, 1.0, 1.0));
@@ -128,3 +153,10 @@ fn reflectivity_default_material() {
   let m = Material::new();
   assert!(equal(m.reflective, 0.0));
 }
+
+#[test]
+fn transparency_and_refractive_index_default_material() {
+  let m = Material::new();
+  assert!(equal(m.transparency, 0.0));
+  assert!(equal(m.refractive_index, 1.0));
+}
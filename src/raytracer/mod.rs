@@ -1,3 +1,5 @@
+pub mod bounds;
+pub mod bvh;
 pub mod geometry;
 pub mod rays;
 pub mod lights;
@@ -5,14 +7,34 @@ pub mod materials;
 pub mod world;
 pub mod camera;
 pub mod patterns;
+pub mod obj;
+pub mod scene;
+pub mod yaml;
 pub use self::geometry::Sphere;
 pub use self::geometry::Plane;
+pub use self::geometry::Cube;
+pub use self::geometry::Cylinder;
+pub use self::geometry::Cone;
+pub use self::geometry::Csg;
+pub use self::geometry::CsgOperation;
+pub use self::geometry::Group;
+pub use self::geometry::SmoothTriangle;
+pub use self::geometry::Triangle;
 pub use self::rays::Intersection;
 pub use self::rays::Ray;
+pub use self::lights::AreaLight;
+pub use self::lights::DirectionalLight;
+pub use self::lights::Light;
 pub use self::lights::PointLight;
+pub use self::lights::SpotLight;
 pub use self::materials::Material;
 pub use self::world::World;
+pub use self::camera::AdaptiveSampling;
 pub use self::camera::Camera;
+pub use self::camera::Projection;
+pub use self::obj::ObjParser;
+pub use self::scene::Scene;
+pub use self::scene::SceneError;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,201 @@
+use super::geometry::Shape;
+use super::{Group, SmoothTriangle, Triangle};
+use crate::math::Tuple;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+// A line from the input that the parser did not understand, kept so callers
+// can report what was skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoredLine {
+  pub line_number: usize,
+  pub text: String,
+}
+
+// One corner of a face: a vertex index plus an optional normal index. Texture
+// indices are validated but triangles do not use them yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FaceVertex {
+  vertex: usize,
+  normal: Option<usize>,
+}
+
+pub struct ObjParser {
+  pub vertices: Vec<Tuple>,
+  pub normals: Vec<Tuple>,
+  pub texture_coordinates: Vec<(f64, f64)>,
+  pub ignored: Vec<IgnoredLine>,
+  default_group: Arc<Group>,
+  groups: Vec<(String, Arc<Group>)>,
+}
+
+impl ObjParser {
+  pub fn parse_file(path: &str) -> io::Result<ObjParser> {
+    let contents = fs::read_to_string(path)?;
+    return Ok(ObjParser::parse(&contents));
+  }
+
+  pub fn parse(input: &str) -> ObjParser {
+    let mut parser = ObjParser {
+      vertices: Vec::new(),
+      normals: Vec::new(),
+      texture_coordinates: Vec::new(),
+      ignored: Vec::new(),
+      default_group: Arc::new(Group::new()),
+      groups: Vec::new(),
+    };
+    let mut current = parser.default_group.clone();
+    for (index, line) in input.lines().enumerate() {
+      let mut words = line.split_whitespace();
+      let understood = match words.next() {
+        Some("v") => {
+          parse_floats(words, 3).map(|c| parser.vertices.push(Tuple::point(c[0], c[1], c[2])))
+        }
+        Some("vn") => {
+          parse_floats(words, 3).map(|c| parser.normals.push(Tuple::vector(c[0], c[1], c[2])))
+        }
+        Some("vt") => parse_floats(words, 1).map(|c| {
+          let v = if c.len() > 1 { c[1] } else { 0. };
+          parser.texture_coordinates.push((c[0], v))
+        }),
+        Some("f") => parser.parse_face(words).map(|triangles| {
+          for triangle in triangles {
+            Group::add_child(&current, triangle);
+          }
+        }),
+        // Blank lines and comments are expected, so they are not reported
+        None => Some(()),
+        Some(word) if word.starts_with('#') => Some(()),
+        Some("g") => words
+          .next()
+          .map(|name| current = parser.group_or_insert(name)),
+        _ => None,
+      };
+      if understood.is_none() {
+        parser.ignored.push(IgnoredLine {
+          line_number: index + 1,
+          text: line.to_string(),
+        });
+      }
+    }
+    return parser;
+  }
+
+  // Vertices and normals are 1-based, as in the file itself
+  pub fn vertex(&self, index: usize) -> &Tuple {
+    &self.vertices[index - 1]
+  }
+  pub fn normal(&self, index: usize) -> &Tuple {
+    &self.normals[index - 1]
+  }
+
+  pub fn default_group(&self) -> Arc<Group> {
+    self.default_group.clone()
+  }
+  pub fn group(&self, name: &str) -> Option<Arc<Group>> {
+    self
+      .groups
+      .iter()
+      .find(|(group_name, _)| group_name == name)
+      .map(|(_, group)| group.clone())
+  }
+
+  // Collects every non-empty group into a single group that can be added to a
+  // world or transformed as a whole
+  pub fn to_group(&self) -> Arc<Group> {
+    let group = Arc::new(Group::new());
+    let groups = Some(&self.default_group)
+      .into_iter()
+      .chain(self.groups.iter().map(|(_, g)| g));
+    for child in groups.filter(|g| !g.is_empty()) {
+      Group::add_child(&group, child.clone());
+    }
+    return group;
+  }
+
+  fn group_or_insert(&mut self, name: &str) -> Arc<Group> {
+    if let Some(group) = self.group(name) {
+      return group;
+    }
+    let group = Arc::new(Group::new());
+    self.groups.push((name.to_string(), group.clone()));
+    return group;
+  }
+
+  // Triangulates the polygon as a fan around its first vertex. Returns None
+  // when the face is malformed or refers to data that does not exist
+  fn parse_face<'a>(&self, words: impl Iterator<Item = &'a str>) -> Option<Vec<Arc<dyn Shape>>> {
+    let corners = words
+      .map(|word| self.parse_face_vertex(word))
+      .collect::<Option<Vec<FaceVertex>>>()?;
+    if corners.len() < 3 {
+      return None;
+    }
+    let mut triangles: Vec<Arc<dyn Shape>> = Vec::new();
+    for i in 1..corners.len() - 1 {
+      let (a, b, c) = (corners[0], corners[i], corners[i + 1]);
+      let (p1, p2, p3) = (
+        self.vertex(a.vertex),
+        self.vertex(b.vertex),
+        self.vertex(c.vertex),
+      );
+      match (a.normal, b.normal, c.normal) {
+        (Some(n1), Some(n2), Some(n3)) => triangles.push(Arc::new(SmoothTriangle::new(
+          p1,
+          p2,
+          p3,
+          self.normal(n1),
+          self.normal(n2),
+          self.normal(n3),
+        ))),
+        _ => triangles.push(Arc::new(Triangle::new(p1, p2, p3))),
+      }
+    }
+    return Some(triangles);
+  }
+
+  // Accepts "v", "v/vt", "v//vn" and "v/vt/vn"
+  fn parse_face_vertex(&self, word: &str) -> Option<FaceVertex> {
+    let mut parts = word.split('/');
+    let vertex = resolve_index(parts.next()?, self.vertices.len())?;
+    if let Some(s) = parts.next().filter(|s| !s.is_empty()) {
+      resolve_index(s, self.texture_coordinates.len())?;
+    }
+    let normal = match parts.next() {
+      None | Some("") => None,
+      Some(s) => Some(resolve_index(s, self.normals.len())?),
+    };
+    if parts.next().is_some() {
+      return None;
+    }
+    return Some(FaceVertex { vertex, normal });
+  }
+}
+
+// Parses at least `min` numbers from the rest of the line. Up to four are
+// allowed since vertices may carry an optional (ignored) w component
+fn parse_floats<'a>(words: impl Iterator<Item = &'a str>, min: usize) -> Option<Vec<f64>> {
+  let values = words
+    .map(|w| w.parse::<f64>().ok())
+    .collect::<Option<Vec<f64>>>()?;
+  if values.len() < min || values.len() > 4 {
+    return None;
+  }
+  return Some(values);
+}
+
+// Turns a 1-based or negative (relative to the end) OBJ index into a 1-based
+// index that is known to exist
+fn resolve_index(s: &str, count: usize) -> Option<usize> {
+  let index = s.parse::<i64>().ok()?;
+  let resolved = if index < 0 {
+    count as i64 + 1 + index
+  } else {
+    index
+  };
+  if resolved < 1 || resolved > count as i64 {
+    return None;
+  }
+  return Some(resolved as usize);
+}
@@ -1,34 +1,34 @@
-use super::geometry::Shape;
+use super::geometry::{world_to_object, Shape};
+#[cfg(test)]
 use super::Sphere;
 use crate::color::Color;
-use crate::math;
-use crate::math::Matrix;
+use crate::math::Matrix4;
+use crate::math::Transform;
 use crate::math::Tuple;
 
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub trait Pattern: fmt::Debug {
+pub trait Pattern: fmt::Debug + Send + Sync {
   fn pattern_at(&self, point: &Tuple) -> Color;
   fn pattern_at_shape_from_transform(
     &self,
-    object: Rc<dyn Shape>,
+    object: Arc<dyn Shape>,
     point: &Tuple,
-    pattern_transform: &Matrix,
+    pattern_transform: &Transform,
   ) -> Color {
-    let inverse_obj = object.get_transform().invert().unwrap();
-    let object_space_point = &inverse_obj * point;
-    let pattern_space_point = &pattern_transform.invert().unwrap() * &object_space_point;
+    let object_space_point = world_to_object(object.as_ref(), point);
+    let pattern_space_point = pattern_transform.inverse() * &object_space_point;
     self.pattern_at(&pattern_space_point)
   }
-  fn pattern_at_shape(&self, object: Rc<dyn Shape>, point: &Tuple) -> Color;
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color;
 }
 
 #[derive(Clone, Debug)]
 pub struct StripePattern {
   pub color_a: Color,
   pub color_b: Color,
-  pub transform: math::Matrix,
+  transform: Transform,
 }
 
 impl StripePattern {
@@ -36,16 +36,19 @@ impl StripePattern {
     Self {
       color_a,
       color_b,
-      transform: math::Matrix::new_identity_matrix(4),
+      transform: Transform::identity(),
     }
   }
-  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix) -> StripePattern {
+  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix4) -> StripePattern {
     Self {
       color_a,
       color_b,
-      transform: transform,
+      transform: Transform::new(transform),
     }
   }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
 }
 impl Pattern for StripePattern {
   fn pattern_at(&self, point: &Tuple) -> Color {
@@ -54,7 +57,7 @@ impl Pattern for StripePattern {
       _ => self.color_b.clone(),
     }
   }
-  fn pattern_at_shape(&self, object: Rc<dyn Shape>, point: &Tuple) -> Color {
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
     self.pattern_at_shape_from_transform(object, point, &self.transform)
   }
 }
@@ -63,23 +66,26 @@ impl Pattern for StripePattern {
 pub struct GradientPattern {
   pub color_a: Color,
   pub color_b: Color,
-  pub transform: math::Matrix,
+  transform: Transform,
 }
 impl GradientPattern {
   pub fn new(color_a: Color, color_b: Color) -> GradientPattern {
     Self {
       color_a,
       color_b,
-      transform: math::Matrix::new_identity_matrix(4),
+      transform: Transform::identity(),
     }
   }
-  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix) -> GradientPattern {
+  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix4) -> GradientPattern {
     Self {
       color_a,
       color_b,
-      transform: transform,
+      transform: Transform::new(transform),
     }
   }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
 }
 impl Pattern for GradientPattern {
   fn pattern_at(&self, point: &Tuple) -> Color {
@@ -87,7 +93,7 @@ impl Pattern for GradientPattern {
     let fraction = point.x - point.x.floor();
     self.color_a.clone() + &(distance * fraction)
   }
-  fn pattern_at_shape(&self, object: Rc<dyn Shape>, point: &Tuple) -> Color {
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
     self.pattern_at_shape_from_transform(object, point, &self.transform)
   }
 }
@@ -96,23 +102,26 @@ impl Pattern for GradientPattern {
 pub struct RingPattern {
   pub color_a: Color,
   pub color_b: Color,
-  pub transform: math::Matrix,
+  transform: Transform,
 }
 impl RingPattern {
   pub fn new(color_a: Color, color_b: Color) -> RingPattern {
     Self {
       color_a,
       color_b,
-      transform: math::Matrix::new_identity_matrix(4),
+      transform: Transform::identity(),
     }
   }
-  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix) -> RingPattern {
+  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix4) -> RingPattern {
     Self {
       color_a,
       color_b,
-      transform: transform,
+      transform: Transform::new(transform),
     }
   }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
 }
 
 impl Pattern for RingPattern {
@@ -123,7 +132,7 @@ impl Pattern for RingPattern {
       return self.color_b.clone();
     }
   }
-  fn pattern_at_shape(&self, object: Rc<dyn Shape>, point: &Tuple) -> Color {
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
     self.pattern_at_shape_from_transform(object, point, &self.transform)
   }
 }
@@ -132,23 +141,26 @@ impl Pattern for RingPattern {
 pub struct CheckersPattern {
   pub color_a: Color,
   pub color_b: Color,
-  pub transform: math::Matrix,
+  transform: Transform,
 }
 impl CheckersPattern {
   pub fn new(color_a: Color, color_b: Color) -> CheckersPattern {
     Self {
       color_a,
       color_b,
-      transform: math::Matrix::new_identity_matrix(4),
+      transform: Transform::identity(),
     }
   }
-  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix) -> CheckersPattern {
+  pub fn new_with_transform(color_a: Color, color_b: Color, transform: Matrix4) -> CheckersPattern {
     Self {
       color_a,
       color_b,
-      transform: transform,
+      transform: Transform::new(transform),
     }
   }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
 }
 
 impl Pattern for CheckersPattern {
@@ -159,14 +171,45 @@ impl Pattern for CheckersPattern {
       return self.color_b.clone();
     }
   }
-  fn pattern_at_shape(&self, object: Rc<dyn Shape>, point: &Tuple) -> Color {
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
+    self.pattern_at_shape_from_transform(object, point, &self.transform)
+  }
+}
+// A pattern that returns the point it is evaluated at as a color,
+// useful for checking which point a pattern is sampled at.
+#[cfg(test)]
+#[derive(Clone, Debug)]
+pub struct TestPattern {
+  transform: Transform,
+}
+#[cfg(test)]
+impl TestPattern {
+  pub fn new() -> TestPattern {
+    Self {
+      transform: Transform::identity(),
+    }
+  }
+  pub fn set_transform(&mut self, transform: Matrix4) {
+    self.transform = Transform::new(transform);
+  }
+}
+
+#[cfg(test)]
+impl Pattern for TestPattern {
+  fn pattern_at(&self, point: &Tuple) -> Color {
+    Color::new(point.x, point.y, point.z)
+  }
+  fn pattern_at_shape(&self, object: Arc<dyn Shape>, point: &Tuple) -> Color {
     self.pattern_at_shape_from_transform(object, point, &self.transform)
   }
 }
+
+#[cfg(test)]
 fn black() -> Color {
   Color::new(0.0, 0.0, 0.0)
 }
 
+#[cfg(test)]
 fn white() -> Color {
   Color::new(1.0, 1.0, 1.0)
 }
@@ -209,17 +252,17 @@ fn stripe_pattern_alternates_x() {
 #[test]
 fn stripe_with_object_transform() {
   let mut object = Sphere::new();
-  object.transform = Rc::new(Matrix::scale(2.0, 2.0, 2.0));
+  object.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
   let pattern = StripePattern::new(white(), black());
-  let c = pattern.pattern_at_shape(Rc::new(object), &Tuple::point(1.5, 0., 0.));
+  let c = pattern.pattern_at_shape(Arc::new(object), &Tuple::point(1.5, 0., 0.));
   assert_eq!(c, white());
 }
 
 #[test]
 fn stripe_with_pattern_transform() {
-  let object = Rc::new(Sphere::new());
+  let object = Arc::new(Sphere::new());
   let mut pattern = StripePattern::new(white(), black());
-  pattern.transform = Matrix::scale(2.0, 2.0, 2.0);
+  pattern.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
   let c = pattern.pattern_at_shape(object, &Tuple::point(1.5, 0., 0.));
   assert_eq!(c, white());
 }
@@ -227,10 +270,10 @@ fn stripe_with_pattern_transform() {
 #[test]
 fn stripe_with_object_and_pattern_transform() {
   let mut object = Sphere::new();
-  object.transform = Rc::new(Matrix::scale(2.0, 2.0, 2.0));
+  object.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
   let mut pattern = StripePattern::new(white(), black());
-  pattern.transform = Matrix::translation(0.5, 0., 0.);
-  let c = pattern.pattern_at_shape(Rc::new(object), &Tuple::point(2.5, 0., 0.));
+  pattern.set_transform(Matrix4::translation(0.5, 0., 0.));
+  let c = pattern.pattern_at_shape(Arc::new(object), &Tuple::point(2.5, 0., 0.));
   assert_eq!(c, white());
 }
 
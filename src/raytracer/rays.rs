@@ -1,9 +1,9 @@
-use super::geometry::normal_at;
-use crate::math::Matrix;
+use super::geometry::normal_at_hit;
+use crate::math::Matrix4;
 use crate::math::Tuple;
 use crate::raytracer::geometry::Shape;
 use crate::util;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 pub struct Ray {
@@ -25,7 +25,7 @@ impl Ray {
     &ray.origin + &(&ray.direction * t)
   }
 
-  pub fn transform(&self, m: &Matrix) -> Ray {
+  pub fn transform(&self, m: &Matrix4) -> Ray {
     Ray {
       origin: m * &self.origin,
       direction: m * &self.direction,
@@ -33,54 +33,136 @@ impl Ray {
   }
 
   pub fn precompute(i: &Intersection, r: &Ray) -> PreComputation {
+    Ray::precompute_with_intersections(i, r, std::slice::from_ref(i))
+  }
+
+  // Like precompute, but also determines the refractive indices on either side
+  // of the hit by walking the full, sorted list of intersections along the ray.
+  pub fn precompute_with_intersections(
+    i: &Intersection,
+    r: &Ray,
+    xs: &[Intersection],
+  ) -> PreComputation {
     let pos = Ray::position(r, i.t);
     let eye_vector = -r.direction.clone();
-    let mut normal_vector = normal_at(i.shape.clone(), &pos);
+    let mut normal_vector = normal_at_hit(i, &pos);
     let inside = Tuple::dot(&normal_vector, &eye_vector) < 0.;
     if inside {
       normal_vector = -normal_vector;
     }
     let reflectv = Tuple::reflect(&r.direction, &normal_vector);
+    let (n1, n2) = Ray::refractive_indices(i, xs);
     PreComputation {
       t: i.t,
       shape: i.shape.clone(),
       point: pos.clone(),
-      over_point: pos + &(&normal_vector * util::EPSILON),
+      over_point: &pos + &(&normal_vector * util::EPSILON),
+      under_point: pos - &(&normal_vector * util::EPSILON),
       eye_vector,
       normal_vector,
       inside,
       reflectv,
+      n1,
+      n2,
+    }
+  }
+
+  // Find the refractive index of the material being exited (n1) and entered (n2)
+  // at the hit. The containers list holds the shapes the ray is currently inside of,
+  // each intersection either enters or exits the shape it belongs to.
+  fn refractive_indices(hit: &Intersection, xs: &[Intersection]) -> (f64, f64) {
+    let mut containers: Vec<Arc<dyn Shape>> = Vec::new();
+    let mut n1 = 1.0;
+    let mut n2 = 1.0;
+    for i in xs {
+      let is_hit = i.shape.get_id() == hit.shape.get_id() && i.t == hit.t;
+      if is_hit {
+        if let Some(last) = containers.last() {
+          n1 = last.get_material().refractive_index;
+        }
+      }
+
+      match containers
+        .iter()
+        .position(|s| s.get_id() == i.shape.get_id())
+      {
+        Some(index) => {
+          containers.remove(index);
+        }
+        None => containers.push(i.shape.clone()),
+      }
+
+      if is_hit {
+        if let Some(last) = containers.last() {
+          n2 = last.get_material().refractive_index;
+        }
+        break;
+      }
     }
+    (n1, n2)
   }
 }
 pub struct PreComputation {
   pub t: f64,
-  pub shape: Rc<dyn Shape>,
+  pub shape: Arc<dyn Shape>,
   pub point: Tuple,
   pub over_point: Tuple,
+  pub under_point: Tuple,
   pub eye_vector: Tuple,
   pub normal_vector: Tuple,
   pub inside: bool,
   pub reflectv: Tuple,
+  pub n1: f64,
+  pub n2: f64,
+}
+
+impl PreComputation {
+  // Schlick's approximation of the Fresnel equations. Returns the fraction of
+  // light that is reflected at the hit, the rest is refracted.
+  pub fn schlick(&self) -> f64 {
+    let mut cos = Tuple::dot(&self.eye_vector, &self.normal_vector);
+    if self.n1 > self.n2 {
+      let n = self.n1 / self.n2;
+      let sin2_t = n * n * (1.0 - cos * cos);
+      if sin2_t > 1.0 {
+        // total internal reflection
+        return 1.0;
+      }
+      // when going from a dense to a less dense medium, use the angle of transmission
+      cos = (1.0 - sin2_t).sqrt();
+    }
+    let r0 = ((self.n1 - self.n2) / (self.n1 + self.n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct Intersection {
-  pub shape: Rc<dyn Shape>,
+  pub shape: Arc<dyn Shape>,
   pub t: f64,
+  // Where on the surface the hit is, for shapes that need it (e.g. triangles)
+  pub uv: Option<(f64, f64)>,
 }
 
 impl Intersection {
-  pub fn new(shape: &Rc<dyn Shape>, t: f64) -> Intersection {
+  pub fn new(shape: &Arc<dyn Shape>, t: f64) -> Intersection {
+    Intersection {
+      shape: Arc::clone(shape),
+      t,
+      uv: None,
+    }
+  }
+  pub fn new_with_uv(shape: &Arc<dyn Shape>, t: f64, u: f64, v: f64) -> Intersection {
     Intersection {
-      shape: Rc::clone(&shape),
+      shape: Arc::clone(shape),
       t,
+      uv: Some((u, v)),
     }
   }
   pub fn intersections(the_intersections: &[Intersection]) -> Vec<Intersection> {
     the_intersections.to_vec()
   }
-  pub fn hit(intersections: &mut Vec<Intersection>) -> Option<Intersection> {
+  pub fn hit(intersections: &mut [Intersection]) -> Option<Intersection> {
     intersections.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
     for intersection in intersections.iter() {
       if intersection.t > 0. {
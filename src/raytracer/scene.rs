@@ -0,0 +1,410 @@
+// Builds a world and camera from a YAML scene description in the format shared
+// by the Ray Tracer Challenge community, e.g.
+//
+//   - define: white-material
+//     value:
+//       color: [ 1, 1, 1 ]
+//       diffuse: 0.7
+//   - add: camera
+//     width: 100
+//     height: 50
+//     field-of-view: 0.785
+//     from: [ -6, 6, -10 ]
+//     to: [ 6, 0, 6 ]
+//     up: [ -0.45, 1, 0 ]
+//   - add: light
+//     at: [ 50, 100, -50 ]
+//     intensity: [ 1, 1, 1 ]
+//   - add: sphere
+//     material: white-material
+//     transform:
+//       - [ scale, 0.5, 0.5, 0.5 ]
+//       - [ translate, 0, 0.5, 0 ]
+//
+// Transforms are applied in the order they are listed.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::geometry::Shape;
+use super::patterns::{CheckersPattern, GradientPattern, Pattern, RingPattern, StripePattern};
+use super::yaml::{self, Node, Value, YamlError};
+use super::{Camera, Cone, Cube, Cylinder, Material, Plane, PointLight, Sphere, World};
+use crate::color::Color;
+use crate::math::{Matrix4, Tuple};
+
+// A world together with the camera to view it through, if the scene file
+// describes one
+pub struct Scene {
+  pub world: World,
+  pub camera: Option<Camera>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for SceneError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl From<YamlError> for SceneError {
+  fn from(e: YamlError) -> SceneError {
+    SceneError {
+      line: e.line,
+      message: e.message,
+    }
+  }
+}
+
+fn error<T>(line: usize, message: String) -> Result<T, SceneError> {
+  Err(SceneError { line, message })
+}
+
+impl Scene {
+  pub fn from_yaml(input: &str) -> Result<Scene, SceneError> {
+    let mut builder = Builder {
+      defines: HashMap::new(),
+      world: World::new(),
+      camera: None,
+    };
+    if let Some(document) = yaml::parse(input)? {
+      for item in document.as_list()? {
+        builder.add_item(item)?;
+      }
+    }
+    Ok(Scene {
+      world: builder.world,
+      camera: builder.camera,
+    })
+  }
+}
+
+struct Builder {
+  defines: HashMap<String, Node>,
+  world: World,
+  camera: Option<Camera>,
+}
+
+impl Builder {
+  fn add_item(&mut self, item: &Node) -> Result<(), SceneError> {
+    if let Some(name) = item.get("define") {
+      return self.define(item, name.as_str()?);
+    }
+    let kind = match item.get("add") {
+      Some(kind) => kind.as_str()?,
+      None => return error(item.line, "expected 'add' or 'define'".to_string()),
+    };
+    match kind {
+      "camera" => {
+        check_keys(
+          item,
+          &[
+            "add",
+            "width",
+            "height",
+            "field-of-view",
+            "from",
+            "to",
+            "up",
+          ],
+        )?;
+        if self.camera.is_some() {
+          return error(item.line, "the scene already has a camera".to_string());
+        }
+        let mut camera = Camera::new(
+          positive(required(item, "width")?)?,
+          positive(required(item, "height")?)?,
+          required(item, "field-of-view")?.as_f64()?,
+        );
+        let view = Camera::view_transform(
+          &point(required(item, "from")?)?,
+          &point(required(item, "to")?)?,
+          &vector(required(item, "up")?)?,
+        );
+        if !view.is_invertible() {
+          return error(
+            item.line,
+            "the camera must look from a different point than 'to', with 'up' not along the view"
+              .to_string(),
+          );
+        }
+        camera.set_transform(view);
+        self.camera = Some(camera);
+      }
+      "light" => {
+        check_keys(item, &["add", "at", "intensity"])?;
+        self.world.lights.push(Arc::new(PointLight::new(
+          &point(required(item, "at")?)?,
+          &color(required(item, "intensity")?)?,
+        )));
+      }
+      _ => {
+        let shape = self.shape(kind, item)?;
+        self.world.shapes.push(shape);
+      }
+    }
+    Ok(())
+  }
+
+  // Records a named value, which may extend an earlier definition by adding
+  // to or replacing its keys
+  fn define(&mut self, item: &Node, name: &str) -> Result<(), SceneError> {
+    check_keys(item, &["define", "extend", "value"])?;
+    let mut value = required(item, "value")?.clone();
+    if let Some(base_name) = item.get("extend") {
+      let base = self.lookup(base_name)?;
+      let mut entries = base.as_map()?.to_vec();
+      for (key, node) in value.as_map()? {
+        match entries.iter_mut().find(|(k, _)| k == key) {
+          Some(entry) => entry.1 = node.clone(),
+          None => entries.push((key.clone(), node.clone())),
+        }
+      }
+      value.value = Value::Map(entries);
+    }
+    self.defines.insert(name.to_string(), value);
+    Ok(())
+  }
+
+  fn lookup(&self, name: &Node) -> Result<&Node, SceneError> {
+    let text = name.as_str()?;
+    match self.defines.get(text) {
+      Some(node) => Ok(node),
+      None => error(name.line, format!("'{}' has not been defined", text)),
+    }
+  }
+
+  fn shape(&self, kind: &str, item: &Node) -> Result<Arc<dyn Shape>, SceneError> {
+    let keys: &[&str] = match kind {
+      "sphere" | "plane" | "cube" => &["add", "transform", "material"],
+      "cylinder" | "cone" => &["add", "transform", "material", "min", "max", "closed"],
+      _ => return error(item.line, format!("unknown object '{}'", kind)),
+    };
+    check_keys(item, keys)?;
+    let transform = match item.get("transform") {
+      Some(node) => self.transform(node, &mut Vec::new())?,
+      None => Matrix4::identity(),
+    };
+    let material = match item.get("material") {
+      Some(node) => self.material(node)?,
+      None => Material::new(),
+    };
+    let minimum = optional(item, "min", Node::as_f64, f64::NEG_INFINITY)?;
+    let maximum = optional(item, "max", Node::as_f64, f64::INFINITY)?;
+    let closed = optional(item, "closed", Node::as_bool, false)?;
+    let shape: Arc<dyn Shape> = match kind {
+      "sphere" => {
+        let mut shape = Sphere::new();
+        shape.set_transform(transform);
+        shape.set_material(material);
+        Arc::new(shape)
+      }
+      "plane" => {
+        let mut shape = Plane::new();
+        shape.set_transform(transform);
+        shape.set_material(material);
+        Arc::new(shape)
+      }
+      "cube" => {
+        let mut shape = Cube::new();
+        shape.set_transform(transform);
+        shape.set_material(material);
+        Arc::new(shape)
+      }
+      "cylinder" => {
+        let mut shape = Cylinder::new();
+        shape.set_transform(transform);
+        shape.set_material(material);
+        shape.set_minimum(minimum);
+        shape.set_maximum(maximum);
+        shape.closed = closed;
+        Arc::new(shape)
+      }
+      _ => {
+        let mut shape = Cone::new();
+        shape.set_transform(transform);
+        shape.set_material(material);
+        shape.set_minimum(minimum);
+        shape.set_maximum(maximum);
+        shape.closed = closed;
+        Arc::new(shape)
+      }
+    };
+    Ok(shape)
+  }
+
+  // A list of operations such as [ translate, 1, 2, 3 ], or names of defined
+  // lists, combined so that the first listed is applied first. `expanding`
+  // holds the definitions being expanded, to catch ones that refer to
+  // themselves.
+  fn transform<'a>(
+    &'a self,
+    node: &'a Node,
+    expanding: &mut Vec<&'a str>,
+  ) -> Result<Matrix4, SceneError> {
+    let mut transform = Matrix4::identity();
+    for step in node.as_list()? {
+      let matrix = match &step.value {
+        Value::Scalar(name) => {
+          if expanding.contains(&name.as_str()) {
+            return error(step.line, format!("'{}' refers to itself", name));
+          }
+          let definition = self.lookup(step)?;
+          expanding.push(name);
+          let matrix = self.transform(definition, expanding)?;
+          expanding.pop();
+          matrix
+        }
+        _ => operation(step)?,
+      };
+      transform = matrix * transform;
+      // Shapes, patterns and cameras need the inverse, which a scale by zero
+      // or a shear onto another axis does not have
+      if !transform.is_invertible() {
+        return error(step.line, "transformation is not invertible".to_string());
+      }
+    }
+    Ok(transform)
+  }
+
+  fn material(&self, node: &Node) -> Result<Material, SceneError> {
+    let node = match node.value {
+      Value::Scalar(_) => self.lookup(node)?,
+      _ => node,
+    };
+    let mut material = Material::new();
+    for (key, value) in node.as_map()? {
+      match key.as_str() {
+        "color" => material.color = color(value)?,
+        "ambient" => material.ambient = value.as_f64()?,
+        "diffuse" => material.diffuse = value.as_f64()?,
+        "specular" => material.specular = value.as_f64()?,
+        "shininess" => material.shininess = value.as_f64()?,
+        "reflective" => material.reflective = value.as_f64()?,
+        "transparency" => material.transparency = value.as_f64()?,
+        "refractive-index" => material.refractive_index = value.as_f64()?,
+        "pattern" => material.pattern = Some(self.pattern(value)?),
+        _ => return error(value.line, format!("unknown material property '{}'", key)),
+      }
+    }
+    Ok(material)
+  }
+
+  fn pattern(&self, node: &Node) -> Result<Arc<dyn Pattern>, SceneError> {
+    check_keys(node, &["type", "colors", "transform"])?;
+    let colors = required(node, "colors")?;
+    let (a, b) = match colors.as_list()? {
+      [a, b] => (color(a)?, color(b)?),
+      _ => return error(colors.line, "a pattern needs two colors".to_string()),
+    };
+    let transform = match node.get("transform") {
+      Some(node) => self.transform(node, &mut Vec::new())?,
+      None => Matrix4::identity(),
+    };
+    let kind = required(node, "type")?;
+    let pattern: Arc<dyn Pattern> = match kind.as_str()? {
+      "stripes" => Arc::new(StripePattern::new_with_transform(a, b, transform)),
+      "gradient" => Arc::new(GradientPattern::new_with_transform(a, b, transform)),
+      "rings" => Arc::new(RingPattern::new_with_transform(a, b, transform)),
+      "checkers" => Arc::new(CheckersPattern::new_with_transform(a, b, transform)),
+      other => return error(kind.line, format!("unknown pattern type '{}'", other)),
+    };
+    Ok(pattern)
+  }
+}
+
+// A single transformation, written as its name followed by its arguments
+fn operation(step: &Node) -> Result<Matrix4, SceneError> {
+  let parts = step.as_list()?;
+  let name = match parts.first() {
+    Some(name) => name.as_str()?,
+    None => return error(step.line, "empty transformation".to_string()),
+  };
+  let expected = match name {
+    "translate" | "scale" => 3,
+    "rotate-x" | "rotate-y" | "rotate-z" => 1,
+    "shear" => 6,
+    _ => return error(step.line, format!("unknown transformation '{}'", name)),
+  };
+  if parts.len() != expected + 1 {
+    return error(
+      step.line,
+      format!(
+        "{} takes {} numbers, found {}",
+        name,
+        expected,
+        parts.len() - 1
+      ),
+    );
+  }
+  let n = parts[1..]
+    .iter()
+    .map(|part| part.as_f64())
+    .collect::<Result<Vec<f64>, YamlError>>()?;
+  Ok(match name {
+    "translate" => Matrix4::translation(n[0], n[1], n[2]),
+    "scale" => Matrix4::scale(n[0], n[1], n[2]),
+    "rotate-x" => Matrix4::rotation_x(n[0]),
+    "rotate-y" => Matrix4::rotation_y(n[0]),
+    "rotate-z" => Matrix4::rotation_z(n[0]),
+    _ => Matrix4::shear(n[0], n[1], n[2], n[3], n[4], n[5]),
+  })
+}
+
+fn check_keys(node: &Node, allowed: &[&str]) -> Result<(), SceneError> {
+  for (key, value) in node.as_map()? {
+    if !allowed.contains(&key.as_str()) {
+      return error(value.line, format!("unexpected key '{}'", key));
+    }
+  }
+  Ok(())
+}
+
+fn required<'a>(node: &'a Node, key: &str) -> Result<&'a Node, SceneError> {
+  match node.get(key) {
+    Some(value) => Ok(value),
+    None => error(node.line, format!("missing '{}'", key)),
+  }
+}
+
+fn optional<T>(
+  node: &Node,
+  key: &str,
+  read: fn(&Node) -> Result<T, YamlError>,
+  default: T,
+) -> Result<T, SceneError> {
+  match node.get(key) {
+    Some(value) => Ok(read(value)?),
+    None => Ok(default),
+  }
+}
+
+fn positive(node: &Node) -> Result<usize, SceneError> {
+  match node.as_usize()? {
+    0 => error(
+      node.line,
+      "expected a whole number above 0, found '0'".to_string(),
+    ),
+    n => Ok(n),
+  }
+}
+
+fn point(node: &Node) -> Result<Tuple, SceneError> {
+  let c = node.as_numbers(3)?;
+  Ok(Tuple::point(c[0], c[1], c[2]))
+}
+
+fn vector(node: &Node) -> Result<Tuple, SceneError> {
+  let c = node.as_numbers(3)?;
+  Ok(Tuple::vector(c[0], c[1], c[2]))
+}
+
+fn color(node: &Node) -> Result<Color, SceneError> {
+  let c = node.as_numbers(3)?;
+  Ok(Color::new(c[0], c[1], c[2]))
+}
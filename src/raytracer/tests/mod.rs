@@ -2,19 +2,34 @@ use super::Intersection;
 use super::Ray;
 use super::Sphere;
 
-use std::{f64::consts, rc::Rc};
+use std::{f64::consts, sync::Arc};
 
-use super::geometry::normal_at;
-use super::lights::PointLight;
+use super::bounds::{parent_space_bounds, BoundingBox};
+use super::bvh::Bvh;
+use super::geometry::{normal_at, normal_at_hit, normal_to_world, world_to_object};
+use super::lights::{AreaLight, DirectionalLight, Light, PointLight, SpotLight};
 use super::materials::Material;
+use super::camera::{AdaptiveSampling, Projection};
 use super::Camera;
 use super::World;
 use crate::color::Color;
-use crate::math::Matrix;
+use crate::math::Matrix4;
 use crate::math::Tuple;
+use crate::raytracer::geometry::Cone;
+use crate::raytracer::geometry::Cube;
+use crate::raytracer::geometry::Csg;
+use crate::raytracer::geometry::CsgOperation;
+use crate::raytracer::geometry::Cylinder;
+use crate::raytracer::geometry::Group;
 use crate::raytracer::geometry::Plane;
 use crate::raytracer::geometry::Shape;
+use crate::raytracer::geometry::SmoothTriangle;
 use crate::raytracer::geometry::TestShape;
+use crate::raytracer::geometry::Triangle;
+use crate::raytracer::obj::ObjParser;
+use crate::raytracer::patterns::{Pattern, TestPattern};
+use crate::raytracer::scene::{Scene, SceneError};
+use crate::raytracer::yaml::{self, Node, Value};
 use crate::util;
 
 #[test]
@@ -39,7 +54,7 @@ fn computing_point_from_distance() {
 #[test]
 fn ray_intersects_sphere_at_two_points() {
   let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let xs = s.intersect(&r);
   assert_eq!(xs.len(), 2);
   assert!(util::equal(xs[0].t, 4.0));
@@ -48,7 +63,7 @@ fn ray_intersects_sphere_at_two_points() {
 #[test]
 fn ray_intersects_sphere_at_tangent() {
   let r = Ray::new(&Tuple::point(0., 1., -5.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let xs = s.intersect(&r);
   assert_eq!(xs.len(), 2);
   // return two points even if tangential!
@@ -59,7 +74,7 @@ fn ray_intersects_sphere_at_tangent() {
 #[test]
 fn ray_misses_sphere() {
   let r = Ray::new(&Tuple::point(0., 2., -5.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let xs = s.intersect(&r);
   assert_eq!(xs.len(), 0);
 }
@@ -67,7 +82,7 @@ fn ray_misses_sphere() {
 #[test]
 fn ray_originates_within_sphere() {
   let r = Ray::new(&Tuple::point(0., 0., 0.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let xs = s.intersect(&r);
   assert!(util::equal(xs[0].t, -1.0));
   assert!(util::equal(xs[1].t, 1.0))
@@ -76,7 +91,7 @@ fn ray_originates_within_sphere() {
 #[test]
 fn sphere_is_behind_ray() {
   let r = Ray::new(&Tuple::point(0., 0., 5.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let xs = s.intersect(&r);
   assert!(util::equal(xs[0].t, -6.0));
   assert!(util::equal(xs[1].t, -4.0))
@@ -84,14 +99,14 @@ fn sphere_is_behind_ray() {
 
 #[test]
 fn intersection_has_t_and_object() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i = Intersection::new(&s, 3.5);
   assert_eq!(s.get_id(), i.shape.get_id());
 }
 
 #[test]
 fn aggregate_intersections() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i1 = Intersection::new(&s, 1.);
   let i2 = Intersection::new(&s, 2.);
   let xs = Intersection::intersections(&[i1, i2]);
@@ -103,23 +118,36 @@ fn aggregate_intersections() {
 #[test]
 fn intersect_sets_intersected_object() {
   let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
-  let xs = s.intersect(&r);
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let xs = s.clone().intersect(&r);
 
   assert_eq!(xs[0].shape.get_id(), s.get_id());
   assert_eq!(xs[1].shape.get_id(), s.get_id());
 }
 
+#[test]
+fn intersections_share_the_intersected_shape() {
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let s = Arc::new(Sphere::new());
+  let xs = s.clone().intersect(&r);
+
+  // both intersections refer to the sphere itself rather than to copies of it
+  assert_eq!(xs.len(), 2);
+  assert_eq!(Arc::strong_count(&s), 3);
+}
+
 #[test]
 fn the_hit_when_all_intersections_have_positive_t() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i1 = Intersection {
     t: 1.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i2 = Intersection {
     t: 2.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i = match Intersection::hit(&mut Intersection::intersections(&[i1.clone(), i2])) {
     Some(an_i) => an_i,
@@ -130,14 +158,16 @@ fn the_hit_when_all_intersections_have_positive_t() {
 
 #[test]
 fn the_hit_when_domr_intersections_have_negative_t() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i1 = Intersection {
     t: -1.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i2 = Intersection {
     t: 2.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i = match Intersection::hit(&mut Intersection::intersections(&[i1, i2.clone()])) {
     Some(an_i) => an_i,
@@ -149,14 +179,16 @@ fn the_hit_when_domr_intersections_have_negative_t() {
 #[test]
 #[should_panic]
 fn the_hit_when_all_intersections_have_negative_t() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i1 = Intersection {
     t: -2.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i2 = Intersection {
     t: -1.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   match Intersection::hit(&mut Intersection::intersections(&[i1, i2])) {
     Some(an_i) => an_i,
@@ -166,22 +198,26 @@ fn the_hit_when_all_intersections_have_negative_t() {
 
 #[test]
 fn the_hit_is_the_lowest_nonnegative_intersection() {
-  let s: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i1 = Intersection {
     t: 5.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i2 = Intersection {
     t: 7.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i3 = Intersection {
     t: -3.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i4 = Intersection {
     t: 2.,
-    shape: Rc::clone(&s),
+    shape: Arc::clone(&s),
+    uv: None,
   };
   let i = match Intersection::hit(&mut Intersection::intersections(&[i1, i2, i3, i4.clone()])) {
     Some(an_i) => an_i,
@@ -193,7 +229,7 @@ fn the_hit_is_the_lowest_nonnegative_intersection() {
 #[test]
 pub fn translate_ray() {
   let r = Ray::new(&Tuple::point(1., 2., 3.), &Tuple::vector(0., 1., 0.));
-  let m = Matrix::translation(3., 4., 5.);
+  let m = Matrix4::translation(3., 4., 5.);
   let r2 = r.transform(&m);
 
   assert!(r2.origin.is_equal(&Tuple::point(4., 6., 8.)));
@@ -203,7 +239,7 @@ pub fn translate_ray() {
 #[test]
 pub fn scale_ray() {
   let r = Ray::new(&Tuple::point(1., 2., 3.), &Tuple::vector(0., 1., 0.));
-  let m = Matrix::scale(2., 3., 4.);
+  let m = Matrix4::scale(2., 3., 4.);
   let r2 = r.transform(&m);
   assert!(r2.origin.is_equal(&Tuple::point(2., 6., 12.)));
   assert!(r2.direction.is_equal(&Tuple::vector(0., 3., 0.)));
@@ -212,24 +248,24 @@ pub fn scale_ray() {
 #[test]
 pub fn sphere_default_transform() {
   let s = Sphere::new();
-  assert_eq!(*s.transform, Matrix::new_identity_matrix(4))
+  assert_eq!(*s.get_transform(), Matrix4::identity())
 }
 
 #[test]
 pub fn change_sphere_transform() {
   let mut s = Sphere::new();
-  let t = Matrix::translation(2., 3., 4.);
-  s.transform = Rc::new(t.clone());
+  let t = Matrix4::translation(2., 3., 4.);
+  s.set_transform(t);
 
-  assert_eq!(*s.transform, t);
+  assert_eq!(*s.get_transform(), t);
 }
 // TODO: consider change ray arguments to two tuples (Rust type)
 #[test]
 pub fn intersect_scaled_sphere_with_ray() {
   let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
   let mut s = Sphere::new();
-  s.transform = Rc::new(Matrix::scale(2., 2., 2.));
-  let s: Rc<dyn Shape> = Rc::new(s);
+  s.set_transform(Matrix4::scale(2., 2., 2.));
+  let s: Arc<dyn Shape> = Arc::new(s);
   let xs = s.intersect(&r);
   assert_eq!(xs.len(), 2);
   assert!(util::equal(xs[0].t, 3.0));
@@ -239,8 +275,8 @@ pub fn intersect_scaled_sphere_with_ray() {
 pub fn intersect_translated_sphere_with_ray() {
   let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
   let mut s = Sphere::new();
-  s.transform = Rc::new(Matrix::translation(5., 0., 0.));
-  let s: Rc<dyn Shape> = Rc::new(s);
+  s.set_transform(Matrix4::translation(5., 0., 0.));
+  let s: Arc<dyn Shape> = Arc::new(s);
   let xs = s.intersect(&r);
   assert_eq!(xs.len(), 0);
 }
@@ -298,17 +334,17 @@ pub fn normal_is_normalized() {
 #[test]
 pub fn compute_normal_on_translated_sphere() {
   let mut s = Sphere::new();
-  s.transform = Rc::new(Matrix::translation(0., 1., 0.));
-  let n = normal_at(Rc::new(s), &Tuple::point(0., 1.70711, -0.70711));
+  s.set_transform(Matrix4::translation(0., 1., 0.));
+  let n = normal_at(Arc::new(s), &Tuple::point(0., 1.70711, -0.70711));
   assert_eq!(n, Tuple::vector(0., 0.70711, -0.70711));
 }
 
 #[test]
 pub fn compute_normal_on_transformed_sphere() {
   let mut s = Sphere::new();
-  s.transform = Rc::new(Matrix::scale(1., 0.5, 1.) * Matrix::rotation_z(consts::PI / 5.));
+  s.set_transform(Matrix4::scale(1., 0.5, 1.) * Matrix4::rotation_z(consts::PI / 5.));
   let n = normal_at(
-    Rc::new(s),
+    Arc::new(s),
     &Tuple::point(0., (2. as f64).sqrt() / 2., -(2. as f64).sqrt() / 2.),
   );
   assert_eq!(n, Tuple::vector(0., 0.97014, -0.24254));
@@ -338,16 +374,16 @@ pub fn default_material() {
 #[test]
 pub fn sphere_has_default_material() {
   let s = Sphere::new();
-  assert_eq!(Rc::try_unwrap(s.material), Ok(Material::new()));
+  assert_eq!(*s.get_material(), Material::new());
 }
 
 #[test]
 pub fn sphere_can_be_assigned_material() {
   let mut s = Sphere::new();
-  let mut m = Rc::new(Material::new());
-  Rc::get_mut(&mut m).unwrap().ambient = 1.;
-  s.material = m.clone();
-  assert_eq!(s.material, m);
+  let mut m = Material::new();
+  m.ambient = 1.;
+  s.set_material(m);
+  assert_eq!(s.get_material().ambient, 1.);
 }
 
 /// Light source
@@ -361,12 +397,12 @@ pub fn lighting_with_eye_between_light_and_surface() {
 
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &position,
     &eye_vector,
     &normal_vector,
-    false,
+    1.0,
   );
   assert_eq!(&Color::new(1.9, 1.9, 1.9), &result);
 }
@@ -380,12 +416,12 @@ pub fn lighting_with_eye_between_light_and_surface_eye_offset_45() {
 
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &position,
     &eye_vector,
     &normal_vector,
-    false,
+    1.0,
   );
   assert_eq!(&Color::new(1.0, 1.0, 1.0), &result);
 }
@@ -399,12 +435,12 @@ pub fn lighting_with_eye_opposite_surface_light_offset_45() {
 
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &position,
     &eye_vector,
     &normal_vector,
-    false,
+    1.0,
   );
   assert_eq!(&Color::new(0.7364, 0.7364, 0.7364), &result);
 }
@@ -418,12 +454,12 @@ pub fn lighting_with_eye_in_path_of_reflection_vector() {
 
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &position,
     &eye_vector,
     &normal_vector,
-    false,
+    1.0,
   );
   assert_eq!(&Color::new(1.6364, 1.6364, 1.6364), &result);
 }
@@ -438,12 +474,12 @@ pub fn lighting_with_light_behind_surface() {
 
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &position,
     &eye_vector,
     &normal_vector,
-    false,
+    1.0,
   );
   assert_eq!(&Color::new(0.1, 0.1, 0.1), &result);
 }
@@ -456,7 +492,7 @@ pub fn create_world() {
   assert_eq!(w.lights.len(), 0);
 }
 
-fn are_shapes_equivalent(s1: &Rc<dyn Shape>, s2: &Rc<dyn Shape>) -> bool {
+fn are_shapes_equivalent(s1: &Arc<dyn Shape>, s2: &Arc<dyn Shape>) -> bool {
   return s1.get_material() == s2.get_material() && s1.get_transform() == s2.get_transform();
 }
 #[test]
@@ -467,13 +503,20 @@ pub fn default_world() {
   mat.color = Color::new(0.8, 1.0, 0.6);
   mat.diffuse = 0.7;
   mat.specular = 0.2;
-  s1.material = Rc::new(mat);
-  let s1: Rc<dyn Shape> = Rc::new(s1);
+  s1.set_material(mat);
+  let s1: Arc<dyn Shape> = Arc::new(s1);
   let mut s2 = Sphere::new();
-  s2.transform = Rc::new(Matrix::scale(0.5, 0.5, 0.5));
-  let s2: Rc<dyn Shape> = Rc::new(s2);
+  s2.set_transform(Matrix4::scale(0.5, 0.5, 0.5));
+  let s2: Arc<dyn Shape> = Arc::new(s2);
   let w = World::default();
-  assert_eq!(w.lights, vec![light]);
+  assert_eq!(w.lights.len(), 1);
+  let origin = Tuple::point(0., 0., 0.);
+  assert_eq!(
+    w.lights[0].direction_to_light(&origin),
+    light.direction_to_light(&origin)
+  );
+  assert!(util::equal(w.lights[0].distance(&origin), light.distance(&origin)));
+  assert_eq!(w.lights[0].intensity(), light.intensity);
   assert!(are_shapes_equivalent(&w.shapes[0], &s1));
   assert!(are_shapes_equivalent(&w.shapes[1], &s2));
 }
@@ -492,7 +535,7 @@ pub fn intersect_world_ray() {
 #[test]
 pub fn precompute_intersect() {
   let ray = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
-  let shape: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let shape: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i = Intersection::new(&shape, 4.0);
   let comps = Ray::precompute(&i, &ray);
   assert!(util::equal(comps.t, i.t));
@@ -504,7 +547,7 @@ pub fn precompute_intersect() {
 #[test]
 pub fn precompute_outside() {
   let ray = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
-  let shape: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let shape: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i = Intersection::new(&shape, 4.0);
   let comps = Ray::precompute(&i, &ray);
   assert!(!comps.inside);
@@ -513,7 +556,7 @@ pub fn precompute_outside() {
 #[test]
 pub fn precompute_inside() {
   let ray = Ray::new(&Tuple::point(0., 0., 0.), &Tuple::vector(0., 0., 1.));
-  let shape: Rc<dyn Shape> = Rc::new(Sphere::new());
+  let shape: Arc<dyn Shape> = Arc::new(Sphere::new());
   let i = Intersection::new(&shape, 1.0);
 
   let comps = Ray::precompute(&i, &ray);
@@ -534,13 +577,31 @@ pub fn shade_intersection() {
   assert_eq!(c, Color::new(0.38066, 0.47583, 0.2855));
 }
 
+#[test]
+fn shade_hit_with_single_cell_area_light_matches_point_light() {
+  let mut w = World::default();
+  w.lights = vec![Arc::new(AreaLight::new(
+    &Tuple::point(-10., 10., -10.),
+    &Tuple::vector(0., 0., 0.),
+    1,
+    &Tuple::vector(0., 0., 0.),
+    1,
+    &Color::new(1., 1., 1.),
+  ))];
+  let ray = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let i = Intersection::new(&w.shapes[0], 4.0);
+  let comps = Ray::precompute(&i, &ray);
+  let c = w.shade_hit(&comps, 4);
+  assert_eq!(c, Color::new(0.38066, 0.47583, 0.2855));
+}
+
 #[test]
 pub fn shade_intersection_from_inside() {
   let mut w = World::default();
-  w.lights = vec![PointLight::new(
+  w.lights = vec![Arc::new(PointLight::new(
     &Tuple::point(0., 0.25, 0.),
     &Color::new(1., 1., 1.),
-  )];
+  ))];
   let ray = Ray::new(&Tuple::point(0., 0., 0.), &Tuple::vector(0., 0., 1.));
   let shape = &w.shapes[1];
   let i = Intersection::new(shape, 0.5);
@@ -553,14 +614,14 @@ pub fn shade_intersection_from_inside() {
 #[test]
 pub fn shade_intersection_in_shadow() {
   let mut w = World::new();
-  w.lights = vec![PointLight::new(
+  w.lights = vec![Arc::new(PointLight::new(
     &Tuple::point(0., 0., -10.),
     &Color::new(1., 1., 1.),
-  )];
+  ))];
   let s1 = Sphere::new();
   let mut s2 = Sphere::new();
-  s2.transform = Rc::new(Matrix::translation(0., 0., 10.));
-  w.shapes = vec![Rc::new(s1), Rc::new(s2)];
+  s2.set_transform(Matrix4::translation(0., 0., 10.));
+  w.shapes = vec![Arc::new(s1), Arc::new(s2)];
 
   let ray = Ray::new(&Tuple::point(0., 0., 5.), &Tuple::vector(0., 0., 1.));
   //let shape = &w.shapes[1];
@@ -611,7 +672,7 @@ pub fn transform_matrix_default_orientation() {
 
   let t = Camera::view_transform(&from, &to, &up);
 
-  assert_eq!(&Matrix::new_identity_matrix(4), &t);
+  assert_eq!(&Matrix4::identity(), &t);
 }
 
 #[test]
@@ -622,7 +683,7 @@ pub fn transform_matrix_positive_z_direction() {
 
   let t = Camera::view_transform(&from, &to, &up);
 
-  assert_eq!(&Matrix::scale(-1., 1., -1.), &t);
+  assert_eq!(&Matrix4::scale(-1., 1., -1.), &t);
 }
 
 #[test]
@@ -633,7 +694,7 @@ pub fn view_transform_moves_he_world() {
 
   let t = Camera::view_transform(&from, &to, &up);
 
-  assert_eq!(&Matrix::translation(0., 0., -8.), &t);
+  assert_eq!(&Matrix4::translation(0., 0., -8.), &t);
 }
 
 #[test]
@@ -643,11 +704,11 @@ pub fn transform_matrix_arbitrary() {
   let up = Tuple::vector(1., 1., 0.);
 
   let t = Camera::view_transform(&from, &to, &up);
-  let m: Matrix = Matrix::new(&[
-    &[-0.50709, 0.50709, 0.67612, -2.36643],
-    &[0.76772, 0.60609, 0.12122, -2.82843],
-    &[-0.35857, 0.59761, -0.71714, 0.00000],
-    &[0.00000, 0.00000, 0.00000, 1.00000],
+  let m: Matrix4 = Matrix4::new([
+    [-0.50709, 0.50709, 0.67612, -2.36643],
+    [0.76772, 0.60609, 0.12122, -2.82843],
+    [-0.35857, 0.59761, -0.71714, 0.00000],
+    [0.00000, 0.00000, 0.00000, 1.00000],
   ]);
   assert_eq!(&m, &t);
 }
@@ -663,7 +724,7 @@ pub fn construct_camera() {
   assert_eq!(c.hsize, hsize);
   assert_eq!(c.vsize, vsize);
   assert!(util::equal(c.field_of_view, consts::PI / 2.));
-  assert_eq!(&c.transform, &Matrix::new_identity_matrix(4));
+  assert_eq!(c.get_transform(), &Matrix4::identity());
 }
 
 #[test]
@@ -701,7 +762,7 @@ pub fn construct_ray_through_corner_of_canvas() {
 #[test]
 pub fn construct_ray_when_camera_is_transformed() {
   let mut c = Camera::new(201, 101, consts::PI / 2.);
-  c.transform = Matrix::rotation_y(consts::PI / 4.) * Matrix::translation(0., -2., 5.);
+  c.set_transform(Matrix4::rotation_y(consts::PI / 4.) * Matrix4::translation(0., -2., 5.));
   let r = c.ray_for_pixel(100, 50);
   assert_eq!(&r.origin, &Tuple::point(0., 2., -5.));
   assert_eq!(
@@ -717,7 +778,7 @@ pub fn render_world_with_camera() {
   let from = Tuple::point(0., 0., -5.);
   let to = Tuple::point(0., 0., 0.);
   let up = Tuple::vector(0., 1., 0.);
-  c.transform = Camera::view_transform(&from, &to, &up);
+  c.set_transform(Camera::view_transform(&from, &to, &up));
 
   let canvas = c.render(&w);
 
@@ -731,17 +792,17 @@ pub fn light_surface_in_shadow() {
   let normal_vec = Tuple::vector(0., 0., -1.);
 
   let light = PointLight::new(&Tuple::point(0., 0., -10.), &Color::new(1., 1., 1.));
-  let in_shadow = true;
+  let light_intensity = 0.0;
   let m = Material::new();
   let pos = Tuple::point(0., 0., 0.);
   let result = Material::lighting(
     &m,
-    Rc::new(Sphere::new()),
+    Arc::new(Sphere::new()),
     &light,
     &pos,
     &eye_vec,
     &normal_vec,
-    in_shadow,
+    light_intensity,
   );
   assert_eq!(&result, &Color::new(0.1, 0.1, 0.1));
 }
@@ -751,7 +812,7 @@ pub fn no_shadow_when_nothing_is_collinear_with_point_and_light() {
   let w = World::default();
   let p = Tuple::point(0., 10.0, 0.);
 
-  assert!(!w.is_shadowed(&p));
+  assert!(!w.is_shadowed(&p, &Tuple::point(-10., 10., -10.)));
 }
 
 #[test]
@@ -759,7 +820,7 @@ pub fn shadow_when_object_is_between_point_and_light() {
   let w = World::default();
   let p = Tuple::point(10., -10.0, 10.);
 
-  assert!(w.is_shadowed(&p));
+  assert!(w.is_shadowed(&p, &Tuple::point(-10., 10., -10.)));
 }
 
 #[test]
@@ -767,7 +828,7 @@ pub fn no_shadow_when_object_behind_light() {
   let w = World::default();
   let p = Tuple::point(-20., 20.0, -20.);
 
-  assert!(!w.is_shadowed(&p));
+  assert!(!w.is_shadowed(&p, &Tuple::point(-10., 10., -10.)));
 }
 
 #[test]
@@ -775,7 +836,7 @@ pub fn no_shadow_when_object_behind_point() {
   let w = World::default();
   let p = Tuple::point(-2., 2.0, -2.);
 
-  assert!(!w.is_shadowed(&p));
+  assert!(!w.is_shadowed(&p, &Tuple::point(-10., 10., -10.)));
 }
 
 #[test]
@@ -786,8 +847,8 @@ pub fn reflected_color_for_nonreflective_material() {
   let mut s2 = Sphere::new();
   let mut mat = Material::new();
   mat.ambient = 1.0;
-  s2.material = Rc::new(mat);
-  w.shapes = vec![Rc::new(s1), Rc::new(s2)];
+  s2.set_material(mat);
+  w.shapes = vec![Arc::new(s1), Arc::new(s2)];
 
   let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
   let i = Intersection::new(&w.shapes[1], 1.0);
@@ -800,12 +861,12 @@ pub fn reflected_color_for_nonreflective_material() {
 pub fn reflected_color_for_reflective_material() {
   let mut w = World::default();
   let mut plane = Plane::new();
-  plane.transform = Rc::new(Matrix::translation(0.0, -1.0, 0.));
+  plane.set_transform(Matrix4::translation(0.0, -1.0, 0.));
 
   let mut mat = Material::new();
   mat.reflective = 0.5;
-  plane.material = Rc::new(mat);
-  w.shapes.push(Rc::new(plane));
+  plane.set_material(mat);
+  w.shapes.push(Arc::new(plane));
 
   let r = Ray::new(
     &Tuple::point(0.0, 0.0, -3.0),
@@ -822,8 +883,8 @@ pub fn reflected_color_for_reflective_material() {
 pub fn hit_should_offset_point() {
   let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
   let mut s = Sphere::new();
-  s.transform = Rc::new(Matrix::translation(0., 0., 1.));
-  let s_s: Rc<dyn Shape> = Rc::new(s);
+  s.set_transform(Matrix4::translation(0., 0., 1.));
+  let s_s: Arc<dyn Shape> = Arc::new(s);
   let i = Intersection::new(&s_s, 5.0);
   let comps = Ray::precompute(&i, &r);
   assert!(comps.over_point.z < -util::EPSILON / 2.);
@@ -837,7 +898,7 @@ fn precompute_reflect_vector() {
     &Tuple::point(0.0, 1.0, -1.0),
     &Tuple::vector(0.0, -(2.0 as f64).sqrt() / 2.0, (2.0 as f64).sqrt() / 2.0),
   );
-  let i = Intersection::new(&(Rc::new(s) as Rc<dyn Shape>), (2.0 as f64).sqrt());
+  let i = Intersection::new(&(Arc::new(s) as Arc<dyn Shape>), (2.0 as f64).sqrt());
   let c = Ray::precompute(&i, &r);
   assert_eq!(
     c.reflectv,
@@ -849,12 +910,12 @@ fn precompute_reflect_vector() {
 fn shade_hit_reflective_material() {
   let mut w = World::default();
   let mut plane = Plane::new();
-  plane.transform = Rc::new(Matrix::translation(0.0, -1.0, 0.));
+  plane.set_transform(Matrix4::translation(0.0, -1.0, 0.));
 
   let mut mat = Material::new();
   mat.reflective = 0.5;
-  plane.material = Rc::new(mat);
-  w.shapes.push(Rc::new(plane));
+  plane.set_material(mat);
+  w.shapes.push(Arc::new(plane));
 
   let r = Ray::new(
     &Tuple::point(0.0, 0.0, -3.0),
@@ -870,24 +931,24 @@ fn shade_hit_reflective_material() {
 #[test]
 fn color_at_with_mutually_reflective_surfaces() {
   let mut w = World::new();
-  w.lights = vec![PointLight::new(
+  w.lights = vec![Arc::new(PointLight::new(
     &Tuple::point(0., 0., 0.),
     &Color::new(1., 1., 1.),
-  )];
+  ))];
 
   let mut plane = Plane::new();
-  plane.transform = Rc::new(Matrix::translation(0.0, -1.0, 0.));
+  plane.set_transform(Matrix4::translation(0.0, -1.0, 0.));
   let mut mat = Material::new();
   mat.reflective = 1.0;
-  plane.material = Rc::new(mat);
-  w.shapes.push(Rc::new(plane));
+  plane.set_material(mat);
+  w.shapes.push(Arc::new(plane));
 
   let mut plane = Plane::new();
-  plane.transform = Rc::new(Matrix::translation(0.0, 1.0, 0.));
+  plane.set_transform(Matrix4::translation(0.0, 1.0, 0.));
   let mut mat = Material::new();
   mat.reflective = 1.0;
-  plane.material = Rc::new(mat);
-  w.shapes.push(Rc::new(plane));
+  plane.set_material(mat);
+  w.shapes.push(Arc::new(plane));
 
   let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 1., 0.));
   let c = w.color_at(&r, 4);
@@ -898,12 +959,12 @@ fn color_at_with_mutually_reflective_surfaces() {
 fn reflected_color_at_max_recursive_depth() {
   let mut w = World::default();
   let mut plane = Plane::new();
-  plane.transform = Rc::new(Matrix::translation(0.0, -1.0, 0.));
+  plane.set_transform(Matrix4::translation(0.0, -1.0, 0.));
 
   let mut mat = Material::new();
   mat.reflective = 0.5;
-  plane.material = Rc::new(mat);
-  w.shapes.push(Rc::new(plane));
+  plane.set_material(mat);
+  w.shapes.push(Arc::new(plane));
 
   let r = Ray::new(
     &Tuple::point(0.0, 0.0, -3.0),
@@ -916,17 +977,259 @@ fn reflected_color_at_max_recursive_depth() {
   assert_eq!(c, Color::new(0., 0., 0.));
 }
 
+fn glass_material() -> Material {
+  let mut mat = Material::new();
+  mat.transparency = 1.0;
+  mat.refractive_index = 1.5;
+  mat
+}
+
+fn glass_sphere() -> Sphere {
+  let mut s = Sphere::new();
+  s.set_material(glass_material());
+  s
+}
+
+#[test]
+fn glass_sphere_has_transparent_material() {
+  let s = glass_sphere();
+  assert_eq!(*s.get_transform(), Matrix4::identity());
+  assert!(util::equal(s.get_material().transparency, 1.0));
+  assert!(util::equal(s.get_material().refractive_index, 1.5));
+}
+
+#[test]
+fn find_n1_and_n2_at_various_intersections() {
+  let mut a = glass_sphere();
+  a.set_transform(Matrix4::scale(2., 2., 2.));
+  let mut mat = glass_material();
+  mat.refractive_index = 1.5;
+  a.set_material(mat);
+  let mut b = glass_sphere();
+  b.set_transform(Matrix4::translation(0., 0., -0.25));
+  let mut mat = glass_material();
+  mat.refractive_index = 2.0;
+  b.set_material(mat);
+  let mut c = glass_sphere();
+  c.set_transform(Matrix4::translation(0., 0., 0.25));
+  let mut mat = glass_material();
+  mat.refractive_index = 2.5;
+  c.set_material(mat);
+  let a: Arc<dyn Shape> = Arc::new(a);
+  let b: Arc<dyn Shape> = Arc::new(b);
+  let c: Arc<dyn Shape> = Arc::new(c);
+
+  let r = Ray::new(&Tuple::point(0., 0., -4.), &Tuple::vector(0., 0., 1.));
+  let xs = Intersection::intersections(&[
+    Intersection::new(&a, 2.),
+    Intersection::new(&b, 2.75),
+    Intersection::new(&c, 3.25),
+    Intersection::new(&b, 4.75),
+    Intersection::new(&c, 5.25),
+    Intersection::new(&a, 6.),
+  ]);
+  let expected = [
+    (1.0, 1.5),
+    (1.5, 2.0),
+    (2.0, 2.5),
+    (2.5, 2.5),
+    (2.5, 1.5),
+    (1.5, 1.0),
+  ];
+  for (i, (n1, n2)) in expected.iter().enumerate() {
+    let comps = Ray::precompute_with_intersections(&xs[i], &r, &xs);
+    assert!(util::equal(comps.n1, *n1));
+    assert!(util::equal(comps.n2, *n2));
+  }
+}
+
+#[test]
+fn under_point_is_offset_below_surface() {
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let mut s = glass_sphere();
+  s.set_transform(Matrix4::translation(0., 0., 1.));
+  let s: Arc<dyn Shape> = Arc::new(s);
+  let xs = Intersection::intersections(&[Intersection::new(&s, 5.0)]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  assert!(comps.under_point.z > util::EPSILON / 2.);
+  assert!(comps.point.z < comps.under_point.z);
+}
+
+#[test]
+fn refracted_color_with_opaque_surface() {
+  let w = World::default();
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let xs = Intersection::intersections(&[
+    Intersection::new(&w.shapes[0], 4.0),
+    Intersection::new(&w.shapes[0], 6.0),
+  ]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  let c = w.refracted_color(&comps, 5);
+  assert_eq!(c, Color::new(0., 0., 0.));
+}
+
+#[test]
+fn refracted_color_at_max_recursive_depth() {
+  let mut w = World::default();
+  let mut s = Sphere::new();
+  let mut mat = Material::new();
+  mat.color = Color::new(0.8, 1.0, 0.6);
+  mat.diffuse = 0.7;
+  mat.specular = 0.2;
+  mat.transparency = 1.0;
+  mat.refractive_index = 1.5;
+  s.set_material(mat);
+  w.shapes[0] = Arc::new(s);
+
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let xs = Intersection::intersections(&[
+    Intersection::new(&w.shapes[0], 4.0),
+    Intersection::new(&w.shapes[0], 6.0),
+  ]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  let c = w.refracted_color(&comps, 0);
+  assert_eq!(c, Color::new(0., 0., 0.));
+}
+
+#[test]
+fn refracted_color_under_total_internal_reflection() {
+  let mut w = World::default();
+  let mut s = Sphere::new();
+  let mut mat = Material::new();
+  mat.color = Color::new(0.8, 1.0, 0.6);
+  mat.diffuse = 0.7;
+  mat.specular = 0.2;
+  mat.transparency = 1.0;
+  mat.refractive_index = 1.5;
+  s.set_material(mat);
+  w.shapes[0] = Arc::new(s);
+
+  let r = Ray::new(
+    &Tuple::point(0., 0., (2. as f64).sqrt() / 2.),
+    &Tuple::vector(0., 1., 0.),
+  );
+  let xs = Intersection::intersections(&[
+    Intersection::new(&w.shapes[0], -(2. as f64).sqrt() / 2.),
+    Intersection::new(&w.shapes[0], (2. as f64).sqrt() / 2.),
+  ]);
+  // the ray starts inside the sphere, so look at the second intersection
+  let comps = Ray::precompute_with_intersections(&xs[1], &r, &xs);
+  let c = w.refracted_color(&comps, 5);
+  assert_eq!(c, Color::new(0., 0., 0.));
+}
+
+#[test]
+fn refracted_color_with_refracted_ray() {
+  let mut w = World::default();
+  let mut a = Sphere::new();
+  let mut mat = Material::new();
+  mat.color = Color::new(0.8, 1.0, 0.6);
+  mat.diffuse = 0.7;
+  mat.specular = 0.2;
+  mat.ambient = 1.0;
+  mat.pattern = Some(Arc::new(TestPattern::new()));
+  a.set_material(mat);
+  let mut b = Sphere::new();
+  b.set_transform(Matrix4::scale(0.5, 0.5, 0.5));
+  let mut mat = Material::new();
+  mat.transparency = 1.0;
+  mat.refractive_index = 1.5;
+  b.set_material(mat);
+  w.shapes = vec![Arc::new(a), Arc::new(b)];
+
+  let r = Ray::new(&Tuple::point(0., 0., 0.1), &Tuple::vector(0., 1., 0.));
+  let xs = Intersection::intersections(&[
+    Intersection::new(&w.shapes[0], -0.9899),
+    Intersection::new(&w.shapes[1], -0.4899),
+    Intersection::new(&w.shapes[1], 0.4899),
+    Intersection::new(&w.shapes[0], 0.9899),
+  ]);
+  let comps = Ray::precompute_with_intersections(&xs[2], &r, &xs);
+  let c = w.refracted_color(&comps, 5);
+  assert_eq!(c, Color::new(0., 0.99888, 0.04725));
+}
+
+#[test]
+fn shade_hit_transparent_material() {
+  let mut w = World::default();
+  let mut floor = Plane::new();
+  floor.set_transform(Matrix4::translation(0., -1., 0.));
+  let mut mat = Material::new();
+  mat.transparency = 0.5;
+  mat.refractive_index = 1.5;
+  floor.set_material(mat);
+  w.shapes.push(Arc::new(floor));
+
+  let mut ball = Sphere::new();
+  ball.set_transform(Matrix4::translation(0., -3.5, -0.5));
+  let mut mat = Material::new();
+  mat.color = Color::new(1., 0., 0.);
+  mat.ambient = 0.5;
+  ball.set_material(mat);
+  w.shapes.push(Arc::new(ball));
+
+  let r = Ray::new(
+    &Tuple::point(0., 0., -3.),
+    &Tuple::vector(0., -(2. as f64).sqrt() / 2., (2. as f64).sqrt() / 2.),
+  );
+  let xs = Intersection::intersections(&[Intersection::new(&w.shapes[2], (2. as f64).sqrt())]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  let c = w.shade_hit(&comps, 5);
+  assert_eq!(c, Color::new(0.93642, 0.68642, 0.68642));
+}
+
+#[test]
+fn shade_hit_without_remaining_bounces_traces_no_secondary_rays() {
+  let world = |reflective: f64, transparency: f64| {
+    let mut w = World::default();
+    let mut floor = Plane::new();
+    floor.set_transform(Matrix4::translation(0., -1., 0.));
+    let mut mat = Material::new();
+    mat.reflective = reflective;
+    mat.transparency = transparency;
+    mat.refractive_index = 1.5;
+    floor.set_material(mat);
+    w.shapes.push(Arc::new(floor));
+
+    let mut ball = Sphere::new();
+    ball.set_transform(Matrix4::translation(0., -3.5, -0.5));
+    let mut mat = Material::new();
+    mat.color = Color::new(1., 0., 0.);
+    mat.ambient = 0.5;
+    ball.set_material(mat);
+    w.shapes.push(Arc::new(ball));
+    w
+  };
+  let r = Ray::new(
+    &Tuple::point(0., 0., -3.),
+    &Tuple::vector(0., -(2. as f64).sqrt() / 2., (2. as f64).sqrt() / 2.),
+  );
+  let shade = |w: &World, remaining: u8| {
+    let xs = Intersection::intersections(&[Intersection::new(&w.shapes[2], (2. as f64).sqrt())]);
+    let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+    w.shade_hit(&comps, remaining)
+  };
+  let surface = shade(&world(0., 0.), 5);
+  // reflection, refraction and the two blended by Schlick are all left out
+  for (reflective, transparency) in [(0.5, 0.), (0., 0.5), (0.5, 0.5)].iter() {
+    let w = world(*reflective, *transparency);
+    assert_ne!(shade(&w, 5), surface);
+    assert_eq!(shade(&w, 0), surface);
+    assert_eq!(w.color_at(&r, 0), surface);
+  }
+}
+
 #[test]
 pub fn shape_default_transformation() {
   let s = TestShape::new();
-  assert_eq!(*s.get_transform(), Matrix::new_identity_matrix(4));
+  assert_eq!(*s.get_transform(), Matrix4::identity());
 }
 
 #[test]
 pub fn shape_assign_transformation() {
   let mut s = TestShape::new();
-  s.set_transform(Matrix::translation(2., 3., 4.));
-  assert_eq!(*s.get_transform(), Matrix::translation(2., 3., 4.));
+  s.set_transform(Matrix4::translation(2., 3., 4.));
+  assert_eq!(*s.get_transform(), Matrix4::translation(2., 3., 4.));
 }
 
 #[test]
@@ -939,19 +1242,20 @@ pub fn shape_default_material() {
 #[test]
 pub fn shape_assign_material() {
   let mut s = TestShape::new();
-  let mut m = Rc::new(Material::new());
-  Rc::get_mut(&mut m).unwrap().ambient = 1.;
+  let mut m = Material::new();
+  m.ambient = 1.;
 
-  s.material = m.clone();
-  assert_eq!(s.material, m);
+  s.set_material(m);
+  assert_eq!(s.get_material().ambient, 1.);
 }
 
 #[test]
 pub fn intersect_scaled_shape_with_ray() {
   let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
   let mut s = TestShape::new();
-  s.set_transform(Matrix::scale(2.0, 2.0, 2.0));
-  let _ = s.intersect(&r);
+  s.set_transform(Matrix4::scale(2.0, 2.0, 2.0));
+  let s = Arc::new(s);
+  let _ = s.clone().intersect(&r);
 
   assert_eq!(s.get_saved_ray().origin, Tuple::point(0., 0., -2.5));
   assert_eq!(s.get_saved_ray().direction, Tuple::vector(0., 0., 0.5));
@@ -961,8 +1265,9 @@ pub fn intersect_scaled_shape_with_ray() {
 pub fn intersect_transformed_shape_with_ray() {
   let r = Ray::new(&Tuple::point(0.0, 0.0, -5.0), &Tuple::vector(0.0, 0.0, 1.0));
   let mut s = TestShape::new();
-  s.set_transform(Matrix::translation(5.0, 0.0, 0.0));
-  let _ = s.intersect(&r);
+  s.set_transform(Matrix4::translation(5.0, 0.0, 0.0));
+  let s = Arc::new(s);
+  let _ = s.clone().intersect(&r);
 
   assert_eq!(s.get_saved_ray().origin, Tuple::point(-5.0, 0., -5.0));
   assert_eq!(s.get_saved_ray().direction, Tuple::vector(0., 0., 1.0));
@@ -971,8 +1276,8 @@ pub fn intersect_transformed_shape_with_ray() {
 #[test]
 pub fn compute_normal_translated_shape() {
   let mut s = TestShape::new();
-  s.set_transform(Matrix::translation(0.0, 1.0, 0.0));
-  let n = normal_at(Rc::new(s), &Tuple::point(0.0, 1.70711, -0.70711));
+  s.set_transform(Matrix4::translation(0.0, 1.0, 0.0));
+  let n = normal_at(Arc::new(s), &Tuple::point(0.0, 1.70711, -0.70711));
 
   assert_eq!(n, Tuple::vector(0.0, 0.70711, -0.70711));
 }
@@ -980,10 +1285,10 @@ pub fn compute_normal_translated_shape() {
 #[test]
 pub fn compute_normal_transformed_shape() {
   let mut s = TestShape::new();
-  let m = Matrix::scale(1.0, 0.5, 1.0) * Matrix::rotation_z(consts::PI / 5.0);
+  let m = Matrix4::scale(1.0, 0.5, 1.0) * Matrix4::rotation_z(consts::PI / 5.0);
   s.set_transform(m);
   let n = normal_at(
-    Rc::new(s),
+    Arc::new(s),
     &Tuple::point(0.0, (2.0 as f64).sqrt() / 2.0, -(2.0 as f64).sqrt() / 2.0),
   );
 
@@ -1005,7 +1310,7 @@ pub fn normal_of_plane_is_constant() {
 pub fn intersect_with_ray_parallel_to_plane() {
   let p = Plane::new();
   let r = Ray::new(&Tuple::point(0.0, 10.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
-  let xs = p.intersect(&r);
+  let xs = Arc::new(p).intersect(&r);
 
   assert_eq!(0, xs.len());
 }
@@ -1014,16 +1319,16 @@ pub fn intersect_with_ray_parallel_to_plane() {
 pub fn intersect_with_coplanar_ray() {
   let p = Plane::new();
   let r = Ray::new(&Tuple::point(0.0, 0.0, 0.0), &Tuple::vector(0.0, 0.0, 1.0));
-  let xs = p.intersect(&r);
+  let xs = Arc::new(p).intersect(&r);
   assert_eq!(0, xs.len());
 }
 
 #[test]
 pub fn ray_intersect_from_above() {
-  let p = Plane::new();
+  let p = Arc::new(Plane::new());
   let r = Ray::new(&Tuple::point(0.0, 1.0, 0.0), &Tuple::vector(0.0, -1.0, 0.0));
 
-  let xs = p.intersect(&r);
+  let xs = p.clone().intersect(&r);
 
   assert_eq!(xs.len(), 1);
   assert!(util::equal(xs[0].t, 1.0));
@@ -1032,12 +1337,2215 @@ pub fn ray_intersect_from_above() {
 
 #[test]
 pub fn ray_intersect_from_below() {
-  let p = Plane::new();
+  let p = Arc::new(Plane::new());
   let r = Ray::new(&Tuple::point(0.0, -1.0, 0.0), &Tuple::vector(0.0, 1.0, 0.0));
 
-  let xs = p.intersect(&r);
+  let xs = p.clone().intersect(&r);
 
   assert_eq!(xs.len(), 1);
   assert!(util::equal(xs[0].t, 1.0));
   assert_eq!(xs[0].shape.get_id(), p.get_id());
 }
+
+#[test]
+fn schlick_under_total_internal_reflection() {
+  let shape: Arc<dyn Shape> = Arc::new(glass_sphere());
+  let r = Ray::new(
+    &Tuple::point(0., 0., (2. as f64).sqrt() / 2.),
+    &Tuple::vector(0., 1., 0.),
+  );
+  let xs = Intersection::intersections(&[
+    Intersection::new(&shape, -(2. as f64).sqrt() / 2.),
+    Intersection::new(&shape, (2. as f64).sqrt() / 2.),
+  ]);
+  let comps = Ray::precompute_with_intersections(&xs[1], &r, &xs);
+  assert!(util::equal(comps.schlick(), 1.0));
+}
+
+#[test]
+fn schlick_with_perpendicular_viewing_angle() {
+  let shape: Arc<dyn Shape> = Arc::new(glass_sphere());
+  let r = Ray::new(&Tuple::point(0., 0., 0.), &Tuple::vector(0., 1., 0.));
+  let xs = Intersection::intersections(&[
+    Intersection::new(&shape, -1.),
+    Intersection::new(&shape, 1.),
+  ]);
+  let comps = Ray::precompute_with_intersections(&xs[1], &r, &xs);
+  assert!(util::equal(comps.schlick(), 0.04));
+}
+
+#[test]
+fn schlick_with_small_angle_and_n2_greater_than_n1() {
+  let shape: Arc<dyn Shape> = Arc::new(glass_sphere());
+  let r = Ray::new(&Tuple::point(0., 0.99, -2.), &Tuple::vector(0., 0., 1.));
+  let xs = Intersection::intersections(&[Intersection::new(&shape, 1.8589)]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  assert!(util::equal(comps.schlick(), 0.48873));
+}
+
+#[test]
+fn shade_hit_reflective_transparent_material() {
+  let mut w = World::default();
+  let mut floor = Plane::new();
+  floor.set_transform(Matrix4::translation(0., -1., 0.));
+  let mut mat = Material::new();
+  mat.reflective = 0.5;
+  mat.transparency = 0.5;
+  mat.refractive_index = 1.5;
+  floor.set_material(mat);
+  w.shapes.push(Arc::new(floor));
+
+  let mut ball = Sphere::new();
+  ball.set_transform(Matrix4::translation(0., -3.5, -0.5));
+  let mut mat = Material::new();
+  mat.color = Color::new(1., 0., 0.);
+  mat.ambient = 0.5;
+  ball.set_material(mat);
+  w.shapes.push(Arc::new(ball));
+
+  let r = Ray::new(
+    &Tuple::point(0., 0., -3.),
+    &Tuple::vector(0., -(2. as f64).sqrt() / 2., (2. as f64).sqrt() / 2.),
+  );
+  let xs = Intersection::intersections(&[Intersection::new(&w.shapes[2], (2. as f64).sqrt())]);
+  let comps = Ray::precompute_with_intersections(&xs[0], &r, &xs);
+  let c = w.shade_hit(&comps, 5);
+  assert_eq!(c, Color::new(0.93391, 0.69643, 0.69243));
+}
+
+#[test]
+fn shade_hit_accumulates_all_lights() {
+  let mut w = World::default();
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let i = Intersection::new(&w.shapes[0], 4.0);
+  let comps = Ray::precompute(&i, &r);
+  let single = w.shade_hit(&comps, 4);
+
+  w.lights.push(Arc::new(PointLight::new(
+    &Tuple::point(-10., 10., -10.),
+    &Color::new(1., 1., 1.),
+  )));
+  let double = w.shade_hit(&comps, 4);
+  assert_eq!(double, single * 2.0);
+}
+
+#[test]
+fn shade_hit_with_one_light_shadowed() {
+  let mut w = World::new();
+  w.lights = vec![
+    Arc::new(PointLight::new(&Tuple::point(0., 0., -10.), &Color::new(1., 1., 1.))),
+    Arc::new(PointLight::new(&Tuple::point(0., 0., 5.), &Color::new(1., 1., 1.))),
+  ];
+  let s1 = Sphere::new();
+  let mut s2 = Sphere::new();
+  s2.set_transform(Matrix4::translation(0., 0., 10.));
+  w.shapes = vec![Arc::new(s1), Arc::new(s2)];
+
+  // The point is shadowed from the first light by s1, but lit head-on by the second
+  let ray = Ray::new(&Tuple::point(0., 0., 5.), &Tuple::vector(0., 0., 1.));
+  let i = Intersection::new(&w.shapes[1], 4.0);
+  let comps = Ray::precompute(&i, &ray);
+
+  assert!(w.is_shadowed(&comps.over_point, &Tuple::point(0., 0., -10.)));
+  assert!(!w.is_shadowed(&comps.over_point, &Tuple::point(0., 0., 5.)));
+  let c = World::shade_hit(&w, &comps, 4);
+  assert_eq!(c, Color::new(2.0, 2.0, 2.0));
+}
+
+#[test]
+fn shade_hit_without_lights_is_ambient() {
+  let mut w = World::default();
+  w.lights = vec![];
+  let ray = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let i = Intersection::new(&w.shapes[0], 4.0);
+  let comps = Ray::precompute(&i, &ray);
+
+  let c = w.shade_hit(&comps, 4);
+  assert_eq!(c, Color::new(0.08, 0.1, 0.06));
+}
+
+#[test]
+fn creating_area_light() {
+  let corner = Tuple::point(0., 0., 0.);
+  let v1 = Tuple::vector(2., 0., 0.);
+  let v2 = Tuple::vector(0., 0., 1.);
+  let light = AreaLight::new(&corner, &v1, 4, &v2, 2, &Color::new(1., 1., 1.));
+  assert_eq!(light.corner, corner);
+  assert_eq!(light.uvec, Tuple::vector(0.5, 0., 0.));
+  assert_eq!(light.usteps, 4);
+  assert_eq!(light.vvec, Tuple::vector(0., 0., 0.5));
+  assert_eq!(light.vsteps, 2);
+  assert_eq!(light.sample_count(), 8);
+  assert_eq!(light.position(), Tuple::point(1., 0., 0.5));
+}
+
+#[test]
+fn area_light_without_steps_has_a_single_sample() {
+  let corner = Tuple::point(0., 0., 0.);
+  let v1 = Tuple::vector(2., 0., 0.);
+  let v2 = Tuple::vector(0., 0., 1.);
+  let light = AreaLight::new(&corner, &v1, 0, &v2, 0, &Color::new(1., 1., 1.));
+  assert_eq!((light.usteps, light.vsteps), (1, 1));
+  assert_eq!(light.uvec, v1);
+  assert_eq!(light.vvec, v2);
+  assert_eq!(light.sample_count(), 1);
+  assert_eq!(light.point_on_light(0, 0, 0.5, 0.5), Tuple::point(1., 0., 0.5));
+}
+
+#[test]
+fn find_single_point_on_area_light() {
+  let corner = Tuple::point(0., 0., 0.);
+  let v1 = Tuple::vector(2., 0., 0.);
+  let v2 = Tuple::vector(0., 0., 1.);
+  let light = AreaLight::new(&corner, &v1, 4, &v2, 2, &Color::new(1., 1., 1.));
+  let cases = [
+    (0, 0, Tuple::point(0.25, 0., 0.25)),
+    (1, 0, Tuple::point(0.75, 0., 0.25)),
+    (0, 1, Tuple::point(0.25, 0., 0.75)),
+    (2, 0, Tuple::point(1.25, 0., 0.25)),
+    (3, 1, Tuple::point(1.75, 0., 0.75)),
+  ];
+  for (u, v, expected) in cases.iter() {
+    assert_eq!(light.point_on_light(*u, *v, 0.5, 0.5), *expected);
+  }
+}
+
+#[test]
+fn area_light_samples_stay_within_cells_when_jittered() {
+  let corner = Tuple::point(0., 0., 0.);
+  let v1 = Tuple::vector(2., 0., 0.);
+  let v2 = Tuple::vector(0., 0., 1.);
+  let mut light = AreaLight::new(&corner, &v1, 4, &v2, 2, &Color::new(1., 1., 1.));
+  light.jitter = true;
+  let point = Tuple::point(0., 5., 0.);
+  let samples = light.samples(&point);
+  assert_eq!(samples.len(), 8);
+  for (i, sample) in samples.iter().enumerate() {
+    let (u, v) = ((i % 4) as f64, (i / 4) as f64);
+    let position = Ray::position(&Ray::new(&point, &sample.direction), sample.distance);
+    assert!(position.x >= u * 0.5 && position.x < (u + 1.) * 0.5);
+    assert!(position.z >= v * 0.5 && position.z < (v + 1.) * 0.5);
+  }
+  // sampling the same point again gives the same jitter
+  let again = light.samples(&point);
+  for (a, b) in samples.iter().zip(again.iter()) {
+    assert_eq!(a.direction, b.direction);
+  }
+}
+
+#[test]
+fn point_light_intensity_at_point() {
+  let w = World::default();
+  let cases = [
+    (Tuple::point(0., 1.0001, 0.), 1.0),
+    (Tuple::point(-1.0001, 0., 0.), 1.0),
+    (Tuple::point(0., 0., -1.0001), 1.0),
+    (Tuple::point(0., 0., 1.0001), 0.0),
+    (Tuple::point(1.0001, 0., 0.), 0.0),
+    (Tuple::point(0., -1.0001, 0.), 0.0),
+    (Tuple::point(0., 0., 0.), 0.0),
+  ];
+  for (point, expected) in cases.iter() {
+    let intensity = w.light_intensity_at(w.lights[0].as_ref(), point);
+    assert!(util::equal(intensity, *expected));
+  }
+}
+
+#[test]
+fn area_light_intensity_at_point() {
+  let w = World::default();
+  let light = AreaLight::new(
+    &Tuple::point(-0.5, -0.5, -5.),
+    &Tuple::vector(1., 0., 0.),
+    2,
+    &Tuple::vector(0., 1., 0.),
+    2,
+    &Color::new(1., 1., 1.),
+  );
+  let cases = [
+    (Tuple::point(0., 0., 2.), 0.0),
+    (Tuple::point(1., -1., 2.), 0.25),
+    (Tuple::point(1.5, 0., 2.), 0.5),
+    (Tuple::point(1.25, 1.25, 3.), 0.75),
+    (Tuple::point(0., 0., -2.), 1.0),
+  ];
+  for (point, expected) in cases.iter() {
+    let intensity = w.light_intensity_at(&light, point);
+    assert!(util::equal(intensity, *expected));
+  }
+}
+
+#[test]
+fn lighting_uses_light_intensity_to_attenuate_color() {
+  let w = World::default();
+  let light = PointLight::new(&Tuple::point(0., 0., -10.), &Color::new(1., 1., 1.));
+  let mut m = Material::new();
+  m.ambient = 0.1;
+  m.diffuse = 0.9;
+  m.specular = 0.;
+  m.color = Color::new(1., 1., 1.);
+  let pt = Tuple::point(0., 0., -1.);
+  let eye_v = Tuple::vector(0., 0., -1.);
+  let normal_v = Tuple::vector(0., 0., -1.);
+  let cases = [
+    (1.0, Color::new(1., 1., 1.)),
+    (0.5, Color::new(0.55, 0.55, 0.55)),
+    (0.0, Color::new(0.1, 0.1, 0.1)),
+  ];
+  for (intensity, expected) in cases.iter() {
+    let c = Material::lighting(
+      &m,
+      w.shapes[0].clone(),
+      &light,
+      &pt,
+      &eye_v,
+      &normal_v,
+      *intensity,
+    );
+    assert_eq!(&c, expected);
+  }
+}
+
+#[test]
+fn lighting_samples_area_light() {
+  let light = AreaLight::new(
+    &Tuple::point(-0.5, -0.5, -5.),
+    &Tuple::vector(1., 0., 0.),
+    2,
+    &Tuple::vector(0., 1., 0.),
+    2,
+    &Color::new(1., 1., 1.),
+  );
+  let shape: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let mut m = Material::new();
+  m.ambient = 0.1;
+  m.diffuse = 0.9;
+  m.specular = 0.;
+  m.color = Color::new(1., 1., 1.);
+  let eye = Tuple::point(0., 0., -5.);
+  let cases = [
+    (Tuple::point(0., 0., -1.), Color::new(0.9965, 0.9965, 0.9965)),
+    (
+      Tuple::point(0., 0.7071, -0.7071),
+      Color::new(0.62318, 0.62318, 0.62318),
+    ),
+  ];
+  for (pt, expected) in cases.iter() {
+    let eye_v = (&eye - pt).normalize();
+    let normal_v = pt - &Tuple::point(0., 0., 0.);
+    let c = Material::lighting(&m, shape.clone(), &light, pt, &eye_v, &normal_v, 1.0);
+    assert_eq!(&c, expected);
+  }
+}
+
+#[test]
+fn point_light_direction_and_distance() {
+  let light = PointLight::new(&Tuple::point(0., 10., 0.), &Color::new(1., 1., 1.));
+  let p = Tuple::point(0., 0., 0.);
+  assert_eq!(light.direction_to_light(&p), Tuple::vector(0., 1., 0.));
+  assert!(util::equal(light.distance(&p), 10.));
+  assert_eq!(light.intensity_at(&p), Color::new(1., 1., 1.));
+}
+
+#[test]
+fn directional_light_is_infinitely_far_away() {
+  let light = DirectionalLight::new(&Tuple::vector(0., -2., 0.), &Color::new(1., 1., 1.));
+  let p = Tuple::point(3., 0., -4.);
+  assert_eq!(light.direction_to_light(&p), Tuple::vector(0., 1., 0.));
+  assert!(light.distance(&p).is_infinite());
+}
+
+#[test]
+fn directional_light_casts_shadows_from_any_distance() {
+  let mut w = World::default();
+  w.lights = vec![Arc::new(DirectionalLight::new(
+    &Tuple::vector(0., -1., 0.),
+    &Color::new(1., 1., 1.),
+  ))];
+  let light = w.lights[0].as_ref();
+  // straight below the spheres, far away from them
+  let shadowed = w.light_intensity_at(light, &Tuple::point(0., -1000., 0.));
+  let lit = w.light_intensity_at(light, &Tuple::point(5., -1000., 0.));
+  assert!(util::equal(shadowed, 0.0));
+  assert!(util::equal(lit, 1.0));
+}
+
+#[test]
+fn lighting_with_directional_light() {
+  let m = Material::new();
+  let light = DirectionalLight::new(&Tuple::vector(0., 0., 1.), &Color::new(1., 1., 1.));
+  let result = Material::lighting(
+    &m,
+    Arc::new(Sphere::new()),
+    &light,
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 0., -1.),
+    &Tuple::vector(0., 0., -1.),
+    1.0,
+  );
+  assert_eq!(result, Color::new(1.9, 1.9, 1.9));
+}
+
+#[test]
+fn spot_light_intensity_inside_and_outside_cone() {
+  let light = SpotLight::new(
+    &Tuple::point(0., 10., 0.),
+    &Tuple::vector(0., -1., 0.),
+    consts::PI / 4.,
+    &Color::new(1., 1., 1.),
+  );
+  assert_eq!(light.intensity_at(&Tuple::point(0., 0., 0.)), Color::new(1., 1., 1.));
+  assert_eq!(light.intensity_at(&Tuple::point(9., 0., 0.)), Color::new(1., 1., 1.));
+  assert_eq!(light.intensity_at(&Tuple::point(11., 0., 0.)), Color::new(0., 0., 0.));
+  assert_eq!(light.intensity_at(&Tuple::point(0., 20., 0.)), Color::new(0., 0., 0.));
+}
+
+#[test]
+fn spot_light_fades_out_towards_edge_of_cone() {
+  let mut light = SpotLight::new(
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 0., 1.),
+    consts::PI / 4.,
+    &Color::new(1., 1., 1.),
+  );
+  light.falloff = consts::PI / 4.;
+  assert_eq!(light.intensity_at(&Tuple::point(0., 0., 1.)), Color::new(1., 1., 1.));
+  // halfway into the falloff region
+  let p = Tuple::point((consts::PI / 8.).tan(), 0., 1.);
+  assert_eq!(light.intensity_at(&p), Color::new(0.5, 0.5, 0.5));
+}
+
+#[test]
+fn lighting_outside_spot_light_cone_is_ambient() {
+  let m = Material::new();
+  let light = SpotLight::new(
+    &Tuple::point(0., 0., -10.),
+    &Tuple::vector(0., 1., 0.),
+    consts::PI / 8.,
+    &Color::new(1., 1., 1.),
+  );
+  let result = Material::lighting(
+    &m,
+    Arc::new(Sphere::new()),
+    &light,
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 0., -1.),
+    &Tuple::vector(0., 0., -1.),
+    1.0,
+  );
+  assert_eq!(result, Color::new(0.1, 0.1, 0.1));
+}
+
+#[test]
+pub fn ray_intersects_cube() {
+  let c = Cube::new();
+  let cases = [
+    (Tuple::point(5., 0.5, 0.), Tuple::vector(-1., 0., 0.), 4., 6.),
+    (Tuple::point(-5., 0.5, 0.), Tuple::vector(1., 0., 0.), 4., 6.),
+    (Tuple::point(0.5, 5., 0.), Tuple::vector(0., -1., 0.), 4., 6.),
+    (Tuple::point(0.5, -5., 0.), Tuple::vector(0., 1., 0.), 4., 6.),
+    (Tuple::point(0.5, 0., 5.), Tuple::vector(0., 0., -1.), 4., 6.),
+    (Tuple::point(0.5, 0., -5.), Tuple::vector(0., 0., 1.), 4., 6.),
+    (Tuple::point(0., 0.5, 0.), Tuple::vector(0., 0., 1.), -1., 1.),
+  ];
+  let c = Arc::new(c);
+  for (origin, direction, t1, t2) in cases.iter() {
+    let r = Ray::new(origin, direction);
+    let xs = c.clone().intersect(&r);
+    assert_eq!(xs.len(), 2);
+    assert!(util::equal(xs[0].t, *t1));
+    assert!(util::equal(xs[1].t, *t2));
+  }
+}
+
+#[test]
+pub fn ray_misses_cube() {
+  let c = Cube::new();
+  let cases = [
+    (Tuple::point(-2., 0., 0.), Tuple::vector(0.2673, 0.5345, 0.8018)),
+    (Tuple::point(0., -2., 0.), Tuple::vector(0.8018, 0.2673, 0.5345)),
+    (Tuple::point(0., 0., -2.), Tuple::vector(0.5345, 0.8018, 0.2673)),
+    (Tuple::point(2., 0., 2.), Tuple::vector(0., 0., -1.)),
+    (Tuple::point(0., 2., 2.), Tuple::vector(0., -1., 0.)),
+    (Tuple::point(2., 2., 0.), Tuple::vector(-1., 0., 0.)),
+  ];
+  let c = Arc::new(c);
+  for (origin, direction) in cases.iter() {
+    let r = Ray::new(origin, direction);
+    let xs = c.clone().intersect(&r);
+    assert_eq!(xs.len(), 0);
+  }
+}
+
+#[test]
+pub fn normal_on_surface_of_cube() {
+  let c = Cube::new();
+  let cases = [
+    (Tuple::point(1., 0.5, -0.8), Tuple::vector(1., 0., 0.)),
+    (Tuple::point(-1., -0.2, 0.9), Tuple::vector(-1., 0., 0.)),
+    (Tuple::point(-0.4, 1., -0.1), Tuple::vector(0., 1., 0.)),
+    (Tuple::point(0.3, -1., -0.7), Tuple::vector(0., -1., 0.)),
+    (Tuple::point(-0.6, 0.3, 1.), Tuple::vector(0., 0., 1.)),
+    (Tuple::point(0.4, 0.4, -1.), Tuple::vector(0., 0., -1.)),
+    (Tuple::point(1., 1., 1.), Tuple::vector(1., 0., 0.)),
+    (Tuple::point(-1., -1., -1.), Tuple::vector(-1., 0., 0.)),
+  ];
+  for (point, normal) in cases.iter() {
+    assert_eq!(c.local_normal_at(point), *normal);
+  }
+}
+
+#[test]
+pub fn intersect_transformed_cube() {
+  let mut c = Cube::new();
+  c.set_transform(Matrix4::translation(0., 0., 3.) * Matrix4::scale(2., 2., 2.));
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let c = Arc::new(c);
+  let xs = c.clone().intersect(&r);
+  assert_eq!(xs.len(), 2);
+  assert!(util::equal(xs[0].t, 6.));
+  assert!(util::equal(xs[1].t, 10.));
+  assert_eq!(xs[0].shape.get_id(), c.get_id());
+}
+
+#[test]
+pub fn ray_misses_cylinder() {
+  let cyl = Cylinder::new();
+  let cases = [
+    (Tuple::point(1., 0., 0.), Tuple::vector(0., 1., 0.)),
+    (Tuple::point(0., 0., 0.), Tuple::vector(0., 1., 0.)),
+    (Tuple::point(0., 0., -5.), Tuple::vector(1., 1., 1.)),
+  ];
+  let cyl = Arc::new(cyl);
+  for (origin, direction) in cases.iter() {
+    let r = Ray::new(origin, &direction.normalize());
+    assert_eq!(cyl.clone().intersect(&r).len(), 0);
+  }
+}
+
+#[test]
+pub fn ray_strikes_cylinder() {
+  let cyl = Cylinder::new();
+  let cases = [
+    (Tuple::point(1., 0., -5.), Tuple::vector(0., 0., 1.), 5., 5.),
+    (Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.), 4., 6.),
+    (
+      Tuple::point(0.5, 0., -5.),
+      Tuple::vector(0.1, 1., 1.),
+      6.80798,
+      7.08872,
+    ),
+  ];
+  let cyl = Arc::new(cyl);
+  for (origin, direction, t0, t1) in cases.iter() {
+    let r = Ray::new(origin, &direction.normalize());
+    let xs = cyl.clone().intersect(&r);
+    assert_eq!(xs.len(), 2);
+    assert!(util::equal(xs[0].t, *t0));
+    assert!(util::equal(xs[1].t, *t1));
+  }
+}
+
+#[test]
+pub fn normal_on_cylinder() {
+  let cyl = Cylinder::new();
+  let cases = [
+    (Tuple::point(1., 0., 0.), Tuple::vector(1., 0., 0.)),
+    (Tuple::point(0., 5., -1.), Tuple::vector(0., 0., -1.)),
+    (Tuple::point(0., -2., 1.), Tuple::vector(0., 0., 1.)),
+    (Tuple::point(-1., 1., 0.), Tuple::vector(-1., 0., 0.)),
+  ];
+  for (point, normal) in cases.iter() {
+    assert_eq!(cyl.local_normal_at(point), *normal);
+  }
+}
+
+#[test]
+pub fn default_cylinder_is_infinite_and_open() {
+  let cyl = Cylinder::new();
+  assert_eq!(cyl.minimum(), f64::NEG_INFINITY);
+  assert_eq!(cyl.maximum(), f64::INFINITY);
+  assert!(!cyl.closed);
+}
+
+#[test]
+pub fn intersect_constrained_cylinder() {
+  let mut cyl = Cylinder::new();
+  cyl.set_minimum(1.);
+  cyl.set_maximum(2.);
+  let cases = [
+    (Tuple::point(0., 1.5, 0.), Tuple::vector(0.1, 1., 0.), 0),
+    (Tuple::point(0., 3., -5.), Tuple::vector(0., 0., 1.), 0),
+    (Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.), 0),
+    (Tuple::point(0., 2., -5.), Tuple::vector(0., 0., 1.), 0),
+    (Tuple::point(0., 1., -5.), Tuple::vector(0., 0., 1.), 0),
+    (Tuple::point(0., 1.5, -2.), Tuple::vector(0., 0., 1.), 2),
+  ];
+  let cyl = Arc::new(cyl);
+  for (point, direction, count) in cases.iter() {
+    let r = Ray::new(point, &direction.normalize());
+    assert_eq!(cyl.clone().intersect(&r).len(), *count);
+  }
+}
+
+#[test]
+pub fn intersect_caps_of_closed_cylinder() {
+  let mut cyl = Cylinder::new();
+  cyl.set_minimum(1.);
+  cyl.set_maximum(2.);
+  cyl.closed = true;
+  let cases = [
+    (Tuple::point(0., 3., 0.), Tuple::vector(0., -1., 0.), 2),
+    (Tuple::point(0., 3., -2.), Tuple::vector(0., -1., 2.), 2),
+    (Tuple::point(0., 4., -2.), Tuple::vector(0., -1., 1.), 2),
+    (Tuple::point(0., 0., -2.), Tuple::vector(0., 1., 2.), 2),
+    (Tuple::point(0., -1., -2.), Tuple::vector(0., 1., 1.), 2),
+  ];
+  let cyl = Arc::new(cyl);
+  for (point, direction, count) in cases.iter() {
+    let r = Ray::new(point, &direction.normalize());
+    assert_eq!(cyl.clone().intersect(&r).len(), *count);
+  }
+}
+
+#[test]
+pub fn normal_on_cylinder_end_caps() {
+  let mut cyl = Cylinder::new();
+  cyl.set_minimum(1.);
+  cyl.set_maximum(2.);
+  cyl.closed = true;
+  let cases = [
+    (Tuple::point(0., 1., 0.), Tuple::vector(0., -1., 0.)),
+    (Tuple::point(0.5, 1., 0.), Tuple::vector(0., -1., 0.)),
+    (Tuple::point(0., 1., 0.5), Tuple::vector(0., -1., 0.)),
+    (Tuple::point(0., 2., 0.), Tuple::vector(0., 1., 0.)),
+    (Tuple::point(0.5, 2., 0.), Tuple::vector(0., 1., 0.)),
+    (Tuple::point(0., 2., 0.5), Tuple::vector(0., 1., 0.)),
+  ];
+  for (point, normal) in cases.iter() {
+    assert_eq!(cyl.local_normal_at(point), *normal);
+  }
+}
+
+#[test]
+pub fn intersect_cone_with_ray() {
+  let shape = Cone::new();
+  let cases = [
+    (Tuple::point(0., 0., -5.), Tuple::vector(0., 0., 1.), 5., 5.),
+    (Tuple::point(0., 0., -5.), Tuple::vector(1., 1., 1.), 8.66025, 8.66025),
+    (
+      Tuple::point(1., 1., -5.),
+      Tuple::vector(-0.5, -1., 1.),
+      4.55006,
+      49.44994,
+    ),
+  ];
+  let shape = Arc::new(shape);
+  for (origin, direction, t0, t1) in cases.iter() {
+    let r = Ray::new(origin, &direction.normalize());
+    let xs = shape.clone().intersect(&r);
+    assert_eq!(xs.len(), 2);
+    assert!(util::equal(xs[0].t, *t0));
+    assert!(util::equal(xs[1].t, *t1));
+  }
+}
+
+#[test]
+pub fn intersect_cone_with_ray_parallel_to_one_half() {
+  let shape = Cone::new();
+  let r = Ray::new(
+    &Tuple::point(0., 0., -1.),
+    &Tuple::vector(0., 1., 1.).normalize(),
+  );
+  let xs = Arc::new(shape).intersect(&r);
+  assert_eq!(xs.len(), 1);
+  assert!(util::equal(xs[0].t, 0.35355));
+}
+
+#[test]
+pub fn intersect_caps_of_closed_cone() {
+  let mut shape = Cone::new();
+  shape.set_minimum(-0.5);
+  shape.set_maximum(0.5);
+  shape.closed = true;
+  let cases = [
+    (Tuple::point(0., 0., -5.), Tuple::vector(0., 1., 0.), 0),
+    (Tuple::point(0., 0., -0.25), Tuple::vector(0., 1., 1.), 2),
+    (Tuple::point(0., 0., -0.25), Tuple::vector(0., 1., 0.), 4),
+  ];
+  let shape = Arc::new(shape);
+  for (point, direction, count) in cases.iter() {
+    let r = Ray::new(point, &direction.normalize());
+    assert_eq!(shape.clone().intersect(&r).len(), *count);
+  }
+}
+
+#[test]
+pub fn normal_on_cone() {
+  let shape = Cone::new();
+  let cases = [
+    (Tuple::point(0., 0., 0.), Tuple::vector(0., 0., 0.)),
+    (
+      Tuple::point(1., 1., 1.),
+      Tuple::vector(1., -(2. as f64).sqrt(), 1.),
+    ),
+    (Tuple::point(-1., -1., 0.), Tuple::vector(-1., 1., 0.)),
+  ];
+  for (point, normal) in cases.iter() {
+    assert_eq!(shape.local_normal_at(point), *normal);
+  }
+}
+
+#[test]
+pub fn normal_on_cone_end_caps() {
+  let mut shape = Cone::new();
+  shape.set_minimum(-1.);
+  shape.set_maximum(2.);
+  shape.closed = true;
+  assert_eq!(
+    shape.local_normal_at(&Tuple::point(0.5, 2., 0.)),
+    Tuple::vector(0., 1., 0.)
+  );
+  assert_eq!(
+    shape.local_normal_at(&Tuple::point(0., -1., 0.5)),
+    Tuple::vector(0., -1., 0.)
+  );
+}
+
+#[test]
+pub fn create_group() {
+  let g = Group::new();
+  assert_eq!(*g.get_transform(), Matrix4::identity());
+  assert!(g.is_empty());
+}
+
+#[test]
+pub fn shape_has_no_parent_by_default() {
+  let s = TestShape::new();
+  assert!(s.get_parent().is_none());
+}
+
+#[test]
+pub fn add_child_to_group() {
+  let g = Arc::new(Group::new());
+  let s: Arc<dyn Shape> = Arc::new(TestShape::new());
+  Group::add_child(&g, s.clone());
+  assert!(!g.is_empty());
+  assert_eq!(g.children()[0].get_id(), s.get_id());
+  assert_eq!(s.get_parent().unwrap().get_id(), g.get_id());
+}
+
+#[test]
+pub fn intersect_ray_with_empty_group() {
+  let g = Group::new();
+  let r = Ray::new(&Tuple::point(0., 0., 0.), &Tuple::vector(0., 0., 1.));
+  assert_eq!(Arc::new(g).intersect(&r).len(), 0);
+}
+
+#[test]
+pub fn intersect_ray_with_nonempty_group() {
+  let g = Arc::new(Group::new());
+  let s1: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let mut s2 = Sphere::new();
+  s2.set_transform(Matrix4::translation(0., 0., -3.));
+  let s2: Arc<dyn Shape> = Arc::new(s2);
+  let mut s3 = Sphere::new();
+  s3.set_transform(Matrix4::translation(5., 0., 0.));
+  let s3: Arc<dyn Shape> = Arc::new(s3);
+  Group::add_child(&g, s1.clone());
+  Group::add_child(&g, s2.clone());
+  Group::add_child(&g, s3);
+
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let xs = g.intersect(&r);
+  assert_eq!(xs.len(), 4);
+  assert_eq!(xs[0].shape.get_id(), s2.get_id());
+  assert_eq!(xs[1].shape.get_id(), s2.get_id());
+  assert_eq!(xs[2].shape.get_id(), s1.get_id());
+  assert_eq!(xs[3].shape.get_id(), s1.get_id());
+}
+
+#[test]
+pub fn intersect_transformed_group() {
+  let mut g = Group::new();
+  g.set_transform(Matrix4::scale(2., 2., 2.));
+  let g = Arc::new(g);
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(5., 0., 0.));
+  Group::add_child(&g, Arc::new(s));
+
+  let r = Ray::new(&Tuple::point(10., 0., -10.), &Tuple::vector(0., 0., 1.));
+  assert_eq!(g.intersect(&r).len(), 2);
+}
+
+// A sphere translated by (5, 0, 0) inside a scaled group inside a rotated group
+fn nested_sphere() -> (Arc<Group>, Arc<dyn Shape>) {
+  let mut g1 = Group::new();
+  g1.set_transform(Matrix4::rotation_y(consts::FRAC_PI_2));
+  let g1 = Arc::new(g1);
+  let mut g2 = Group::new();
+  g2.set_transform(Matrix4::scale(1., 2., 3.));
+  let g2 = Arc::new(g2);
+  Group::add_child(&g1, g2.clone());
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(5., 0., 0.));
+  let s: Arc<dyn Shape> = Arc::new(s);
+  Group::add_child(&g2, s.clone());
+  (g1, s)
+}
+
+#[test]
+pub fn convert_point_from_world_to_object_space() {
+  let mut g1 = Group::new();
+  g1.set_transform(Matrix4::rotation_y(consts::FRAC_PI_2));
+  let g1 = Arc::new(g1);
+  let mut g2 = Group::new();
+  g2.set_transform(Matrix4::scale(2., 2., 2.));
+  let g2 = Arc::new(g2);
+  Group::add_child(&g1, g2.clone());
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(5., 0., 0.));
+  let s: Arc<dyn Shape> = Arc::new(s);
+  Group::add_child(&g2, s.clone());
+
+  let p = world_to_object(s.as_ref(), &Tuple::point(-2., 0., -10.));
+  assert_eq!(p, Tuple::point(0., 0., -1.));
+}
+
+#[test]
+pub fn convert_normal_from_object_to_world_space() {
+  let (_g, s) = nested_sphere();
+  let n = normal_to_world(
+    s.as_ref(),
+    &Tuple::vector(
+      (3. as f64).sqrt() / 3.,
+      (3. as f64).sqrt() / 3.,
+      (3. as f64).sqrt() / 3.,
+    ),
+  );
+  assert_eq!(n, Tuple::vector(0.2857, 0.4286, -0.8571));
+}
+
+#[test]
+pub fn find_normal_on_child_object() {
+  let (_g, s) = nested_sphere();
+  let n = normal_at(s, &Tuple::point(1.7321, 1.1547, -5.5774));
+  assert_eq!(n, Tuple::vector(0.2857, 0.42854, -0.85716));
+}
+
+#[test]
+pub fn intersections_with_group_children_keep_parent() {
+  let (g, s) = nested_sphere();
+  let g: Arc<dyn Shape> = g;
+  let r = Ray::new(&Tuple::point(-20., 0., -5.), &Tuple::vector(1., 0., 0.));
+  let xs = g.clone().intersect(&r);
+  assert_eq!(xs.len(), 2);
+  assert_eq!(xs[0].shape.get_id(), s.get_id());
+  // normals of the hit are computed through the whole hierarchy
+  let comps = Ray::precompute(&xs[0], &r);
+  assert_eq!(comps.normal_vector, Tuple::vector(-1., 0., 0.));
+}
+
+#[test]
+pub fn pattern_on_child_object_uses_group_transforms() {
+  let mut g = Group::new();
+  g.set_transform(Matrix4::scale(2., 2., 2.));
+  let g = Arc::new(g);
+  let s: Arc<dyn Shape> = Arc::new(Sphere::new());
+  Group::add_child(&g, s.clone());
+  let pattern = TestPattern::new();
+  let c = pattern.pattern_at_shape(s, &Tuple::point(2., 3., 4.));
+  assert_eq!(c, Color::new(1., 1.5, 2.));
+}
+
+fn default_triangle() -> Triangle {
+  Triangle::new(
+    &Tuple::point(0., 1., 0.),
+    &Tuple::point(-1., 0., 0.),
+    &Tuple::point(1., 0., 0.),
+  )
+}
+
+#[test]
+pub fn construct_triangle() {
+  let t = default_triangle();
+  assert_eq!(t.p1, Tuple::point(0., 1., 0.));
+  assert_eq!(t.p2, Tuple::point(-1., 0., 0.));
+  assert_eq!(t.p3, Tuple::point(1., 0., 0.));
+  assert_eq!(t.e1, Tuple::vector(-1., -1., 0.));
+  assert_eq!(t.e2, Tuple::vector(1., -1., 0.));
+  assert_eq!(t.normal, Tuple::vector(0., 0., -1.));
+}
+
+#[test]
+pub fn normal_on_triangle_is_constant() {
+  let t = default_triangle();
+  assert_eq!(t.local_normal_at(&Tuple::point(0., 0.5, 0.)), t.normal);
+  assert_eq!(t.local_normal_at(&Tuple::point(-0.5, 0.75, 0.)), t.normal);
+  assert_eq!(t.local_normal_at(&Tuple::point(0.5, 0.25, 0.)), t.normal);
+}
+
+#[test]
+pub fn intersect_ray_parallel_to_triangle() {
+  let t = default_triangle();
+  let r = Ray::new(&Tuple::point(0., -1., -2.), &Tuple::vector(0., 1., 0.));
+  assert_eq!(Arc::new(t).intersect(&r).len(), 0);
+}
+
+#[test]
+pub fn ray_misses_triangle_edges() {
+  let t = default_triangle();
+  let origins = [
+    Tuple::point(1., 1., -2.),
+    Tuple::point(-1., 1., -2.),
+    Tuple::point(0., -1., -2.),
+  ];
+  let t = Arc::new(t);
+  for origin in origins.iter() {
+    let r = Ray::new(origin, &Tuple::vector(0., 0., 1.));
+    assert_eq!(t.clone().intersect(&r).len(), 0);
+  }
+}
+
+#[test]
+pub fn ray_strikes_triangle() {
+  let t = default_triangle();
+  let r = Ray::new(&Tuple::point(0., 0.5, -2.), &Tuple::vector(0., 0., 1.));
+  let xs = Arc::new(t).intersect(&r);
+  assert_eq!(xs.len(), 1);
+  assert!(util::equal(xs[0].t, 2.));
+}
+
+#[test]
+pub fn intersection_can_carry_u_and_v() {
+  let s: Arc<dyn Shape> = Arc::new(default_triangle());
+  let i = Intersection::new_with_uv(&s, 3.5, 0.2, 0.4);
+  assert_eq!(i.uv, Some((0.2, 0.4)));
+  assert_eq!(Intersection::new(&s, 3.5).uv, None);
+}
+
+fn default_smooth_triangle() -> SmoothTriangle {
+  SmoothTriangle::new(
+    &Tuple::point(0., 1., 0.),
+    &Tuple::point(-1., 0., 0.),
+    &Tuple::point(1., 0., 0.),
+    &Tuple::vector(0., 1., 0.),
+    &Tuple::vector(-1., 0., 0.),
+    &Tuple::vector(1., 0., 0.),
+  )
+}
+
+#[test]
+pub fn construct_smooth_triangle() {
+  let tri = default_smooth_triangle();
+  assert_eq!(tri.p1, Tuple::point(0., 1., 0.));
+  assert_eq!(tri.p2, Tuple::point(-1., 0., 0.));
+  assert_eq!(tri.p3, Tuple::point(1., 0., 0.));
+  assert_eq!(tri.n1, Tuple::vector(0., 1., 0.));
+  assert_eq!(tri.n2, Tuple::vector(-1., 0., 0.));
+  assert_eq!(tri.n3, Tuple::vector(1., 0., 0.));
+}
+
+#[test]
+pub fn intersection_with_smooth_triangle_stores_u_and_v() {
+  let tri = default_smooth_triangle();
+  let r = Ray::new(&Tuple::point(-0.2, 0.3, -2.), &Tuple::vector(0., 0., 1.));
+  let xs = Arc::new(tri).intersect(&r);
+  let (u, v) = xs[0].uv.unwrap();
+  assert!(util::equal(u, 0.45));
+  assert!(util::equal(v, 0.25));
+}
+
+#[test]
+pub fn smooth_triangle_interpolates_normal() {
+  let tri: Arc<dyn Shape> = Arc::new(default_smooth_triangle());
+  let i = Intersection::new_with_uv(&tri, 1., 0.45, 0.25);
+  let n = normal_at_hit(&i, &Tuple::point(0., 0., 0.));
+  assert_eq!(n, Tuple::vector(-0.5547, 0.83205, 0.));
+}
+
+#[test]
+pub fn precompute_with_smooth_triangle_normal() {
+  let tri: Arc<dyn Shape> = Arc::new(default_smooth_triangle());
+  let i = Intersection::new_with_uv(&tri, 1., 0.45, 0.25);
+  let r = Ray::new(&Tuple::point(-0.2, 0.3, -2.), &Tuple::vector(0., 0., 1.));
+  let comps = Ray::precompute(&i, &r);
+  assert_eq!(comps.normal_vector, Tuple::vector(-0.5547, 0.83205, 0.));
+}
+
+#[test]
+pub fn obj_parser_ignores_unrecognized_lines() {
+  let gibberish = "There was a young lady named Bright
+who traveled much faster than light.
+She set out one day
+in a relative way,
+and came back the previous night.";
+  let parser = ObjParser::parse(gibberish);
+  assert_eq!(parser.ignored.len(), 5);
+  assert_eq!(parser.ignored[1].line_number, 2);
+  assert_eq!(parser.ignored[1].text, "who traveled much faster than light.");
+}
+
+#[test]
+pub fn obj_parser_does_not_report_blank_lines_or_comments() {
+  let parser = ObjParser::parse("# a comment\n\nv 1 2 3\n");
+  assert_eq!(parser.ignored.len(), 0);
+  assert_eq!(parser.vertices.len(), 1);
+}
+
+#[test]
+pub fn obj_parser_reads_vertex_records() {
+  let file = "v -1 1 0
+v -1.0000 0.5000 0.0000
+v 1 0 0
+v 1 1 0";
+  let parser = ObjParser::parse(file);
+  assert_eq!(*parser.vertex(1), Tuple::point(-1., 1., 0.));
+  assert_eq!(*parser.vertex(2), Tuple::point(-1., 0.5, 0.));
+  assert_eq!(*parser.vertex(3), Tuple::point(1., 0., 0.));
+  assert_eq!(*parser.vertex(4), Tuple::point(1., 1., 0.));
+}
+
+#[test]
+pub fn obj_parser_reads_vertex_normal_and_texture_records() {
+  let file = "vn 0 0 1
+vn 0.707 0 -0.707
+vn 1 2 3
+vt 0.5 0.25
+vt 0.75";
+  let parser = ObjParser::parse(file);
+  assert_eq!(*parser.normal(1), Tuple::vector(0., 0., 1.));
+  assert_eq!(*parser.normal(2), Tuple::vector(0.707, 0., -0.707));
+  assert_eq!(*parser.normal(3), Tuple::vector(1., 2., 3.));
+  assert_eq!(parser.texture_coordinates, vec![(0.5, 0.25), (0.75, 0.)]);
+}
+
+// Fires a ray straight down the z axis at (x, y) and counts the hits
+fn hits_at(shape: &Arc<dyn Shape>, x: f64, y: f64) -> usize {
+  let r = Ray::new(&Tuple::point(x, y, -2.), &Tuple::vector(0., 0., 1.));
+  shape.clone().intersect(&r).len()
+}
+
+#[test]
+pub fn obj_parser_reads_triangle_faces() {
+  let file = "v -1 1 0
+v -1 0 0
+v 1 0 0
+v 1 1 0
+
+f 1 2 3
+f 1 3 4";
+  let parser = ObjParser::parse(file);
+  let children = parser.default_group().children();
+  assert_eq!(children.len(), 2);
+  assert_eq!(hits_at(&children[0], -0.5, 0.5), 1);
+  assert_eq!(hits_at(&children[0], 0.5, 0.9), 0);
+  assert_eq!(hits_at(&children[1], 0.5, 0.9), 1);
+  assert_eq!(children[0].local_normal_at(&Tuple::point(0., 0., 0.)), Tuple::vector(0., 0., -1.));
+}
+
+#[test]
+pub fn obj_parser_triangulates_polygons() {
+  let file = "v -1 1 0
+v -1 0 0
+v 1 0 0
+v 1 1 0
+v 0 2 0
+
+f 1 2 3 4 5";
+  let parser = ObjParser::parse(file);
+  let children = parser.default_group().children();
+  assert_eq!(children.len(), 3);
+  assert_eq!(hits_at(&children[0], -1. / 3., 1. / 3.), 1);
+  assert_eq!(hits_at(&children[1], 1. / 3., 2. / 3.), 1);
+  assert_eq!(hits_at(&children[2], 0., 4. / 3.), 1);
+}
+
+#[test]
+pub fn obj_parser_reads_named_groups() {
+  let file = "v -1 1 0
+v -1 0 0
+v 1 0 0
+v 1 1 0
+
+g FirstGroup
+f 1 2 3
+g SecondGroup
+f 1 3 4";
+  let parser = ObjParser::parse(file);
+  assert!(parser.default_group().is_empty());
+  let first = parser.group("FirstGroup").unwrap();
+  let second = parser.group("SecondGroup").unwrap();
+  assert_eq!(first.children().len(), 1);
+  assert_eq!(second.children().len(), 1);
+  assert!(parser.group("ThirdGroup").is_none());
+
+  let g = parser.to_group();
+  let children = g.children();
+  assert_eq!(children.len(), 2);
+  assert_eq!(children[0].get_id(), first.get_id());
+  assert_eq!(children[1].get_id(), second.get_id());
+  assert_eq!(first.get_parent().unwrap().get_id(), g.get_id());
+}
+
+#[test]
+pub fn obj_parser_reads_faces_with_normals() {
+  let file = "v 0 1 0
+v -1 0 0
+v 1 0 0
+
+vt 0 0
+vn -1 0 0
+vn 1 0 0
+vn 0 1 0
+
+f 1//3 2//1 3//2
+f 1/1/3 2/1/1 3/1/2
+f -3/-1/-1 -2/-1/-3 -1/-1/-2";
+  let parser = ObjParser::parse(file);
+  assert_eq!(parser.ignored.len(), 0);
+  let children = parser.default_group().children();
+  assert_eq!(children.len(), 3);
+  for child in children {
+    // A smooth triangle interpolates its normals, so the normal at a hit
+    // depends on where the hit lands
+    let r = Ray::new(&Tuple::point(-0.2, 0.3, -2.), &Tuple::vector(0., 0., 1.));
+    let xs = child.intersect(&r);
+    let n = normal_at_hit(&xs[0], &Tuple::point(-0.2, 0.3, 0.));
+    assert_eq!(n, Tuple::vector(-0.5547, 0.83205, 0.));
+  }
+}
+
+#[test]
+pub fn obj_parser_reports_faces_with_missing_vertices() {
+  let file = "v 0 1 0
+v -1 0 0
+f 1 2 3
+f 1 2
+f 1/2 2 1";
+  let parser = ObjParser::parse(file);
+  assert!(parser.default_group().is_empty());
+  let lines: Vec<usize> = parser.ignored.iter().map(|l| l.line_number).collect();
+  assert_eq!(lines, vec![3, 4, 5]);
+}
+
+#[test]
+pub fn csg_is_created_with_operation_and_two_shapes() {
+  let s1: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let s2: Arc<dyn Shape> = Arc::new(Cube::new());
+  let c = Csg::new(CsgOperation::Union, s1.clone(), s2.clone()).into_shape();
+  assert_eq!(c.operation, CsgOperation::Union);
+  assert_eq!(c.left.get_id(), s1.get_id());
+  assert_eq!(c.right.get_id(), s2.get_id());
+  assert_eq!(s1.get_parent().unwrap().get_id(), c.get_id());
+  assert_eq!(s2.get_parent().unwrap().get_id(), c.get_id());
+}
+
+#[test]
+pub fn csg_operation_rules() {
+  use CsgOperation::{Difference, Intersection as Intersect, Union};
+  // (operation, left_hit, in_left, in_right, allowed)
+  let cases = [
+    (Union, true, true, true, false),
+    (Union, true, true, false, true),
+    (Union, true, false, true, false),
+    (Union, true, false, false, true),
+    (Union, false, true, true, false),
+    (Union, false, true, false, false),
+    (Union, false, false, true, true),
+    (Union, false, false, false, true),
+    (Intersect, true, true, true, true),
+    (Intersect, true, true, false, false),
+    (Intersect, true, false, true, true),
+    (Intersect, true, false, false, false),
+    (Intersect, false, true, true, true),
+    (Intersect, false, true, false, true),
+    (Intersect, false, false, true, false),
+    (Intersect, false, false, false, false),
+    (Difference, true, true, true, false),
+    (Difference, true, true, false, true),
+    (Difference, true, false, true, false),
+    (Difference, true, false, false, true),
+    (Difference, false, true, true, true),
+    (Difference, false, true, false, true),
+    (Difference, false, false, true, false),
+    (Difference, false, false, false, false),
+  ];
+  for (op, left_hit, in_left, in_right, allowed) in cases.iter() {
+    assert_eq!(op.intersection_allowed(*left_hit, *in_left, *in_right), *allowed);
+  }
+}
+
+#[test]
+pub fn csg_filters_list_of_intersections() {
+  let cases = [
+    (CsgOperation::Union, 0, 3),
+    (CsgOperation::Intersection, 1, 2),
+    (CsgOperation::Difference, 0, 1),
+  ];
+  for (op, x0, x1) in cases.iter() {
+    let s1: Arc<dyn Shape> = Arc::new(Sphere::new());
+    let s2: Arc<dyn Shape> = Arc::new(Cube::new());
+    let c = Csg::new(*op, s1.clone(), s2.clone()).into_shape();
+    let xs = vec![
+      Intersection::new(&s1, 1.),
+      Intersection::new(&s2, 2.),
+      Intersection::new(&s1, 3.),
+      Intersection::new(&s2, 4.),
+    ];
+    let ts: Vec<f64> = xs.iter().map(|i| i.t).collect();
+    let result = c.filter_intersections(xs);
+    assert_eq!(result.len(), 2);
+    assert_eq!(result[0].t, ts[*x0]);
+    assert_eq!(result[1].t, ts[*x1]);
+  }
+}
+
+#[test]
+pub fn ray_misses_csg_object() {
+  let c = Csg::new(CsgOperation::Union, Arc::new(Sphere::new()), Arc::new(Cube::new())).into_shape();
+  let r = Ray::new(&Tuple::point(0., 2., -5.), &Tuple::vector(0., 0., 1.));
+  assert_eq!(c.intersect(&r).len(), 0);
+}
+
+#[test]
+pub fn ray_hits_csg_object() {
+  let s1: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let mut s2 = Sphere::new();
+  s2.set_transform(Matrix4::translation(0., 0., 0.5));
+  let s2: Arc<dyn Shape> = Arc::new(s2);
+  let c = Csg::new(CsgOperation::Union, s1.clone(), s2.clone()).into_shape();
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let xs = c.intersect(&r);
+  assert_eq!(xs.len(), 2);
+  assert!(util::equal(xs[0].t, 4.));
+  assert_eq!(xs[0].shape.get_id(), s1.get_id());
+  assert!(util::equal(xs[1].t, 6.5));
+  assert_eq!(xs[1].shape.get_id(), s2.get_id());
+}
+
+#[test]
+pub fn csg_includes_shapes_nested_in_groups() {
+  let inner: Arc<dyn Shape> = Arc::new(Sphere::new());
+  let g = Arc::new(Group::new());
+  Group::add_child(&g, inner.clone());
+  let other: Arc<dyn Shape> = Arc::new(Cube::new());
+  let c = Csg::new(CsgOperation::Difference, g.clone(), other.clone()).into_shape();
+  assert!(g.includes(inner.as_ref()));
+  assert!(c.includes(inner.as_ref()));
+  assert!(c.includes(other.as_ref()));
+  assert!(!c.left.includes(other.as_ref()));
+  assert!(!c.includes(&Sphere::new()));
+}
+
+#[test]
+pub fn csg_difference_carves_shape_with_group_operand() {
+  // A cube with a sphere nested in a group subtracted from it: the ray enters
+  // the cube, then exits at the sphere's near side
+  let g = Arc::new(Group::new());
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::scale(0.5, 0.5, 0.5));
+  Group::add_child(&g, Arc::new(s));
+  let c = Csg::new(CsgOperation::Difference, Arc::new(Cube::new()), g).into_shape();
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  let xs = c.intersect(&r);
+  let ts: Vec<f64> = xs.iter().map(|i| i.t).collect();
+  assert_eq!(ts, vec![4., 4.5, 5.5, 6.]);
+}
+
+#[test]
+pub fn empty_bounding_box() {
+  let b = BoundingBox::empty();
+  assert!(b.is_empty());
+  assert_eq!(b.min.x, f64::INFINITY);
+  assert_eq!(b.max.x, f64::NEG_INFINITY);
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  assert!(!b.intersects(&r));
+}
+
+#[test]
+pub fn adding_points_and_boxes_to_bounding_box() {
+  let mut b = BoundingBox::empty();
+  b.add_point(&Tuple::point(-5., 2., 0.));
+  b.add_point(&Tuple::point(7., 0., -3.));
+  assert_eq!(b.min, Tuple::point(-5., 0., -3.));
+  assert_eq!(b.max, Tuple::point(7., 2., 0.));
+
+  let mut b1 = BoundingBox::new(&Tuple::point(-5., -2., 0.), &Tuple::point(7., 4., 4.));
+  b1.merge(&BoundingBox::new(&Tuple::point(8., -7., -2.), &Tuple::point(14., 2., 8.)));
+  assert_eq!(b1.min, Tuple::point(-5., -7., -2.));
+  assert_eq!(b1.max, Tuple::point(14., 4., 8.));
+}
+
+#[test]
+pub fn bounding_box_contains_points_and_boxes() {
+  let b = BoundingBox::new(&Tuple::point(5., -2., 0.), &Tuple::point(11., 4., 7.));
+  assert!(b.contains_point(&Tuple::point(5., -2., 0.)));
+  assert!(b.contains_point(&Tuple::point(8., 1., 3.)));
+  assert!(!b.contains_point(&Tuple::point(3., 0., 3.)));
+  assert!(!b.contains_point(&Tuple::point(8., -4., 3.)));
+  assert!(!b.contains_point(&Tuple::point(8., 1., 8.)));
+
+  assert!(b.contains_box(&BoundingBox::new(&Tuple::point(6., -1., 1.), &Tuple::point(10., 3., 6.))));
+  assert!(!b.contains_box(&BoundingBox::new(&Tuple::point(4., -3., -1.), &Tuple::point(10., 3., 6.))));
+}
+
+#[test]
+pub fn transforming_bounding_box() {
+  let b = BoundingBox::new(&Tuple::point(-1., -1., -1.), &Tuple::point(1., 1., 1.));
+  let m = Matrix4::rotation_x(consts::PI / 4.) * Matrix4::rotation_y(consts::PI / 4.);
+  let b2 = b.transform(&m);
+  assert_eq!(b2.min, Tuple::point(-1.41421, -1.70710, -1.70710));
+  assert_eq!(b2.max, Tuple::point(1.41421, 1.70710, 1.70710));
+}
+
+#[test]
+pub fn transforming_infinite_bounding_box() {
+  let plane = Plane::new();
+  // Rotating a plane about y keeps it flat along y
+  let b = plane.bounds().transform(&Matrix4::rotation_y(consts::PI / 4.));
+  assert_eq!(b.min.y, 0.);
+  assert_eq!(b.max.y, 0.);
+  assert_eq!(b.min.x, f64::NEG_INFINITY);
+  assert_eq!(b.max.z, f64::INFINITY);
+  // but tilting it makes it unbounded along every axis
+  let b = plane.bounds().transform(&Matrix4::rotation_x(consts::PI / 4.));
+  assert_eq!(b.min.y, f64::NEG_INFINITY);
+  assert_eq!(b.max.y, f64::INFINITY);
+}
+
+#[test]
+pub fn ray_intersects_bounding_box() {
+  let b = BoundingBox::new(&Tuple::point(5., -2., 0.), &Tuple::point(11., 4., 7.));
+  let cases = [
+    (Tuple::point(15., 1., 2.), Tuple::vector(-1., 0., 0.), true),
+    (Tuple::point(-5., -1., 4.), Tuple::vector(1., 0., 0.), true),
+    (Tuple::point(7., 6., 5.), Tuple::vector(0., -1., 0.), true),
+    (Tuple::point(9., -5., 6.), Tuple::vector(0., 1., 0.), true),
+    (Tuple::point(8., 2., 12.), Tuple::vector(0., 0., -1.), true),
+    (Tuple::point(6., 0., -5.), Tuple::vector(0., 0., 1.), true),
+    (Tuple::point(8., 1., 3.5), Tuple::vector(0., 0., 1.), true),
+    (Tuple::point(9., -1., -8.), Tuple::vector(2., 4., 6.), false),
+    (Tuple::point(8., 3., -4.), Tuple::vector(6., 2., 4.), false),
+    (Tuple::point(9., -1., -2.), Tuple::vector(4., 6., 2.), false),
+    (Tuple::point(4., 0., 9.), Tuple::vector(0., 0., -1.), false),
+    (Tuple::point(8., 6., -1.), Tuple::vector(0., -1., 0.), false),
+    (Tuple::point(12., 5., 4.), Tuple::vector(-1., 0., 0.), false),
+    // boxes behind the origin still count, since the whole line is tested
+    (Tuple::point(8., 1., 20.), Tuple::vector(0., 0., 1.), true),
+  ];
+  for (origin, direction, expected) in cases.iter() {
+    let r = Ray::new(origin, &direction.normalize());
+    assert_eq!(b.intersects(&r), *expected);
+  }
+}
+
+#[test]
+pub fn shapes_have_bounding_boxes() {
+  let b = Sphere::new().bounds();
+  assert_eq!(b.min, Tuple::point(-1., -1., -1.));
+  assert_eq!(b.max, Tuple::point(1., 1., 1.));
+
+  let b = Cube::new().bounds();
+  assert_eq!(b.min, Tuple::point(-1., -1., -1.));
+  assert_eq!(b.max, Tuple::point(1., 1., 1.));
+
+  let b = Plane::new().bounds();
+  assert!(b.is_infinite());
+  assert_eq!(b.min.y, 0.);
+  assert_eq!(b.max.y, 0.);
+
+  let mut cyl = Cylinder::new();
+  assert!(cyl.bounds().is_infinite());
+  cyl.set_minimum(-5.);
+  cyl.set_maximum(3.);
+  assert_eq!(cyl.bounds().min, Tuple::point(-1., -5., -1.));
+  assert_eq!(cyl.bounds().max, Tuple::point(1., 3., 1.));
+
+  let mut cone = Cone::new();
+  cone.set_minimum(-5.);
+  cone.set_maximum(3.);
+  assert_eq!(cone.bounds().min, Tuple::point(-5., -5., -5.));
+  assert_eq!(cone.bounds().max, Tuple::point(5., 3., 5.));
+
+  let t = Triangle::new(
+    &Tuple::point(-3., 7., 2.),
+    &Tuple::point(6., 2., -4.),
+    &Tuple::point(2., -1., -1.),
+  );
+  assert_eq!(t.bounds().min, Tuple::point(-3., -1., -4.));
+  assert_eq!(t.bounds().max, Tuple::point(6., 7., 2.));
+}
+
+#[test]
+pub fn shape_bounds_in_parent_space() {
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(1., -3., 5.) * Matrix4::scale(0.5, 2., 4.));
+  let b = parent_space_bounds(&s);
+  assert_eq!(b.min, Tuple::point(0.5, -5., 1.));
+  assert_eq!(b.max, Tuple::point(1.5, -1., 9.));
+}
+
+#[test]
+pub fn group_and_csg_bounds_contain_their_children() {
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(2., 5., -3.) * Matrix4::scale(2., 2., 2.));
+  let mut c = Cylinder::new();
+  c.set_minimum(-2.);
+  c.set_maximum(2.);
+  c.set_transform(Matrix4::translation(-4., -1., 4.) * Matrix4::scale(0.5, 1., 0.5));
+  let (s, c): (Arc<dyn Shape>, Arc<dyn Shape>) = (Arc::new(s), Arc::new(c));
+
+  let g = Arc::new(Group::new());
+  Group::add_child(&g, s.clone());
+  Group::add_child(&g, c.clone());
+  let b = g.bounds();
+  assert_eq!(b.min, Tuple::point(-4.5, -3., -5.));
+  assert_eq!(b.max, Tuple::point(4., 7., 4.5));
+
+  let csg = Csg::new(CsgOperation::Difference, s, c).into_shape();
+  let b = csg.bounds();
+  assert_eq!(b.min, Tuple::point(-4.5, -3., -5.));
+  assert_eq!(b.max, Tuple::point(4., 7., 4.5));
+}
+
+#[test]
+pub fn group_bounds_follow_added_children() {
+  let g = Arc::new(Group::new());
+  Group::add_child(&g, Arc::new(Sphere::new()));
+  assert_eq!(g.bounds().max, Tuple::point(1., 1., 1.));
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(5., 0., 0.));
+  Group::add_child(&g, Arc::new(s));
+  assert_eq!(g.bounds().max, Tuple::point(6., 1., 1.));
+}
+
+// A row of n unit spheres spaced three units apart along x
+fn row_of_spheres(n: usize) -> Vec<Arc<dyn Shape>> {
+  (0..n)
+    .map(|i| {
+      let mut s = Sphere::new();
+      s.set_transform(Matrix4::translation(3. * i as f64, 0., 0.));
+      Arc::new(s) as Arc<dyn Shape>
+    })
+    .collect()
+}
+
+#[test]
+pub fn bvh_splits_shapes_into_subtrees() {
+  let bvh = Bvh::new(&row_of_spheres(64));
+  assert!(bvh.leaf_count() > 1);
+  assert!(bvh.depth() <= 10);
+  let b = bvh.bounds();
+  assert!(b.contains_point(&Tuple::point(-1., -1., -1.)));
+  assert!(b.contains_point(&Tuple::point(190., 1., 1.)));
+}
+
+#[test]
+pub fn bvh_matches_brute_force_intersections() {
+  let mut shapes = row_of_spheres(20);
+  shapes.push(Arc::new(Plane::new()));
+  let bvh = Bvh::new(&shapes);
+  let rays = [
+    Ray::new(&Tuple::point(-5., 0., 0.), &Tuple::vector(1., 0., 0.)),
+    Ray::new(&Tuple::point(9., 5., 0.), &Tuple::vector(0., -1., 0.)),
+    Ray::new(&Tuple::point(10., 0.5, -5.), &Tuple::vector(0.2, 0., 1.).normalize()),
+    Ray::new(&Tuple::point(0., 5., -5.), &Tuple::vector(0., 0., 1.)),
+  ];
+  for r in rays.iter() {
+    let mut expected: Vec<Intersection> = shapes.iter().flat_map(|s| s.clone().intersect(r)).collect();
+    expected.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+    let xs = bvh.intersect(r);
+    assert_eq!(xs.len(), expected.len());
+    for (x, e) in xs.iter().zip(expected.iter()) {
+      assert_eq!(x.t, e.t);
+      assert_eq!(x.shape.get_id(), e.shape.get_id());
+    }
+  }
+}
+
+#[test]
+pub fn bvh_skips_shapes_whose_boxes_are_missed() {
+  let mut near = TestShape::new();
+  near.set_transform(Matrix4::translation(-5., 0., 0.));
+  let mut far = TestShape::new();
+  far.set_transform(Matrix4::translation(5., 0., 0.));
+  let (near, far) = (Arc::new(near), Arc::new(far));
+  let shapes: Vec<Arc<dyn Shape>> = vec![near.clone(), far.clone()];
+  let bvh = Bvh::new(&shapes);
+  let r = Ray::new(&Tuple::point(-5., 0., -5.), &Tuple::vector(0., 0., 1.));
+  bvh.intersect(&r);
+  assert!(near.was_intersected());
+  assert!(!far.was_intersected());
+}
+
+#[test]
+pub fn group_skips_children_whose_boxes_are_missed() {
+  let g = Arc::new(Group::new());
+  let mut children = Vec::new();
+  for i in 0..8 {
+    let mut s = TestShape::new();
+    s.set_transform(Matrix4::translation(3. * i as f64, 0., 0.));
+    let s = Arc::new(s);
+    Group::add_child(&g, s.clone());
+    children.push(s);
+  }
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  g.intersect(&r);
+  assert!(children[0].was_intersected());
+  assert!(children[1..].iter().all(|c| !c.was_intersected()));
+}
+
+#[test]
+pub fn world_rebuilds_bvh_when_shapes_change() {
+  let mut w = World::new();
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  assert_eq!(w.intersect_world(&r).len(), 0);
+  w.shapes.push(Arc::new(Sphere::new()));
+  assert_eq!(w.intersect_world(&r).len(), 2);
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(10., 0., 0.));
+  w.shapes[0] = Arc::new(s);
+  assert_eq!(w.intersect_world(&r).len(), 0);
+}
+
+#[test]
+pub fn bvh_is_rebuilt_after_groups_gain_children() {
+  let mut w = World::new();
+  let outer = Arc::new(Group::new());
+  let inner = Arc::new(Group::new());
+  Group::add_child(&outer, inner.clone());
+  w.shapes.push(outer.clone());
+  let r = Ray::new(&Tuple::point(0., 0., -5.), &Tuple::vector(0., 0., 1.));
+  assert_eq!(w.intersect_world(&r).len(), 0);
+  // the empty groups were left out of both hierarchies when they were built
+  Group::add_child(&inner, Arc::new(Sphere::new()));
+  assert_eq!(w.intersect_world(&r).len(), 2);
+
+  // changes to shapes outside a hierarchy leave it as it was
+  let bvh = Bvh::new(&w.shapes);
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::translation(1., 0., 0.));
+  assert!(bvh.is_built_from(&w.shapes));
+  // while changes deep inside it are passed up to the top
+  Group::add_child(&inner, Arc::new(Sphere::new()));
+  assert!(!bvh.is_built_from(&w.shapes));
+}
+
+#[test]
+pub fn shape_generation_changes_with_its_bounds() {
+  let mut cyl = Cylinder::new();
+  let generation = cyl.generation();
+  cyl.set_minimum(-1.);
+  assert!(cyl.generation() > generation);
+  let generation = cyl.generation();
+  cyl.set_maximum(1.);
+  assert!(cyl.generation() > generation);
+  let generation = cyl.generation();
+  cyl.closed = true;
+  cyl.set_material(Material::new());
+  assert_eq!(cyl.generation(), generation);
+  cyl.set_transform(Matrix4::scale(2., 2., 2.));
+  assert!(cyl.generation() > generation);
+}
+
+#[test]
+pub fn scene_graph_can_be_shared_between_threads() {
+  fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+  assert_send_sync::<World>();
+  assert_send_sync::<dyn Shape>();
+  assert_send_sync::<dyn Light>();
+  assert_send_sync::<dyn Pattern>();
+  assert_send_sync::<Material>();
+  assert_send_sync::<Group>();
+}
+
+// A small scene exercising reflection, refraction, soft shadows, patterns and
+// groups, for comparing renders
+fn busy_world() -> World {
+  let mut w = World::new();
+  let mut floor = Plane::new();
+  let mut m = Material::new();
+  m.pattern = Some(Arc::new(TestPattern::new()));
+  m.reflective = 0.3;
+  floor.set_material(m);
+  floor.set_transform(Matrix4::translation(0., -1., 0.));
+
+  let mut glass = glass_sphere();
+  glass.set_transform(Matrix4::translation(-0.5, 0., 0.));
+  let mut mat = glass_material();
+  mat.reflective = 0.9;
+  glass.set_material(mat);
+
+  let g = Arc::new(Group::new());
+  Group::add_child(
+    &g,
+    Arc::new(Triangle::new(
+      &Tuple::point(1., -1., 1.),
+      &Tuple::point(2., 1., 1.),
+      &Tuple::point(3., -1., 1.),
+    )),
+  );
+  let mut cube = Cube::new();
+  cube.set_transform(Matrix4::translation(2., 0., 3.) * Matrix4::scale(0.5, 0.5, 0.5));
+  Group::add_child(&g, Arc::new(cube));
+
+  w.shapes = vec![Arc::new(floor), Arc::new(glass), g];
+  let mut light = AreaLight::new(
+    &Tuple::point(-5., 5., -5.),
+    &Tuple::vector(2., 0., 0.),
+    2,
+    &Tuple::vector(0., 2., 0.),
+    2,
+    &Color::new(1., 1., 1.),
+  );
+  light.jitter = true;
+  w.lights = vec![Arc::new(light)];
+  w
+}
+
+#[test]
+pub fn tiled_render_matches_serial_render() {
+  let w = busy_world();
+  // an odd size so the last row and column of tiles are partial
+  let mut c = Camera::new(37, 21, consts::PI / 2.);
+  c.set_transform(Camera::view_transform(
+    &Tuple::point(0., 1., -5.),
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 1., 0.),
+  ));
+  let serial = c.render_serial(&w);
+  for workers in [1, 3, 8].iter() {
+    c.threads = *workers;
+    let tiled = c.render(&w);
+    for y in 0..c.vsize {
+      for x in 0..c.hsize {
+        let (a, b) = (serial.pixel_at(x, y), tiled.pixel_at(x, y));
+        assert_eq!(a.r().to_bits(), b.r().to_bits());
+        assert_eq!(a.g().to_bits(), b.g().to_bits());
+        assert_eq!(a.b().to_bits(), b.b().to_bits());
+      }
+    }
+  }
+}
+
+#[test]
+pub fn setting_shape_transform_caches_its_inverse() {
+  let mut s = Sphere::new();
+  assert_eq!(*s.get_inverse(), Matrix4::identity());
+  let m = Matrix4::translation(1., 2., 3.) * Matrix4::rotation_y(consts::PI / 3.);
+  s.set_transform(m);
+  assert_eq!(*s.get_transform(), m);
+  assert_eq!(*s.get_inverse(), m.invert().unwrap());
+  assert_eq!(*s.get_inverse_transpose(), m.invert().unwrap().transpose());
+}
+
+#[test]
+pub fn setting_camera_transform_caches_its_inverse() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  let m = Matrix4::rotation_y(consts::PI / 4.) * Matrix4::translation(0., -2., 5.);
+  c.set_transform(m);
+  assert_eq!(*c.get_transform(), m);
+  // moving the camera again must not leave a stale inverse behind
+  c.set_transform(Matrix4::identity());
+  let r = c.ray_for_pixel(100, 50);
+  assert_eq!(r.origin, Tuple::point(0., 0., 0.));
+  assert_eq!(r.direction, Tuple::vector(0., 0., -1.));
+}
+
+#[test]
+#[should_panic(expected = "not invertible")]
+pub fn singular_shape_transform_is_rejected() {
+  let mut s = Sphere::new();
+  s.set_transform(Matrix4::scale(0., 1., 1.));
+}
+
+#[test]
+pub fn seeded_rng_is_repeatable() {
+  let mut a = util::Rng::new(42);
+  let mut b = util::Rng::new(42);
+  let mut c = util::Rng::new(43);
+  let xs: Vec<f64> = (0..100).map(|_| a.next_f64()).collect();
+  let ys: Vec<f64> = (0..100).map(|_| b.next_f64()).collect();
+  let zs: Vec<f64> = (0..100).map(|_| c.next_f64()).collect();
+  assert_eq!(xs, ys);
+  assert_ne!(xs, zs);
+  assert!(xs.iter().all(|x| (0.0..1.0).contains(x)));
+}
+
+#[test]
+pub fn single_sample_goes_through_pixel_center() {
+  let c = Camera::new(201, 101, consts::PI / 2.);
+  assert_eq!(c.samples, 1);
+  assert_eq!(c.pixel_samples(100, 50), vec![(0.5, 0.5)]);
+  let r = c.ray_for_pixel_offset(100, 50, 0.5, 0.5);
+  assert_eq!(r.direction, c.ray_for_pixel(100, 50).direction);
+}
+
+#[test]
+pub fn unjittered_samples_sit_at_cell_centers() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  c.samples = 4;
+  c.jitter = false;
+  assert_eq!(
+    c.pixel_samples(3, 7),
+    vec![(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+  );
+}
+
+#[test]
+pub fn sample_cells_cover_the_pixel_evenly() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  for count in [2, 3, 5, 7].iter() {
+    c.samples = *count;
+    let cells = c.pixel_cells();
+    assert_eq!(cells.len(), *count);
+    for (x0, y0, x1, y1) in cells.iter() {
+      assert!(util::equal((x1 - x0) * (y1 - y0), 1. / *count as f64));
+    }
+    // every point of the pixel lies in exactly one cell
+    for i in 0..50 {
+      for j in 0..50 {
+        let (x, y) = ((i as f64 + 0.5) / 50., (j as f64 + 0.5) / 50.);
+        let inside = cells
+          .iter()
+          .filter(|(x0, y0, x1, y1)| x >= *x0 && x < *x1 && y >= *y0 && y < *y1)
+          .count();
+        assert_eq!(inside, 1, "{} samples, point ({}, {})", count, x, y);
+      }
+    }
+    // and each sample is placed in a cell of its own
+    let samples = c.pixel_samples(3, 7);
+    for ((dx, dy), (x0, y0, x1, y1)) in samples.iter().zip(cells.iter()) {
+      assert!(dx >= x0 && dx < x1 && dy >= y0 && dy < y1);
+    }
+  }
+}
+
+#[test]
+pub fn jittered_samples_are_stratified_and_seeded() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  c.samples = 6;
+  let samples = c.pixel_samples(3, 7);
+  assert_eq!(samples.len(), 6);
+  // six samples are laid out as two rows of three cells
+  for (i, (dx, dy)) in samples.iter().enumerate() {
+    let (row, column) = ((i / 3) as f64, (i % 3) as f64);
+    assert!(*dx >= column / 3. && *dx < (column + 1.) / 3.);
+    assert!(*dy >= row / 2. && *dy < (row + 1.) / 2.);
+  }
+  assert_eq!(c.pixel_samples(3, 7), samples);
+  assert_ne!(c.pixel_samples(4, 7), samples);
+  c.seed = 1;
+  assert_ne!(c.pixel_samples(3, 7), samples);
+}
+
+// A flat white sphere on black: every ray either sees 1.0 or 0.0
+fn silhouette_scene() -> (World, Camera) {
+  let mut w = World::new();
+  let mut s = Sphere::new();
+  let mut m = Material::new();
+  m.ambient = 1.;
+  s.set_material(m);
+  w.shapes.push(Arc::new(s));
+  let mut c = Camera::new(21, 21, consts::PI / 2.);
+  c.set_transform(Camera::view_transform(
+    &Tuple::point(0., 0., -2.5),
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 1., 0.),
+  ));
+  (w, c)
+}
+
+#[test]
+pub fn supersampling_smooths_silhouettes() {
+  let (w, mut c) = silhouette_scene();
+  c.threads = 1;
+  let aliased = c.render(&w);
+  c.samples = 16;
+  let smooth = c.render(&w);
+
+  let is_extreme = |v: f64| util::equal(v, 0.) || util::equal(v, 1.);
+  let mut edge_pixels = 0;
+  for y in 0..21 {
+    for x in 0..21 {
+      assert!(is_extreme(aliased.pixel_at(x, y).r()));
+      let v = smooth.pixel_at(x, y).r();
+      if !is_extreme(v) {
+        edge_pixels += 1;
+        assert!(v > 0. && v < 1.);
+      }
+    }
+  }
+  assert!(edge_pixels > 0);
+  // the middle of the sphere and the corners are unaffected
+  assert!(util::equal(smooth.pixel_at(10, 10).r(), 1.));
+  assert!(util::equal(smooth.pixel_at(0, 0).r(), 0.));
+}
+
+#[test]
+pub fn adaptive_sampling_only_refines_edges() {
+  let (w, mut c) = silhouette_scene();
+  c.threads = 1;
+  let result = c.render_adaptive(&w, &AdaptiveSampling::new(0.1, 2));
+  assert_eq!(result.sample_counts.len(), 21 * 21);
+  // flat regions keep their single sample
+  assert_eq!(result.samples_at(10, 10), 1);
+  assert_eq!(result.samples_at(0, 0), 1);
+  assert!(util::equal(result.canvas.pixel_at(10, 10).r(), 1.));
+
+  let mut edge_pixels = 0;
+  for y in 0..21 {
+    for x in 0..21 {
+      let samples = result.samples_at(x, y);
+      // one center sample, four quarters, and sixteen more at most
+      assert!(samples == 1 || (5..=21).contains(&samples));
+      let v = result.canvas.pixel_at(x, y).r();
+      if !util::equal(v, 0.) && !util::equal(v, 1.) {
+        edge_pixels += 1;
+        assert!(samples > 1);
+      }
+    }
+  }
+  assert!(edge_pixels > 0);
+}
+
+#[test]
+pub fn adaptive_sampling_uses_every_ray_it_counts() {
+  let (w, mut c) = silhouette_scene();
+  c.threads = 1;
+  let result = c.render_adaptive(&w, &AdaptiveSampling::new(0.1, 1));
+  let trace = |x, y, dx, dy| w.color_at(&c.ray_for_pixel_offset(x, y, dx, dy), c.depth);
+  let mut refined = 0;
+  for y in 0..21 {
+    for x in 0..21 {
+      if result.samples_at(x, y) == 1 {
+        continue;
+      }
+      // the first center sample and the four quarters, weighted alike
+      assert_eq!(result.samples_at(x, y), 5);
+      let expected = [(0.5, 0.5), (0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)]
+        .iter()
+        .fold(Color::new(0., 0., 0.), |sum, (dx, dy)| sum + &trace(x, y, *dx, *dy));
+      assert_eq!(*result.canvas.pixel_at(x, y), expected * 0.2);
+      refined += 1;
+    }
+  }
+  assert!(refined > 0);
+}
+
+#[test]
+pub fn adaptive_debug_canvas_shows_sample_counts() {
+  let (w, mut c) = silhouette_scene();
+  c.threads = 1;
+  let result = c.render_adaptive(&w, &AdaptiveSampling::new(0.1, 2));
+  let debug = result.debug_canvas();
+  let max = *result.sample_counts.iter().max().unwrap();
+  assert!(max > 1);
+  for y in 0..21 {
+    for x in 0..21 {
+      let expected = result.samples_at(x, y) as f64 / max as f64;
+      assert_eq!(*debug.pixel_at(x, y), Color::new(expected, expected, expected));
+    }
+  }
+  assert!(debug.pixel_at(10, 10).r() < 1.);
+}
+
+#[test]
+pub fn adaptive_render_is_used_when_configured() {
+  let w = busy_world();
+  let mut c = Camera::new(37, 21, consts::PI / 2.);
+  c.set_transform(Camera::view_transform(
+    &Tuple::point(0., 1., -5.),
+    &Tuple::point(0., 0., 0.),
+    &Tuple::vector(0., 1., 0.),
+  ));
+  let settings = AdaptiveSampling::new(0.2, 2);
+  c.threads = 1;
+  let serial = c.render_adaptive(&w, &settings);
+  c.threads = 4;
+  c.adaptive = Some(settings);
+  let threaded = c.render(&w);
+  for y in 0..c.vsize {
+    for x in 0..c.hsize {
+      let (a, b) = (serial.canvas.pixel_at(x, y), threaded.pixel_at(x, y));
+      assert_eq!(a.r().to_bits(), b.r().to_bits());
+      assert_eq!(a.g().to_bits(), b.g().to_bits());
+      assert_eq!(a.b().to_bits(), b.b().to_bits());
+    }
+  }
+}
+
+#[test]
+pub fn lens_center_gives_pinhole_ray() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  c.set_transform(Matrix4::rotation_y(consts::PI / 4.) * Matrix4::translation(0., -2., 5.));
+  let pinhole = c.ray_for_pixel(0, 0);
+  c.aperture = 0.5;
+  c.focal_distance = 3.;
+  let r = c.ray_through_lens(0, 0, 0.5, 0.5, 0.5, 0.5);
+  assert_eq!(r.origin, pinhole.origin);
+  assert_eq!(r.direction, pinhole.direction);
+}
+
+#[test]
+pub fn lens_rays_meet_on_focal_plane() {
+  let mut c = Camera::new(201, 101, consts::PI / 2.);
+  c.aperture = 0.5;
+  c.focal_distance = 4.;
+  let pinhole = c.ray_for_pixel(30, 80);
+  // the pinhole ray reaches z = -4 after 4 / |z| of its direction
+  let focus = Ray::position(&pinhole, 4. / -pinhole.direction.z);
+  let lens = [(0., 0.), (0.99, 0.5), (0.3, 0.8), (0.5, 0.01)];
+  for (u, v) in lens.iter() {
+    let r = c.ray_through_lens(30, 80, 0.5, 0.5, *u, *v);
+    assert!(util::equal(r.origin.z, 0.));
+    assert!(r.origin.x * r.origin.x + r.origin.y * r.origin.y <= 0.25 + util::EPSILON);
+    let p = Ray::position(&r, (focus.z - r.origin.z) / r.direction.z);
+    assert_eq!(p, focus);
+  }
+  // each sample position in the pixel is given its own point on the lens
+  let a = c.ray_for_pixel_offset(30, 80, 0.25, 0.25);
+  let b = c.ray_for_pixel_offset(30, 80, 0.75, 0.25);
+  assert_ne!(a.origin, b.origin);
+  assert_eq!(c.ray_for_pixel_offset(30, 80, 0.25, 0.25).origin, a.origin);
+}
+
+#[test]
+pub fn depth_of_field_blurs_out_of_focus_silhouettes() {
+  let count_blurred = |aperture: f64, focal_distance: f64| {
+    let (w, mut c) = silhouette_scene();
+    c.threads = 1;
+    c.samples = 16;
+    c.aperture = aperture;
+    c.focal_distance = focal_distance;
+    let canvas = c.render(&w);
+    let mut blurred = 0;
+    for y in 0..21 {
+      for x in 0..21 {
+        let v = canvas.pixel_at(x, y).r();
+        if !util::equal(v, 0.) && !util::equal(v, 1.) {
+          blurred += 1;
+        }
+      }
+    }
+    blurred
+  };
+  // supersampling alone already softens the pixels along the silhouette,
+  // which lies 2.1 in front of the camera
+  let pinhole = count_blurred(0., 1.);
+  let sharp = count_blurred(0.3, 2.1);
+  let blurry = count_blurred(0.3, 8.);
+  assert!(sharp <= pinhole + 4, "{} vs {}", sharp, pinhole);
+  assert!(blurry > pinhole * 3 / 2, "{} vs {}", blurry, pinhole);
+}
+
+#[test]
+pub fn orthographic_rays_are_parallel() {
+  let mut c = Camera::new(200, 100, consts::PI / 2.);
+  c.set_projection(Projection::Orthographic { view_width: 4. });
+  assert_eq!(c.get_projection(), Projection::Orthographic { view_width: 4. });
+  assert!(util::equal(c.pixel_size, 0.02));
+  let r = c.ray_for_pixel(0, 0);
+  assert_eq!(r.origin, Tuple::point(1.99, 0.99, 0.));
+  assert_eq!(r.direction, Tuple::vector(0., 0., -1.));
+  let r = c.ray_for_pixel(199, 99);
+  assert_eq!(r.origin, Tuple::point(-1.99, -0.99, 0.));
+  assert_eq!(r.direction, Tuple::vector(0., 0., -1.));
+
+  c.set_transform(Matrix4::rotation_y(consts::PI / 2.) * Matrix4::translation(0., 0., 5.));
+  let r = c.ray_for_pixel(0, 0);
+  assert_eq!(r.origin, Tuple::point(0., 0.99, -5. + 1.99));
+  assert_eq!(r.direction, Tuple::vector(1., 0., 0.));
+}
+
+#[test]
+pub fn orthographic_camera_keeps_sizes_with_distance() {
+  // the same sphere seen from near and far covers the same pixels
+  let mut w = World::new();
+  let mut s = Sphere::new();
+  let mut m = Material::new();
+  m.ambient = 1.;
+  s.set_material(m);
+  w.shapes.push(Arc::new(s));
+  let mut c = Camera::new(11, 11, consts::PI / 2.);
+  c.threads = 1;
+  c.set_projection(Projection::Orthographic { view_width: 4. });
+  let coverage = |c: &Camera| {
+    let canvas = c.render(&w);
+    (0..121).filter(|i| canvas.pixel_at(i % 11, i / 11).r() > 0.5).count()
+  };
+  c.set_transform(Matrix4::translation(0., 0., -2.));
+  let near = coverage(&c);
+  c.set_transform(Matrix4::translation(0., 0., -20.));
+  let far = coverage(&c);
+  assert!(near > 0);
+  assert_eq!(near, far);
+}
+
+#[test]
+pub fn equirectangular_rays_cover_every_direction() {
+  let mut c = Camera::new(360, 180, consts::PI / 2.);
+  c.set_projection(Projection::Equirectangular);
+  let at = |x: f64, y: f64| c.ray_through_lens(0, 0, x, y, 0.5, 0.5);
+  // the center looks ahead, the left and right edges behind
+  assert_eq!(at(180., 90.).direction, Tuple::vector(0., 0., -1.));
+  assert_eq!(at(90., 90.).direction, Tuple::vector(1., 0., 0.));
+  assert_eq!(at(270., 90.).direction, Tuple::vector(-1., 0., 0.));
+  assert_eq!(at(0., 90.).direction, Tuple::vector(0., 0., 1.));
+  // and the top and bottom edges straight up and down
+  assert_eq!(at(180., 0.).direction, Tuple::vector(0., 1., 0.));
+  assert_eq!(at(100., 180.).direction, Tuple::vector(0., -1., 0.));
+  assert_eq!(at(180., 45.).direction, Tuple::vector(0., 0.70711, -0.70711));
+  for (x, y) in [(0, 0), (45, 30), (359, 179)].iter() {
+    assert_eq!(c.ray_for_pixel(*x, *y).origin, Tuple::point(0., 0., 0.));
+  }
+}
+
+#[test]
+pub fn equirectangular_covers_every_longitude_on_portrait_canvases() {
+  let mut c = Camera::new(90, 180, consts::PI / 2.);
+  c.set_projection(Projection::Equirectangular);
+  let at = |x: f64, y: f64| c.ray_through_lens(0, 0, x, y, 0.5, 0.5);
+  assert_eq!(at(45., 90.).direction, Tuple::vector(0., 0., -1.));
+  assert_eq!(at(22.5, 90.).direction, Tuple::vector(1., 0., 0.));
+  assert_eq!(at(67.5, 90.).direction, Tuple::vector(-1., 0., 0.));
+  assert_eq!(at(0., 90.).direction, Tuple::vector(0., 0., 1.));
+  assert_eq!(at(45., 0.).direction, Tuple::vector(0., 1., 0.));
+}
+
+#[test]
+pub fn fisheye_angle_grows_with_distance_from_center() {
+  let mut c = Camera::new(201, 101, consts::PI);
+  c.set_projection(Projection::Fisheye);
+  assert_eq!(c.ray_for_pixel(100, 50).direction, Tuple::vector(0., 0., -1.));
+  // the left and right edges are 90 degrees off the view direction
+  let r = c.ray_through_lens(0, 50, 0., 0.5, 0.5, 0.5);
+  assert_eq!(r.direction, Tuple::vector(1., 0., 0.));
+  let r = c.ray_through_lens(200, 50, 1., 0.5, 0.5, 0.5);
+  assert_eq!(r.direction, Tuple::vector(-1., 0., 0.));
+  // a quarter of the width above the center is 45 degrees off
+  let r = c.ray_through_lens(100, 0, 0.5, 0.25, 0.5, 0.5);
+  let angle = r.direction.z.abs().acos();
+  assert!(util::equal(angle, consts::PI / 4.));
+  assert!(r.direction.y > 0.);
+}
+
+fn scene_error(input: &str) -> SceneError {
+  match Scene::from_yaml(input) {
+    Ok(_) => panic!("expected an error"),
+    Err(e) => e,
+  }
+}
+
+#[test]
+pub fn yaml_reads_nested_blocks_with_line_numbers() {
+  let input = "# a scene\n- add: camera  # comment\n  from: [ 1, -2.5, [3] ]\n\n- define: t\n  value:\n  - [ scale, 1, 1, 1 ]\n  - name: 'a # b'\n";
+  let document = yaml::parse(input).unwrap().unwrap();
+  let items = document.as_list().unwrap();
+  assert_eq!(items.len(), 2);
+  assert_eq!(items[0].line, 2);
+  assert_eq!(items[0].get("add").unwrap().as_str().unwrap(), "camera");
+  let from = items[0].get("from").unwrap();
+  assert_eq!(from.line, 3);
+  assert_eq!(from.as_list().unwrap()[1].as_f64().unwrap(), -2.5);
+  assert_eq!(from.as_list().unwrap()[2].as_list().unwrap().len(), 1);
+  let value = items[1].get("value").unwrap();
+  assert_eq!(value.line, 6);
+  let steps = value.as_list().unwrap();
+  assert_eq!(steps[0].as_list().unwrap()[0].value, Value::Scalar("scale".to_string()));
+  assert_eq!(steps[1].line, 8);
+  assert_eq!(steps[1].get("name").unwrap().as_str().unwrap(), "a # b");
+  assert_eq!(yaml::parse("# nothing\n\n").unwrap(), None);
+}
+
+#[test]
+pub fn yaml_plain_scalars_keep_commas_and_brackets_outside_flow_lists() {
+  let input = "- file: a,b.obj\n- name: x[1]\n- value: [ a b, c ]\n- 1, 2\n";
+  let document = yaml::parse(input).unwrap().unwrap();
+  let items = document.as_list().unwrap();
+  assert_eq!(items[0].get("file").unwrap().as_str().unwrap(), "a,b.obj");
+  assert_eq!(items[1].get("name").unwrap().as_str().unwrap(), "x[1]");
+  let value = items[2].get("value").unwrap().as_list().unwrap();
+  assert_eq!(value.len(), 2);
+  assert_eq!(value[0].as_str().unwrap(), "a b");
+  assert_eq!(items[3].as_str().unwrap(), "1, 2");
+}
+
+#[test]
+pub fn yaml_errors_report_the_line() {
+  let cases = [
+    ("- a: [ 1, 2\n", 1, "missing ']'"),
+    ("a: 1\nb:\nc: 2\n", 2, "'b' has no value"),
+    ("a: 1\n  b: 2\n", 2, "unexpected indentation"),
+    ("a: 1\nb: 2\na: 3\n", 3, "'a' is given twice"),
+    ("a:\n\t- 1\n", 2, "tabs cannot be used for indentation"),
+  ];
+  for (input, line, message) in cases.iter() {
+    let e = yaml::parse(input).err().unwrap();
+    assert_eq!((e.line, e.message.as_str()), (*line, *message));
+  }
+  let node = Node {
+    line: 4,
+    value: Value::Scalar("wide".to_string()),
+  };
+  assert_eq!(
+    node.as_f64().err().unwrap().to_string(),
+    "line 4: expected a number, found 'wide'"
+  );
+}
+
+#[test]
+pub fn scene_reads_camera_and_lights() {
+  let scene = Scene::from_yaml(
+    "
+- add: camera
+  width: 100
+  height: 50
+  field-of-view: 0.785
+  from: [ 0, 0, -5 ]
+  to: [ 0, 0, 0 ]
+  up: [ 0, 1, 0 ]
+- add: light
+  at: [ -10, 10, -10 ]
+  intensity: [ 1, 0.5, 0.25 ]
+",
+  )
+  .unwrap();
+  let camera = scene.camera.unwrap();
+  assert_eq!((camera.hsize, camera.vsize), (100, 50));
+  assert!(util::equal(camera.field_of_view, 0.785));
+  assert_eq!(
+    *camera.get_transform(),
+    Camera::view_transform(
+      &Tuple::point(0., 0., -5.),
+      &Tuple::point(0., 0., 0.),
+      &Tuple::vector(0., 1., 0.)
+    )
+  );
+  assert_eq!(scene.world.lights.len(), 1);
+  assert_eq!(scene.world.lights[0].intensity(), Color::new(1., 0.5, 0.25));
+  assert!(scene.world.shapes.is_empty());
+}
+
+#[test]
+pub fn scene_applies_transforms_in_order() {
+  let scene = Scene::from_yaml(
+    "
+- define: standard
+  value:
+    - [ scale, 2, 2, 2 ]
+- add: sphere
+  transform:
+    - standard
+    - [ translate, 1, 0, 0 ]
+    - [ rotate-y, 1.5707963267948966 ]
+- add: plane
+",
+  )
+  .unwrap();
+  assert!(scene.camera.is_none());
+  assert_eq!(scene.world.shapes.len(), 2);
+  let expected = Matrix4::rotation_y(consts::PI / 2.)
+    * Matrix4::translation(1., 0., 0.)
+    * Matrix4::scale(2., 2., 2.);
+  assert_eq!(*scene.world.shapes[0].get_transform(), expected);
+  assert_eq!(*scene.world.shapes[1].get_transform(), Matrix4::identity());
+}
+
+#[test]
+pub fn scene_definitions_extend_materials() {
+  let scene = Scene::from_yaml(
+    "
+- define: white
+  value:
+    color: [ 1, 1, 1 ]
+    diffuse: 0.7
+    reflective: 0.1
+- define: blue
+  extend: white
+  value:
+    color: [ 0, 0, 1 ]
+    refractive-index: 1.5
+- add: cube
+  material: blue
+- add: cylinder
+  min: -1
+  max: 2
+  closed: true
+  material:
+    ambient: 0.3
+    pattern:
+      type: stripes
+      colors:
+        - [ 1, 1, 1 ]
+        - [ 0, 0, 0 ]
+      transform:
+        - [ scale, 0.5, 0.5, 0.5 ]
+",
+  )
+  .unwrap();
+  let blue = scene.world.shapes[0].get_material();
+  assert_eq!(blue.color, Color::new(0., 0., 1.));
+  assert!(util::equal(blue.diffuse, 0.7));
+  assert!(util::equal(blue.reflective, 0.1));
+  assert!(util::equal(blue.refractive_index, 1.5));
+  assert!(util::equal(blue.specular, Material::new().specular));
+
+  let cylinder = &scene.world.shapes[1];
+  let bounds = cylinder.bounds();
+  assert!(util::equal(bounds.min.y, -1.) && util::equal(bounds.max.y, 2.));
+  let material = cylinder.get_material();
+  assert!(util::equal(material.ambient, 0.3));
+  let pattern = material.pattern.as_ref().unwrap();
+  assert_eq!(
+    pattern.pattern_at_shape(cylinder.clone(), &Tuple::point(0.25, 0., 0.)),
+    Color::new(1., 1., 1.)
+  );
+  assert_eq!(
+    pattern.pattern_at_shape(cylinder.clone(), &Tuple::point(0.75, 0., 0.)),
+    Color::new(0., 0., 0.)
+  );
+}
+
+#[test]
+pub fn scene_errors_report_the_line() {
+  let cases = [
+    ("- add: teapot\n", 1, "unknown object 'teapot'"),
+    ("- add: sphere\n  colour: [ 1, 0, 0 ]\n", 2, "unexpected key 'colour'"),
+    ("- add: light\n  at: [ 1, 2 ]\n  intensity: [ 1, 1, 1 ]\n", 2, "expected 3 numbers, found 2"),
+    ("- add: light\n  at: [ 1, 2, 3 ]\n", 1, "missing 'intensity'"),
+    ("\n- add: sphere\n  material: shiny\n", 3, "'shiny' has not been defined"),
+    ("- add: cone\n  transform:\n    - [ spin, 1 ]\n", 3, "unknown transformation 'spin'"),
+    ("- add: cone\n  transform:\n    - [ scale, 1 ]\n", 3, "scale takes 3 numbers, found 1"),
+    ("- add: plane\n  material:\n    shine: 1\n", 3, "unknown material property 'shine'"),
+    ("- define: a\n  value: [ b ]\n- define: b\n  value: [ a ]\n- add: cube\n  transform: [ a ]\n", 4, "'a' refers to itself"),
+    ("- define: a\n  value: [ a ]\n- add: cube\n  transform: [ a ]\n", 2, "'a' refers to itself"),
+  ];
+  let camera = |width: &str, from: &str, up: &str| {
+    format!(
+      "- add: camera\n  width: {}\n  height: 10\n  field-of-view: 1\n  from: {}\n  to: [ 0, 0, 0 ]\n  up: {}\n",
+      width, from, up
+    )
+  };
+  let cameras = [
+    (camera("0", "[ 0, 0, -5 ]", "[ 0, 1, 0 ]"), 2, "expected a whole number above 0, found '0'"),
+    (camera("10", "[ 0, 0, 0 ]", "[ 0, 1, 0 ]"), 1, "the camera must look from a different point than 'to', with 'up' not along the view"),
+    (camera("10", "[ 0, 0, -5 ]", "[ 0, 0, 1 ]"), 1, "the camera must look from a different point than 'to', with 'up' not along the view"),
+    ("- add: sphere\n  transform:\n    - [ translate, 1, 0, 0 ]\n    - [ scale, 0, 0, 0 ]\n".to_string(), 4, "transformation is not invertible"),
+    ("- add: cube\n  transform: [ [ scale, 1e-200, 1, 1 ], [ scale, 1e-200, 1, 1 ] ]\n".to_string(), 2, "transformation is not invertible"),
+    ("- define: flat\n  value:\n    - [ shear, 1, 0, 1, 0, 0, 0 ]\n- add: plane\n  material:\n    pattern:\n      type: rings\n      colors: [ [1, 1, 1], [0, 0, 0] ]\n      transform: [ flat ]\n".to_string(), 3, "transformation is not invertible"),
+  ];
+  for (input, line, message) in cameras.iter() {
+    let e = scene_error(input);
+    assert_eq!((e.line, e.message.as_str()), (*line, *message), "{}", input);
+  }
+  for (input, line, message) in cases.iter() {
+    let e = scene_error(input);
+    assert_eq!((e.line, e.message.as_str()), (*line, *message), "{}", input);
+  }
+  assert_eq!(
+    scene_error("- add: sphere\n  material:\n    pattern:\n      type: dots\n      colors: [ [1, 1, 1], [0, 0, 0] ]\n").to_string(),
+    "line 4: unknown pattern type 'dots'"
+  );
+}
@@ -1,128 +1,188 @@
 use crate::raytracer::Intersection;
-use std::rc::Rc;
+use crate::util::LockCell;
+use std::sync::Arc;
 
-use super::PointLight;
+use super::bvh::Bvh;
+use super::{Light, PointLight};
 use super::{Material, Ray, Sphere};
 use crate::color::Color;
-use crate::math::Matrix;
+use crate::math::Matrix4;
 use crate::math::Tuple;
 use crate::raytracer::geometry::Shape;
 use crate::util;
 pub struct World {
-  pub shapes: Vec<Rc<dyn Shape>>,
-  pub lights: Vec<PointLight>,
+  pub shapes: Vec<Arc<dyn Shape>>,
+  pub lights: Vec<Arc<dyn Light>>,
+  // Built from the shapes on first use, and rebuilt when shapes are added or
+  // removed, or a transform or group has changed since
+  bvh: LockCell<Option<Bvh>>,
 }
 
 impl World {
   pub fn new() -> World {
-    let shapes: Vec<Rc<dyn Shape>> = Vec::new();
-    let lights: Vec<PointLight> = Vec::new();
-    World { shapes, lights }
+    let shapes: Vec<Arc<dyn Shape>> = Vec::new();
+    let lights: Vec<Arc<dyn Light>> = Vec::new();
+    World {
+      shapes,
+      lights,
+      bvh: LockCell::new(None),
+    }
   }
   pub fn default() -> World {
-    let lights = vec![PointLight::new(
+    let lights: Vec<Arc<dyn Light>> = vec![Arc::new(PointLight::new(
       &Tuple::point(-10., 10., -10.),
       &Color::new(1., 1., 1.),
-    )];
+    ))];
 
     let mut s1 = Sphere::new();
     let mut mat = Material::new();
     mat.color = Color::new(0.8, 1.0, 0.6);
     mat.diffuse = 0.7;
     mat.specular = 0.2;
-    s1.material = Rc::new(mat);
+    s1.set_material(mat);
     let mut s2 = Sphere::new();
-    s2.transform = Rc::new(Matrix::scale(0.5, 0.5, 0.5));
+    s2.set_transform(Matrix4::scale(0.5, 0.5, 0.5));
     World {
-      shapes: vec![Rc::new(s1), Rc::new(s2)],
+      shapes: vec![Arc::new(s1), Arc::new(s2)],
       lights,
+      bvh: LockCell::new(None),
     }
   }
   pub fn default_world_with_ambient_materials(ambience: f64) -> World {
-    let lights = vec![PointLight::new(
+    let lights: Vec<Arc<dyn Light>> = vec![Arc::new(PointLight::new(
       &Tuple::point(-10., 10., -10.),
       &Color::new(1., 1., 1.),
-    )];
+    ))];
     let mut s1 = Sphere::new();
     let mut mat = Material::new();
     mat.color = Color::new(0.8, 1.0, 0.8);
     mat.diffuse = 0.7;
     mat.specular = 0.2;
     mat.ambient = ambience;
-    s1.material = Rc::new(mat);
+    s1.set_material(mat);
     let mut s2 = Sphere::new();
-    s2.transform = Rc::new(Matrix::scale(0.5, 0.5, 0.5));
+    s2.set_transform(Matrix4::scale(0.5, 0.5, 0.5));
     mat = Material::new();
     mat.ambient = ambience;
-    s2.material = Rc::new(mat);
+    s2.set_material(mat);
     World {
-      shapes: vec![Rc::new(s1), Rc::new(s2)],
+      shapes: vec![Arc::new(s1), Arc::new(s2)],
       lights,
+      bvh: LockCell::new(None),
     }
   }
   pub fn shade_hit(&self, comps: &super::rays::PreComputation, remaining: u8) -> Color {
-    let is_shadow = self.is_shadowed(&comps.over_point);
-    let surface_color = Material::lighting(
-      &comps.shape.get_material(),
-      comps.shape.clone(),
-      &self.lights[0],
-      &comps.over_point,
-      &comps.eye_vector,
-      &comps.normal_vector,
-      is_shadow,
-    );
-    let reflected_color = self.reflected_color(&comps, remaining - 1);
-    return surface_color + &reflected_color;
+    let material = comps.shape.get_material();
+    // Without any light sources only the ambient term contributes
+    let mut surface_color = if self.lights.is_empty() {
+      Material::ambient_lighting(&material, comps.shape.clone(), &comps.over_point)
+    } else {
+      Color::new(0., 0., 0.)
+    };
+    // Each light source contributes independently, attenuated by how much of it is visible
+    for light in &self.lights {
+      let light_intensity = self.light_intensity_at(light.as_ref(), &comps.over_point);
+      surface_color = surface_color
+        + &Material::lighting(
+          &material,
+          comps.shape.clone(),
+          light.as_ref(),
+          &comps.over_point,
+          &comps.eye_vector,
+          &comps.normal_vector,
+          light_intensity,
+        );
+    }
+    // Without bounces left only the surface itself is seen
+    let remaining = match remaining.checked_sub(1) {
+      Some(remaining) => remaining,
+      None => return surface_color,
+    };
+    let reflected_color = self.reflected_color(comps, remaining);
+    let refracted_color = self.refracted_color(comps, remaining);
+
+    if material.reflective > 0.0 && material.transparency > 0.0 {
+      // Surfaces that both reflect and refract blend the two by the Fresnel effect
+      let reflectance = comps.schlick();
+      return surface_color
+        + &(reflected_color * reflectance)
+        + &(refracted_color * (1.0 - reflectance));
+    }
+    return surface_color + &reflected_color + &refracted_color;
   }
   pub fn intersect_world(&self, ray: &Ray) -> Vec<Intersection> {
-    // Traverse all shapes, find intersections for all shapes
-    // return a vector of intersections sorted on low t
-    let mut vec: Vec<Intersection> = Vec::new();
-    for shape in &self.shapes {
-      let mut intersections = shape.intersect(ray);
-      vec.append(&mut intersections);
+    // Find the intersections with every shape whose bounding box the ray
+    // passes through, sorted on low t
+    self.build_bvh();
+    self.bvh.read().as_ref().unwrap().intersect(ray)
+  }
+  // Builds the hierarchy over the shapes unless it is already up to date.
+  // intersect_world does this on demand, but calling it before rendering on
+  // several threads saves them from racing to build it.
+  pub fn build_bvh(&self) {
+    let up_to_date = |bvh: &Option<Bvh>| match bvh {
+      Some(bvh) => bvh.is_built_from(&self.shapes),
+      None => false,
+    };
+    if up_to_date(&self.bvh.read()) {
+      return;
+    }
+    let mut bvh = self.bvh.write();
+    if !up_to_date(&bvh) {
+      *bvh = Some(Bvh::new(&self.shapes));
     }
-    vec.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
-    vec
   }
   pub fn color_at(&self, ray: &Ray, remaining: u8) -> Color {
     let xs = self.intersect_world(ray);
-    if xs.len() < 1 {
+    if xs.is_empty() {
       return Color::new(0., 0., 0.);
     }
     // use the intersection nearest to camera and find color at this point
     let maybe_t = xs.iter().find(|&i| i.t > 0.);
     if let Some(i) = maybe_t {
-      let comps = Ray::precompute(&i, ray);
+      let comps = Ray::precompute_with_intersections(i, ray, &xs);
       return self.shade_hit(&comps, remaining);
     } else {
       return Color::new(0.0, 0.0, 0.0);
     }
   }
 
-  // Determine if a point in 3D space is in shadow:
-  // TODO: only considers the first light source for now.
-  pub fn is_shadowed(&self, point: &Tuple) -> bool {
-    let light_pos = &self.lights[0].position;
-    let point_to_light = light_pos - point;
-    let distance = point_to_light.magnitude();
-    let point_to_light_normalized = point_to_light.normalize();
+  // The fraction of the light's samples that are visible from the point,
+  // 0.0 for a point in full shadow and 1.0 for a fully lit point.
+  pub fn light_intensity_at(&self, light: &dyn Light, point: &Tuple) -> f64 {
+    let samples = light.samples(point);
+    let visible = samples
+      .iter()
+      .filter(|sample| !self.is_blocked(point, &sample.direction, sample.distance))
+      .count();
+    visible as f64 / samples.len() as f64
+  }
+
+  // Determine if a point in 3D space is in shadow with respect to a position on a light:
+  pub fn is_shadowed(&self, point: &Tuple, light_position: &Tuple) -> bool {
+    let point_to_light = light_position - point;
+    self.is_blocked(point, &point_to_light.normalize(), point_to_light.magnitude())
+  }
 
+  // Determine if anything lies between the point and a light the given
+  // distance away in the given (normalized) direction.
+  pub fn is_blocked(
+    &self,
+    point: &Tuple,
+    point_to_light_normalized: &Tuple,
+    distance: f64,
+  ) -> bool {
     // Determine if a point is in shadow by casting a ray *from* the point
     // *to* the light-source. A point will be in shadow if the ray intersects
     // at least one object for t E[0, distance>
-    let point_to_light_ray = Ray::new(&point, &point_to_light_normalized);
+    let point_to_light_ray = Ray::new(point, point_to_light_normalized);
     let mut intersections = self.intersect_world(&point_to_light_ray);
     let h = Intersection::hit(&mut intersections);
     let t = match h {
       Some(i) => i.t,
       None => -1.,
     };
-    if t > 0. && t < distance {
-      return true;
-    } else {
-      return false;
-    }
+    t > 0. && t < distance
   }
 
   pub fn reflected_color(
@@ -141,4 +201,33 @@ impl World {
 
     return color * precomputation.shape.get_material().reflective;
   }
+
+  pub fn refracted_color(
+    &self,
+    precomputation: &super::rays::PreComputation,
+    remaining: u8,
+  ) -> Color {
+    if remaining < 1 {
+      return Color::new(0.0, 0.0, 0.0);
+    }
+    let transparency = precomputation.shape.get_material().transparency;
+    if util::equal(transparency, 0.0) {
+      return Color::new(0.0, 0.0, 0.0);
+    }
+    // Snell's law: sin(theta_t) / sin(theta_i) = n1 / n2
+    let n_ratio = precomputation.n1 / precomputation.n2;
+    let cos_i = Tuple::dot(&precomputation.eye_vector, &precomputation.normal_vector);
+    let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+      // total internal reflection, no light is refracted
+      return Color::new(0.0, 0.0, 0.0);
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let direction = &precomputation.normal_vector * (n_ratio * cos_i - cos_t)
+      - &(&precomputation.eye_vector * n_ratio);
+    let refract_ray = Ray::new(&precomputation.under_point, &direction);
+    let color = self.color_at(&refract_ray, remaining);
+
+    return color * transparency;
+  }
 }
@@ -0,0 +1,372 @@
+// A reader for the subset of YAML that scene files use: block sequences and
+// mappings laid out by indentation, flow sequences such as [ 1, 2, 3 ],
+// scalars, and comments. Every value remembers the line it started on so that
+// errors can point at it.
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Scalar(String),
+  List(Vec<Node>),
+  Map(Vec<(String, Node)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+  pub line: usize,
+  pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct YamlError {
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for YamlError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+fn error<T>(line: usize, message: String) -> Result<T, YamlError> {
+  Err(YamlError { line, message })
+}
+
+impl Node {
+  pub fn as_str(&self) -> Result<&str, YamlError> {
+    match &self.value {
+      Value::Scalar(s) => Ok(s),
+      _ => error(self.line, "expected a single value".to_string()),
+    }
+  }
+  pub fn as_f64(&self) -> Result<f64, YamlError> {
+    let text = self.as_str()?;
+    match text.parse::<f64>() {
+      Ok(n) if n.is_finite() => Ok(n),
+      _ => error(self.line, format!("expected a number, found '{}'", text)),
+    }
+  }
+  pub fn as_usize(&self) -> Result<usize, YamlError> {
+    let text = self.as_str()?;
+    text.parse().or_else(|_| {
+      error(
+        self.line,
+        format!("expected a whole number, found '{}'", text),
+      )
+    })
+  }
+  pub fn as_bool(&self) -> Result<bool, YamlError> {
+    match self.as_str()? {
+      "true" => Ok(true),
+      "false" => Ok(false),
+      other => error(
+        self.line,
+        format!("expected true or false, found '{}'", other),
+      ),
+    }
+  }
+  pub fn as_list(&self) -> Result<&[Node], YamlError> {
+    match &self.value {
+      Value::List(items) => Ok(items),
+      _ => error(self.line, "expected a list".to_string()),
+    }
+  }
+  pub fn as_map(&self) -> Result<&[(String, Node)], YamlError> {
+    match &self.value {
+      Value::Map(entries) => Ok(entries),
+      _ => error(self.line, "expected a set of key: value pairs".to_string()),
+    }
+  }
+  // A list of exactly `count` numbers
+  pub fn as_numbers(&self, count: usize) -> Result<Vec<f64>, YamlError> {
+    let items = self.as_list()?;
+    if items.len() != count {
+      return error(
+        self.line,
+        format!("expected {} numbers, found {}", count, items.len()),
+      );
+    }
+    items.iter().map(|item| item.as_f64()).collect()
+  }
+  pub fn get(&self, key: &str) -> Option<&Node> {
+    match &self.value {
+      Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+}
+
+struct Line {
+  number: usize,
+  indent: usize,
+  text: String,
+}
+
+// Parses a whole document, which is empty or a single block
+pub fn parse(input: &str) -> Result<Option<Node>, YamlError> {
+  let mut lines = Vec::new();
+  for (i, raw) in input.lines().enumerate() {
+    let number = i + 1;
+    let without_comment = strip_comment(raw);
+    let text = without_comment.trim_end();
+    if text.trim_start().is_empty() || text == "---" {
+      continue;
+    }
+    if text.starts_with('\t') || text.trim_start_matches(' ').starts_with('\t') {
+      return error(number, "tabs cannot be used for indentation".to_string());
+    }
+    let indent = text.len() - text.trim_start().len();
+    lines.push(Line {
+      number,
+      indent,
+      text: text.trim_start().to_string(),
+    });
+  }
+  if lines.is_empty() {
+    return Ok(None);
+  }
+  let mut parser = Parser { lines, pos: 0 };
+  let indent = parser.lines[0].indent;
+  let node = parser.block(indent)?;
+  if let Some(line) = parser.lines.get(parser.pos) {
+    return error(line.number, "unexpected indentation".to_string());
+  }
+  Ok(Some(node))
+}
+
+// A '#' starts a comment when it begins the line or follows a space, and is
+// not inside quotes
+fn strip_comment(line: &str) -> &str {
+  let mut quote = None;
+  let mut previous = ' ';
+  for (i, c) in line.char_indices() {
+    match quote {
+      Some(q) if c == q => quote = None,
+      Some(_) => (),
+      None if c == '"' || c == '\'' => quote = Some(c),
+      None if c == '#' && previous.is_whitespace() => return &line[..i],
+      None => (),
+    }
+    previous = c;
+  }
+  line
+}
+
+struct Parser {
+  lines: Vec<Line>,
+  pos: usize,
+}
+
+impl Parser {
+  // The block of lines starting at the current one, which is indented by
+  // `indent`
+  fn block(&mut self, indent: usize) -> Result<Node, YamlError> {
+    let line = &self.lines[self.pos];
+    if is_list_item(&line.text) {
+      self.list(indent)
+    } else if split_key(&line.text).is_some() {
+      self.map(indent)
+    } else {
+      let node = inline_value(&line.text, line.number)?;
+      self.pos += 1;
+      Ok(node)
+    }
+  }
+
+  fn list(&mut self, indent: usize) -> Result<Node, YamlError> {
+    let start = self.lines[self.pos].number;
+    let mut items = Vec::new();
+    while let Some(line) = self.lines.get(self.pos) {
+      if line.indent != indent || !is_list_item(&line.text) {
+        break;
+      }
+      let rest = line.text[1..].trim_start();
+      if rest.is_empty() {
+        // The item is the block on the following lines
+        let number = line.number;
+        self.pos += 1;
+        match self.lines.get(self.pos) {
+          Some(next) if next.indent > indent => {
+            let next_indent = next.indent;
+            items.push(self.block(next_indent)?);
+          }
+          _ => return error(number, "list item has no value".to_string()),
+        }
+      } else {
+        // The rest of the line starts a block indented to where it begins,
+        // so that keys on the following lines line up with it
+        let item_indent = indent + (line.text.len() - rest.len());
+        let rest = rest.to_string();
+        self.lines[self.pos].indent = item_indent;
+        self.lines[self.pos].text = rest;
+        items.push(self.block(item_indent)?);
+      }
+    }
+    Ok(Node {
+      line: start,
+      value: Value::List(items),
+    })
+  }
+
+  fn map(&mut self, indent: usize) -> Result<Node, YamlError> {
+    let start = self.lines[self.pos].number;
+    let mut entries: Vec<(String, Node)> = Vec::new();
+    while let Some(line) = self.lines.get(self.pos) {
+      if line.indent != indent || is_list_item(&line.text) {
+        break;
+      }
+      let number = line.number;
+      let (key, rest) = match split_key(&line.text) {
+        Some((key, rest)) => (key.to_string(), rest.to_string()),
+        None => {
+          return error(
+            number,
+            format!("expected 'key: value', found '{}'", line.text),
+          )
+        }
+      };
+      if entries.iter().any(|(k, _)| *k == key) {
+        return error(number, format!("'{}' is given twice", key));
+      }
+      self.pos += 1;
+      let value = if rest.is_empty() {
+        // A nested block, more indented or a list at the same indentation.
+        // It is reported at the key's line, where it is introduced.
+        match self.lines.get(self.pos) {
+          Some(next)
+            if next.indent > indent || (next.indent == indent && is_list_item(&next.text)) =>
+          {
+            let next_indent = next.indent;
+            let mut node = self.block(next_indent)?;
+            node.line = number;
+            node
+          }
+          _ => return error(number, format!("'{}' has no value", key)),
+        }
+      } else {
+        inline_value(&rest, number)?
+      };
+      entries.push((key, value));
+    }
+    Ok(Node {
+      line: start,
+      value: Value::Map(entries),
+    })
+  }
+}
+
+fn is_list_item(text: &str) -> bool {
+  text == "-" || text.starts_with("- ")
+}
+
+// Splits "key: value" or "key:" at the colon, unless the line is a flow
+// sequence or a quoted scalar
+fn split_key(text: &str) -> Option<(&str, &str)> {
+  if text.starts_with('[') || text.starts_with('"') || text.starts_with('\'') {
+    return None;
+  }
+  let colon = text.find(": ").or_else(|| {
+    if text.ends_with(':') {
+      Some(text.len() - 1)
+    } else {
+      None
+    }
+  })?;
+  let key = text[..colon].trim();
+  if key.is_empty() {
+    return None;
+  }
+  Some((key, text[colon + 1..].trim()))
+}
+
+// A value written on one line: a flow sequence or a scalar
+fn inline_value(text: &str, line: usize) -> Result<Node, YamlError> {
+  let chars: Vec<char> = text.chars().collect();
+  let mut pos = 0;
+  let node = flow_value(&chars, &mut pos, line, false)?;
+  skip_spaces(&chars, &mut pos);
+  if pos < chars.len() {
+    let rest: String = chars[pos..].iter().collect();
+    return error(line, format!("unexpected '{}'", rest));
+  }
+  Ok(node)
+}
+
+fn skip_spaces(chars: &[char], pos: &mut usize) {
+  while *pos < chars.len() && chars[*pos].is_whitespace() {
+    *pos += 1;
+  }
+}
+
+fn flow_value(
+  chars: &[char],
+  pos: &mut usize,
+  line: usize,
+  in_flow: bool,
+) -> Result<Node, YamlError> {
+  skip_spaces(chars, pos);
+  match chars.get(*pos) {
+    Some('[') => {
+      *pos += 1;
+      let mut items = Vec::new();
+      loop {
+        skip_spaces(chars, pos);
+        match chars.get(*pos) {
+          Some(']') => {
+            *pos += 1;
+            break;
+          }
+          None => return error(line, "missing ']'".to_string()),
+          _ => (),
+        }
+        items.push(flow_value(chars, pos, line, true)?);
+        skip_spaces(chars, pos);
+        match chars.get(*pos) {
+          Some(',') => *pos += 1,
+          Some(']') => (),
+          None => return error(line, "missing ']'".to_string()),
+          Some(c) => return error(line, format!("expected ',' or ']', found '{}'", c)),
+        }
+      }
+      Ok(Node {
+        line,
+        value: Value::List(items),
+      })
+    }
+    Some('{') => error(line, "{ } mappings are not supported".to_string()),
+    Some(&quote) if quote == '"' || quote == '\'' => {
+      let start = *pos + 1;
+      let end = chars[start..]
+        .iter()
+        .position(|c| *c == quote)
+        .map(|i| start + i)
+        .ok_or(YamlError {
+          line,
+          message: "unterminated string".to_string(),
+        })?;
+      *pos = end + 1;
+      Ok(Node {
+        line,
+        value: Value::Scalar(chars[start..end].iter().collect()),
+      })
+    }
+    _ => {
+      // A plain scalar runs up to the next separator inside [ ], and to the
+      // end of the line anywhere else, so 'file: a,b.obj' stays one value
+      let start = *pos;
+      while *pos < chars.len() && !(in_flow && matches!(chars[*pos], ',' | '[' | ']')) {
+        *pos += 1;
+      }
+      let text: String = chars[start..*pos].iter().collect();
+      let text = text.trim();
+      if text.is_empty() {
+        return error(line, "missing value".to_string());
+      }
+      Ok(Node {
+        line,
+        value: Value::Scalar(text.to_string()),
+      })
+    }
+  }
+}
@@ -1,9 +1,76 @@
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
 pub const EPSILON: f64 = 0.0001;
 pub fn equal(a: f64, b: f64) -> bool {
-  
-  if f64::abs(a - b) < EPSILON {
-    return true;
-  } else {
-    return false;
+  f64::abs(a - b) < EPSILON
+}
+
+const SPLITMIX64_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// One step of splitmix64: advances the state by the golden ratio increment and
+// scrambles the result
+fn splitmix64(state: u64) -> u64 {
+  let mut z = state.wrapping_add(SPLITMIX64_GAMMA);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
+
+// Deterministically maps a set of numbers to a pseudo-random value in [0, 1)
+pub fn hash_to_unit(values: &[f64]) -> f64 {
+  let mut hash = SPLITMIX64_GAMMA;
+  for value in values {
+    hash = splitmix64(hash ^ value.to_bits());
   }
-}
\ No newline at end of file
+  (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// A cloneable RwLock, giving shapes that are shared between render threads the
+// same interior mutability RefCell would on a single thread
+#[derive(Debug, Default)]
+pub struct LockCell<T>(RwLock<T>);
+
+impl<T> LockCell<T> {
+  pub fn new(value: T) -> LockCell<T> {
+    LockCell(RwLock::new(value))
+  }
+  // A poisoned lock only means another thread panicked while holding it; the
+  // value itself is always left in a usable state
+  pub fn read(&self) -> RwLockReadGuard<'_, T> {
+    self.0.read().unwrap_or_else(|e| e.into_inner())
+  }
+  pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+    self.0.write().unwrap_or_else(|e| e.into_inner())
+  }
+  pub fn replace(&self, value: T) -> T {
+    std::mem::replace(&mut *self.write(), value)
+  }
+}
+
+impl<T: Clone> Clone for LockCell<T> {
+  fn clone(&self) -> LockCell<T> {
+    LockCell::new(self.read().clone())
+  }
+}
+
+// A small seeded pseudo-random number generator (splitmix64). Not suitable
+// for anything but sampling, but fast and reproducible across runs.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    Rng { state: seed }
+  }
+  pub fn next_u64(&mut self) -> u64 {
+    let value = splitmix64(self.state);
+    self.state = self.state.wrapping_add(SPLITMIX64_GAMMA);
+    value
+  }
+  // A value in [0, 1)
+  pub fn next_f64(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+}